@@ -1,3 +1,5 @@
+use std::collections::BTreeMap;
+
 use derive_new::new;
 use prophet_model::{DatabaseType, Entity, Field};
 use serde::{Deserialize, Serialize};
@@ -39,13 +41,22 @@ pub(crate) struct BoundedContextField {
 }
 
 impl BoundedContextSystem {
+    /// Groups the entities into a module per service, each named by its qualified name
+    /// within the service, with any entity of an unknown service in a module of its own
     pub fn new(system_name: String, entities: &[Entity]) -> BoundedContextSystem {
+        let mut modules: BTreeMap<String, Vec<BoundedContextEntity>> = BTreeMap::new();
+        for entity in entities.iter().cloned() {
+            let module = entity
+                .service
+                .clone()
+                .unwrap_or_else(|| entity.qualified_name());
+            modules.entry(module).or_default().push(entity.into());
+        }
         BoundedContextSystem {
             system_name,
-            modules: entities
-                .iter()
-                .cloned()
-                .map(|entity| BoundedContextModule::new(entity.name.clone(), vec![entity.into()]))
+            modules: modules
+                .into_iter()
+                .map(|(name, entities)| BoundedContextModule::new(name, entities))
                 .collect(),
         }
     }
@@ -54,7 +65,7 @@ impl BoundedContextSystem {
 impl From<Entity> for BoundedContextEntity {
     fn from(entity: Entity) -> Self {
         BoundedContextEntity {
-            entity_name: entity.name,
+            entity_name: entity.qualified_name(),
             fields: entity
                 .fields
                 .into_iter()
@@ -92,7 +103,6 @@ pub(crate) struct MergedEntity {
 #[derive(Deserialize)]
 #[serde(rename_all(deserialize = "camelCase"))]
 pub(crate) struct MergedName {
-    #[allow(unused)]
     name: String,
     full_name: String,
 }
@@ -107,29 +117,195 @@ pub(crate) struct MergedField {
     collection: bool,
 }
 
-impl From<MergedEntitySystem> for Vec<Entity> {
-    fn from(mes: MergedEntitySystem) -> Self {
-        mes.bounded_context_entities
+impl MergedEntitySystem {
+    /// Converts the merged entities back into entities, each identified as the entity
+    /// it was merged from among those sent
+    pub fn into_entities(self, sources: &[Entity]) -> Vec<Entity> {
+        self.bounded_context_entities
             .into_iter()
-            .map(|entity| entity.into())
+            .map(|entity| entity.into_entity(sources))
             .collect()
     }
 }
-impl From<MergedEntity> for Entity {
-    fn from(me: MergedEntity) -> Self {
-        Entity {
-            name: me.entity_name.full_name,
-            fields: me.fields.into_iter().map(|field| field.into()).collect(),
-            ty: DatabaseType::Unknown(String::new()),
+
+impl MergedEntity {
+    /// Finds the entity sent that the merged entity is named for, by its qualified name,
+    /// with or without its service, or else by its simple name
+    fn source<'a>(&self, sources: &'a [Entity]) -> Option<&'a Entity> {
+        let full_name = &self.entity_name.full_name;
+        sources
+            .iter()
+            .find(|entity| {
+                let qualified = entity.qualified_name();
+                let in_service = |sep| {
+                    entity
+                        .service
+                        .as_ref()
+                        .map(|service| format!("{}{}{}", service, sep, qualified))
+                };
+                *full_name == qualified
+                    || Some(full_name) == in_service('.').as_ref()
+                    || Some(full_name) == in_service('/').as_ref()
+            })
+            .or_else(|| {
+                sources
+                    .iter()
+                    .find(|entity| entity.name == self.entity_name.name)
+            })
+    }
+
+    /// Converts the merged entity back into an entity, reattaching what the service does
+    /// not merge from the entities it was merged from: the entity it extends, and whether
+    /// each field is optional, how it aggregates what it references, and what maps it
+    fn into_entity(self, sources: &[Entity]) -> Entity {
+        let source = self.source(sources);
+        let merged: Vec<&Entity> =
+            source
+                .into_iter()
+                .chain(sources.iter().filter(|entity| {
+                    entity.name == self.entity_name.name && Some(*entity) != source
+                }))
+                .collect();
+        let fields = self
+            .fields
+            .into_iter()
+            .map(|field| {
+                let original =
+                    merged
+                        .iter()
+                        .flat_map(|entity| entity.fields.iter())
+                        .find(|original| {
+                            original.name == field.name.name
+                                || original.name == field.name.full_name
+                        });
+                Field {
+                    is_optional: original.map_or(false, |original| original.is_optional),
+                    aggregation: original
+                        .map(|original| original.aggregation)
+                        .unwrap_or_default(),
+                    mapped_by: original.and_then(|original| original.mapped_by.clone()),
+                    ..field.into()
+                }
+            })
+            .collect();
+        let ty = DatabaseType::Unknown(String::new());
+        match source {
+            Some(source) => Entity {
+                service: source.service.clone(),
+                namespace: source.namespace.clone(),
+                imports: source.imports.clone(),
+                extends: merged.iter().find_map(|entity| entity.extends.clone()),
+                ..Entity::new(&source.name, fields, ty)
+            },
+            None => Entity::new(self.entity_name.full_name, fields, ty),
         }
     }
 }
 impl From<MergedField> for Field {
     fn from(mf: MergedField) -> Self {
-        Field {
-            name: mf.name.full_name,
-            ty: mf.r#type,
-            is_collection: mf.collection,
-        }
+        Field::new(mf.name.full_name, mf.r#type, mf.collection)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prophet_model::{Aggregation, EntityId};
+
+    fn sources() -> Vec<Entity> {
+        vec![
+            Entity::new("User", vec![], DatabaseType::MySQL)
+                .with_service("users")
+                .with_namespace("com.shop"),
+            Entity::new("User", vec![], DatabaseType::MySQL)
+                .with_service("orders")
+                .with_namespace("com.shop"),
+            Entity::new("Order", vec![], DatabaseType::MySQL).with_service("orders"),
+        ]
+    }
+
+    #[test]
+    fn sends_services_as_modules() {
+        let system = BoundedContextSystem::new("system".into(), &sources());
+        let modules: Vec<(&str, Vec<&str>)> = system
+            .modules
+            .iter()
+            .map(|module| {
+                let entities = module.entities.iter().map(|e| e.entity_name.as_str());
+                (module.name.as_str(), entities.collect())
+            })
+            .collect();
+        assert_eq!(
+            vec![
+                ("orders", vec!["com.shop.User", "Order"]),
+                ("users", vec!["com.shop.User"])
+            ],
+            modules
+        );
+    }
+
+    #[test]
+    fn maps_merged_entities_to_their_ids() {
+        let merged: MergedEntitySystem = serde_json::from_str(
+            r#"{
+                "systemName": "system",
+                "boundedContextEntities": [
+                    {"entityName": {"name": "User", "fullName": "orders.com.shop.User"}, "fields": []},
+                    {"entityName": {"name": "Order", "fullName": "Order"}, "fields": []}
+                ]
+            }"#,
+        )
+        .unwrap();
+        let ids: Vec<EntityId> = merged
+            .into_entities(&sources())
+            .iter()
+            .map(Entity::id)
+            .collect();
+        let expected: Vec<EntityId> = sources().iter().skip(1).map(Entity::id).collect();
+        assert_eq!(expected, ids);
+    }
+
+    #[test]
+    fn reattaches_what_is_not_merged() {
+        let sources = vec![Entity {
+            extends: Some("Base".into()),
+            ..Entity::new(
+                "Order",
+                vec![Field {
+                    is_optional: true,
+                    aggregation: Aggregation::Composite,
+                    mapped_by: Some("order".into()),
+                    ..Field::new("items", "Item", true)
+                }],
+                DatabaseType::MySQL,
+            )
+            .with_service("orders")
+        }];
+        let merged: MergedEntitySystem = serde_json::from_str(
+            r#"{
+                "systemName": "system",
+                "boundedContextEntities": [{
+                    "entityName": {"name": "Order", "fullName": "Order"},
+                    "fields": [{
+                        "name": {"name": "items", "fullName": "items"},
+                        "type": "Item",
+                        "reference": true,
+                        "collection": true
+                    }]
+                }]
+            }"#,
+        )
+        .unwrap();
+        let entities = merged.into_entities(&sources);
+        assert_eq!(
+            sources,
+            entities
+                .into_iter()
+                .map(|entity| Entity {
+                    ty: DatabaseType::MySQL,
+                    ..entity
+                })
+                .collect::<Vec<_>>()
+        );
     }
 }
@@ -39,7 +39,7 @@ pub async fn get_bounded_context(entities: &[Entity]) -> Result<EntityGraph, Err
         BoundedContextSystem::new("dummy".to_string(), entities),
         false,
     );
-    let entities: Vec<Entity> = retrieve(req).await?.into();
+    let entities = retrieve(req).await?.into_entities(entities);
     match EntityGraph::try_new(&entities) {
         Some(graph) => Ok(graph),
         None => Err(Error::Conversion),
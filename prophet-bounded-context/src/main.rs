@@ -3,24 +3,16 @@ use prophet_model::{DatabaseType, Entity, EntityGraph, Field};
 /// TODO replace with a proper integration test
 #[actix_web::main]
 async fn main() {
-    let entity_a = Entity {
-        name: "Entity1".to_string(),
-        fields: vec![Field {
-            name: "FieldA".to_string(),
-            ty: "Foo".to_string(),
-            is_collection: false,
-        }],
-        ty: DatabaseType::MongoDB,
-    };
-    let entity_b = Entity {
-        name: "AnotherEntity".to_string(),
-        fields: vec![Field {
-            name: "AnotherField".to_string(),
-            ty: "Waa".to_string(),
-            is_collection: true,
-        }],
-        ty: DatabaseType::MySQL,
-    };
+    let entity_a = Entity::new(
+        "Entity1",
+        vec![Field::new("FieldA", "Foo", false)],
+        DatabaseType::MongoDB,
+    );
+    let entity_b = Entity::new(
+        "AnotherEntity",
+        vec![Field::new("AnotherField", "Waa", true)],
+        DatabaseType::MySQL,
+    );
 
     let oracle = match EntityGraph::try_new(&[
         Entity {
@@ -1,6 +1,6 @@
 use prophet_model::{
-    Cardinality, Edge, Edges, Entity, EntityGraph, Microservice, MicroserviceCall,
-    MicroserviceGraph,
+    CallKind, Cardinality, Edge, Edges, Entity, EntityGraph, MicroserviceCall, MicroserviceGraph,
+    ModelDiff, Relationship, RelationshipKind, ServiceNode,
 };
 use serde::Serialize;
 use std::fmt::Write;
@@ -69,6 +69,7 @@ impl From<MicroserviceGraph> for MermaidString {
             label: Option<&str>,
         ) -> std::fmt::Result {
             // Write the call edge with any extra information if available
+            let (from, to) = (node(from), node(to));
             match label {
                 Some(label) => writeln!(w, "{} -->|\"{}\"| {}", to, label, from),
                 None => writeln!(w, "{} --> {}", to, from),
@@ -77,33 +78,65 @@ impl From<MicroserviceGraph> for MermaidString {
 
         fn write_ms_edge(
             w: &mut impl Write,
-            edge: &Edge<Microservice, MicroserviceCall>,
+            edge: &Edge<ServiceNode, MicroserviceCall>,
         ) -> std::fmt::Result {
-            // Note: it looks like the only information we have defined for the calls at the moment
-            // is just the HTTP method or RPC call indicator. As seen in the comment above, there
-            // was previously extra information like arguments and specific endpoints.
-            let label = match &edge.weight {
-                call_ty @ MicroserviceCall::Http(_) => {
-                    format!("HTTP Verb: {}", call_ty)
-                }
-                call_ty @ MicroserviceCall::Rpc => format!("{}", call_ty),
+            let call = &edge.weight;
+            let mut label = match &call.kind {
+                CallKind::Http(method) => format!("HTTP Verb: {}", method),
+                kind @ CallKind::Rpc => format!("{}", kind),
             };
+            match (&call.endpoint, &call.path) {
+                (Some(endpoint), _) => {
+                    write!(label, "<br/>Endpoint: {}", endpoint.path)?;
+                    if !endpoint.parameters.is_empty() {
+                        let args = endpoint
+                            .parameters
+                            .iter()
+                            .map(|param| format!("{} {}", param.ty, param.name))
+                            .collect::<Vec<_>>();
+                        write!(label, "<br/>Arguments: {}", args.join(", "))?;
+                    }
+                    let handler = match (&endpoint.handler_class, &endpoint.handler_method) {
+                        (Some(class), Some(method)) => Some(format!("{}.{}", class, method)),
+                        (Some(name), None) | (None, Some(name)) => Some(name.clone()),
+                        (None, None) => None,
+                    };
+                    if let Some(handler) = handler {
+                        write!(label, "<br/>Endpoint function: {}", handler)?;
+                    }
+                    if let Some(return_type) = &endpoint.return_type {
+                        write!(label, "<br/>Returns: {}", return_type)?;
+                    }
+                }
+                (None, Some(path)) => write!(label, "<br/>Path: {}", path)?,
+                (None, None) => {}
+            }
             write_edge(
                 w,
-                edge.from.name.as_str(),
-                edge.to.name.as_str(),
-                Some(label.as_str()),
+                edge.from.name(),
+                edge.to.name(),
+                Some(escape_label(&label).as_str()),
             )
         }
 
-        fn write_ms_orphan(w: &mut impl Write, node: &Microservice) -> std::fmt::Result {
-            write_edge(w, node.name.as_str(), "N/A", None)
+        fn write_ms_orphan(w: &mut impl Write, node: &ServiceNode) -> std::fmt::Result {
+            write_edge(w, node.name(), "N/A", None)
+        }
+
+        // Draw external services, which are outside the analyzed repositories, dashed
+        let mut header = "graph TD".to_string();
+        let externals = graph.external_services();
+        if !externals.is_empty() {
+            header.push_str("\nclassDef external stroke-dasharray: 5 5");
+            for external in externals {
+                write!(header, "\n{}:::external", node(&external.name)).unwrap();
+            }
         }
 
         MermaidString::from_graph(
             graph.nodes(),
             graph.edges(),
-            "graph TD",
+            &header,
             None,
             write_ms_edge,
             Some(write_ms_orphan),
@@ -111,6 +144,102 @@ impl From<MicroserviceGraph> for MermaidString {
     }
 }
 
+/*
+graph TD
+classDef added stroke:#2a2,stroke-width:3px
+classDef removed stroke:#c22,stroke-dasharray: 5 5
+AddedMicroservice:::added
+TargetMicroservice -->|"GET /unchanged"| SourceMicroservice
+TargetMicroservice ==>|"GET /added"| SourceMicroservice
+TargetMicroservice -.->|"GET /removed"| SourceMicroservice
+linkStyle 1 stroke:#2a2,stroke-width:3px
+...
+ */
+impl From<&ModelDiff> for MermaidString {
+    fn from(diff: &ModelDiff) -> Self {
+        let mut mermaid = String::from(
+            "graph TD\n\
+             classDef added stroke:#2a2,stroke-width:3px\n\
+             classDef removed stroke:#c22,stroke-dasharray: 5 5\n",
+        );
+        for service in diff.added_services.iter() {
+            writeln!(mermaid, "{}:::added", node(service)).unwrap();
+        }
+        for service in diff.removed_services.iter() {
+            writeln!(mermaid, "{}:::removed", node(service)).unwrap();
+        }
+
+        // Draw added calls thick and highlighted, and removed calls dashed, keeping the
+        // caller and callee in the same places as the communication diagram
+        let calls = diff
+            .unchanged_calls
+            .iter()
+            .map(|call| (call, "-->"))
+            .chain(diff.added_calls.iter().map(|call| (call, "==>")))
+            .chain(diff.removed_calls.iter().map(|call| (call, "-.->")));
+        let mut added_links = vec![];
+        for (ndx, (call, arrow)) in calls.enumerate() {
+            if arrow == "==>" {
+                added_links.push(ndx.to_string());
+            }
+            writeln!(
+                mermaid,
+                "{} {}|\"{}\"| {}",
+                node(&call.to),
+                arrow,
+                escape_label(&call.call),
+                node(&call.from)
+            )
+            .unwrap();
+        }
+        if !added_links.is_empty() {
+            writeln!(
+                mermaid,
+                "linkStyle {} stroke:#2a2,stroke-width:3px",
+                added_links.join(",")
+            )
+            .unwrap();
+        }
+
+        Self(mermaid)
+    }
+}
+
+/// Refers to a flowchart node by a service's name, giving it an id of its own and the
+/// name as a quoted label unless the name is a plain identifier
+///
+/// Plain identifiers have no underscores, so ids made for other names, which always do,
+/// never collide with them.
+fn node(name: &str) -> String {
+    let plain =
+        !name.is_empty() && name != "end" && name.chars().all(|c| c.is_ascii_alphanumeric());
+    if plain {
+        return name.to_string();
+    }
+    let mut id = String::from("_");
+    for c in name.chars() {
+        if c.is_ascii_alphanumeric() {
+            id.push(c);
+        } else {
+            write!(id, "_{:x}_", c as u32).unwrap();
+        }
+    }
+    format!("{}[\"{}\"]", id, escape_label(name))
+}
+
+/// Escapes the characters a quoted Mermaid label cannot hold, keeping `<br/>` breaks
+fn escape_label(label: &str) -> String {
+    label
+        .split("<br/>")
+        .map(|line| {
+            line.replace('"', "#quot;")
+                .replace('<', "#lt;")
+                .replace('>', "#gt;")
+        })
+        .collect::<Vec<_>>()
+        .join("<br/>")
+}
+
 /*
 classDiagram
 class A {
@@ -118,13 +247,21 @@ class A {
 +Type name
 ...
 }
-A "1" --> "*" B
+A --> "*" B
+A "1" -- "0..1" B
+A "1" *-- "*" B
+A o-- "*" B
+A <|-- B
 ...
  */
 impl From<EntityGraph> for MermaidString {
     fn from(graph: EntityGraph) -> Self {
-        fn write_entity_string(w: &mut impl Write, entity: &Entity) -> std::fmt::Result {
-            writeln!(w, "class {} {{", entity.name)?;
+        fn write_entity_string(
+            w: &mut impl Write,
+            entity: &Entity,
+            shared_names: &[String],
+        ) -> std::fmt::Result {
+            writeln!(w, "class {} {{", class_name(entity, shared_names))?;
             // Write the database type
             writeln!(w, "<<{}>>", entity.ty)?;
             // Write the fields
@@ -141,28 +278,73 @@ impl From<EntityGraph> for MermaidString {
 
         fn write_entity_edge(
             w: &mut impl Write,
-            edge: &Edge<Entity, Cardinality>,
+            edge: &Edge<Entity, Relationship>,
+            shared_names: &[String],
         ) -> std::fmt::Result {
-            // Write the relation represented by the edge
-            let cardinality = edge.weight.to_string();
+            let (from, to) = (
+                class_name(&edge.from, shared_names),
+                class_name(&edge.to, shared_names),
+            );
+            let relationship = &edge.weight;
+
+            // `Parent <|-- Child` draws the child extending the parent
+            let link = match relationship.kind {
+                RelationshipKind::Inheritance => return writeln!(w, "{} <|-- {}", to, from),
+                RelationshipKind::Association if relationship.is_bidirectional() => "--",
+                RelationshipKind::Association => "-->",
+                RelationshipKind::Aggregation => "o--",
+                RelationshipKind::Composition => "*--",
+            };
+            let cardinality = |cardinality: Option<Cardinality>| match cardinality {
+                Some(cardinality) => format!(" \"{}\"", cardinality.to_string()),
+                None => String::new(),
+            };
             writeln!(
                 w,
-                r#"{} "1" --> "{}" {}"#,
-                edge.from.name, cardinality, edge.to.name
+                "{}{} {}{} {}",
+                from,
+                cardinality(relationship.source),
+                link,
+                cardinality(relationship.target),
+                to
             )
         }
 
+        // Find the names more than one entity has, whose classes need qualifying
+        let nodes = graph.nodes();
+        let mut shared_names: Vec<_> = nodes
+            .iter()
+            .filter(|entity| nodes.iter().filter(|e| e.name == entity.name).count() > 1)
+            .map(|entity| entity.name.clone())
+            .collect();
+        shared_names.sort();
+        shared_names.dedup();
+
         MermaidString::from_graph(
-            graph.nodes(),
+            nodes,
             graph.edges(),
             "classDiagram",
-            Some(write_entity_string),
-            write_entity_edge,
+            Some(|w: &mut String, entity: &Entity| write_entity_string(w, entity, &shared_names)),
+            |w, edge| write_entity_edge(w, edge, &shared_names),
             None,
         )
     }
 }
 
+/// Names an entity's class by its name, or by its whole [`EntityId`](prophet_model::EntityId)
+/// if other entities have the same name
+fn class_name(entity: &Entity, shared_names: &[String]) -> String {
+    if !shared_names.contains(&entity.name) {
+        return entity.name.clone();
+    }
+    entity
+        .id()
+        .to_string()
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -179,8 +361,7 @@ class EntityTwo {
 +int x
 +EntityOne other
 }
-EntityOne "1" --> "*" EntityTwo
-EntityTwo "1" --> "1" EntityOne
+EntityOne "1" -- "*" EntityTwo
 "#;
 
     fn get_entity_graph() -> EntityGraph {
@@ -202,8 +383,147 @@ EntityTwo "1" --> "1" EntityOne
         .unwrap()
     }
 
+    const SHARED_NAME_MERMAID: &str = r#"classDiagram
+class orders_Order {
+<<MySQL>>
+}
+class shipping_Order {
+<<MySQL>>
+}
+class Shipment {
+<<MySQL>>
++Order order
+}
+Shipment --> "1" shipping_Order
+"#;
+
+    fn get_shared_name_graph() -> EntityGraph {
+        EntityGraph::try_new(&[
+            Entity::new("Order", vec![], DatabaseType::MySQL).with_service("orders"),
+            Entity::new("Order", vec![], DatabaseType::MySQL).with_service("shipping"),
+            Entity::new(
+                "Shipment",
+                vec![Field::new("order", "Order", false)],
+                DatabaseType::MySQL,
+            )
+            .with_service("shipping"),
+        ])
+        .unwrap()
+    }
+
+    const RELATIONSHIP_MERMAID: &str = r#"classDiagram
+class Order {
+<<MySQL>>
++List<Item> items
++Customer customer
++List<Tag> tags
+}
+class Item {
+<<MySQL>>
+}
+class Customer {
+<<MySQL>>
++List<Item> wishlist
+}
+class Tag {
+<<MySQL>>
++List<Order> orders
+}
+class VipCustomer {
+<<MySQL>>
+}
+Order "1" *-- "*" Item
+Order --> "0..1" Customer
+Order "*" -- "*" Tag
+Customer o-- "*" Item
+Customer <|-- VipCustomer
+"#;
+
+    fn get_relationship_graph() -> EntityGraph {
+        let entity = |name: &str, fields| Entity::new(name, fields, DatabaseType::MySQL);
+        EntityGraph::try_new(&[
+            entity(
+                "Order",
+                vec![
+                    Field {
+                        aggregation: Aggregation::Composite,
+                        ..Field::new("items", "Item", true)
+                    },
+                    Field {
+                        is_optional: true,
+                        ..Field::new("customer", "Customer", false)
+                    },
+                    Field::new("tags", "Tag", true),
+                ],
+            ),
+            entity("Item", vec![]),
+            entity(
+                "Customer",
+                vec![Field {
+                    aggregation: Aggregation::Shared,
+                    ..Field::new("wishlist", "Item", true)
+                }],
+            ),
+            entity("Tag", vec![Field::new("orders", "Order", true)]),
+            Entity {
+                extends: Some("Customer".into()),
+                ..entity("VipCustomer", vec![])
+            },
+        ])
+        .unwrap()
+    }
+
     #[test_case(get_entity_graph() => MermaidString(ENTITY_MERMAID.to_string()) ; "one_to_many")]
+    #[test_case(get_relationship_graph() => MermaidString(RELATIONSHIP_MERMAID.to_string()) ; "relationships")]
+    #[test_case(get_shared_name_graph() => MermaidString(SHARED_NAME_MERMAID.to_string()) ; "shared_name")]
     fn from_entity_graph_test(graph: impl Into<MermaidString>) -> MermaidString {
         graph.into()
     }
+
+    #[test]
+    fn quotes_names_that_are_not_plain_ids() {
+        assert_eq!("users", node("users"));
+        assert_eq!(
+            r#"_ts_2d_order_2d_service["ts-order-service"]"#,
+            node("ts-order-service")
+        );
+        assert_eq!(r#"_end["end"]"#, node("end"));
+        assert_eq!(r#"_a_22_b_3e_["a#quot;b#gt;"]"#, node("a\"b>"));
+    }
+
+    const DIFF_MERMAID: &str = r#"graph TD
+classDef added stroke:#2a2,stroke-width:3px
+classDef removed stroke:#c22,stroke-dasharray: 5 5
+audit:::added
+_legacy_2d_billing["legacy-billing"]:::removed
+users -->|"RPC"| orders
+audit ==>|"POST /api/v1/audit"| users
+payments -.->|"RPC"| orders
+_legacy_2d_billing["legacy-billing"] -.->|"RPC"| orders
+linkStyle 1 stroke:#2a2,stroke-width:3px
+"#;
+
+    #[test]
+    fn from_model_diff_test() {
+        let call = |from: &str, to: &str, call: &str| CallRef {
+            from: from.into(),
+            to: to.into(),
+            call: call.into(),
+        };
+        let diff = ModelDiff {
+            added_services: vec!["audit".into()],
+            removed_services: vec!["legacy-billing".into()],
+            unchanged_calls: vec![call("orders", "users", "RPC")],
+            added_calls: vec![call("users", "audit", "POST /api/v1/audit")],
+            removed_calls: vec![
+                call("orders", "payments", "RPC"),
+                call("orders", "legacy-billing", "RPC"),
+            ],
+            ..Default::default()
+        };
+        assert_eq!(
+            MermaidString(DIFF_MERMAID.to_string()),
+            MermaidString::from(&diff)
+        );
+    }
 }
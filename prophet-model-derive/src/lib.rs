@@ -0,0 +1,306 @@
+//! A derive macro for reading prophet-model types from ReSSA objects
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{
+    parse_macro_input, spanned::Spanned, Data, DeriveInput, Error, Fields, Lit, Meta, NestedMeta,
+    Path,
+};
+
+/// Derives `FromRessa`, and `TryFrom<&BTreeMap<String, Value>>`, for a struct with named
+/// fields
+///
+/// Each field is read from the attribute of the same name, as its type's
+/// `FromRessaAttribute` implementation reads it: strings, bools, vectors of `FromRessa`
+/// types, and options of these for attributes that may be absent. Fields take these
+/// `#[ressa(...)]` attributes:
+///
+/// - `rename = "key"` reads the field from a differently named attribute
+/// - `default` uses the field type's default when the attribute is absent
+/// - `from = "Type"` reads the attribute as `Type`, then converts it with `From`
+/// - `with = "path"` reads the field with a function taking the whole `&RessaObject`
+/// - `skip` always uses the field type's default
+///
+/// ```ignore
+/// #[derive(FromRessa)]
+/// pub struct Entity {
+///     pub name: String,
+///     #[ressa(rename = "type", from = "String")]
+///     pub ty: DatabaseType,
+///     pub fields: Vec<Field>,
+/// }
+/// ```
+#[proc_macro_derive(FromRessa, attributes(ressa))]
+pub fn derive_from_ressa(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match expand(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+/// How a field is read
+#[derive(Default)]
+struct FieldOptions {
+    rename: Option<String>,
+    default: bool,
+    from: Option<syn::Type>,
+    with: Option<Path>,
+    skip: bool,
+}
+
+fn expand(input: &DeriveInput) -> Result<TokenStream2, Error> {
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => {
+                return Err(Error::new(
+                    input.span(),
+                    "FromRessa can only be derived for structs with named fields",
+                ))
+            }
+        },
+        _ => {
+            return Err(Error::new(
+                input.span(),
+                "FromRessa can only be derived for structs",
+            ))
+        }
+    };
+
+    let reads = fields
+        .iter()
+        .map(|field| {
+            let ident = field.ident.as_ref().expect("named field");
+            let options = field_options(field)?;
+            let key = options.rename.clone().unwrap_or_else(|| ident.to_string());
+            let ty = &field.ty;
+
+            let read = if options.skip {
+                quote! { ::std::default::Default::default() }
+            } else if let Some(with) = &options.with {
+                quote! { #with(object)? }
+            } else {
+                let read = match &options.from {
+                    Some(from) => quote! {
+                        <#ty as ::std::convert::From<#from>>::from(
+                            <#from as ::prophet_model::FromRessaAttribute>::from_attribute(object, #key)?
+                        )
+                    },
+                    None => quote! {
+                        <#ty as ::prophet_model::FromRessaAttribute>::from_attribute(object, #key)?
+                    },
+                };
+                if options.default {
+                    quote! {
+                        if object.contains(#key) {
+                            #read
+                        } else {
+                            ::std::default::Default::default()
+                        }
+                    }
+                } else {
+                    read
+                }
+            };
+            Ok(quote! { #ident: #read })
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::prophet_model::FromRessa for #name #ty_generics #where_clause {
+            fn from_ressa(
+                object: &::prophet_model::RessaObject,
+            ) -> ::std::result::Result<Self, ::prophet_model::SchemaError> {
+                ::std::result::Result::Ok(#name {
+                    #(#reads,)*
+                })
+            }
+        }
+
+        impl #impl_generics ::std::convert::TryFrom<
+            &::std::collections::BTreeMap<::std::string::String, ::prophet_model::__private::Value>,
+        > for #name #ty_generics #where_clause {
+            type Error = ::prophet_model::__private::RessaError;
+
+            fn try_from(
+                object: &::std::collections::BTreeMap<
+                    ::std::string::String,
+                    ::prophet_model::__private::Value,
+                >,
+            ) -> ::std::result::Result<Self, Self::Error> {
+                ::prophet_model::__private::from_object(object)
+            }
+        }
+    })
+}
+
+/// Parses a field's `#[ressa(...)]` attributes
+fn field_options(field: &syn::Field) -> Result<FieldOptions, Error> {
+    let mut options = FieldOptions::default();
+    for attr in field
+        .attrs
+        .iter()
+        .filter(|attr| attr.path.is_ident("ressa"))
+    {
+        let list = match attr.parse_meta()? {
+            Meta::List(list) => list,
+            meta => return Err(Error::new(meta.span(), "expected #[ressa(...)]")),
+        };
+
+        for nested in list.nested.iter() {
+            match nested {
+                NestedMeta::Meta(Meta::Path(path)) if path.is_ident("default") => {
+                    options.default = true
+                }
+                NestedMeta::Meta(Meta::Path(path)) if path.is_ident("skip") => options.skip = true,
+                NestedMeta::Meta(Meta::NameValue(pair)) => {
+                    let value = match &pair.lit {
+                        Lit::Str(value) => value,
+                        lit => return Err(Error::new(lit.span(), "expected a string")),
+                    };
+                    if pair.path.is_ident("rename") {
+                        options.rename = Some(value.value());
+                    } else if pair.path.is_ident("from") {
+                        options.from = Some(value.parse()?);
+                    } else if pair.path.is_ident("with") {
+                        options.with = Some(value.parse()?);
+                    } else {
+                        return Err(Error::new(pair.path.span(), "unknown ressa attribute"));
+                    }
+                }
+                nested => return Err(Error::new(nested.span(), "unknown ressa attribute")),
+            }
+        }
+    }
+    Ok(options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use syn::parse_quote;
+
+    /// Expands the derive, with whitespace removed so the output is easy to search
+    fn expand_compact(input: DeriveInput) -> Result<String, String> {
+        expand(&input)
+            .map(|tokens| tokens.to_string().replace(' ', ""))
+            .map_err(|err| err.to_string())
+    }
+
+    #[test]
+    fn reads_fields_by_name() {
+        let tokens = expand_compact(parse_quote! {
+            struct Service {
+                name: String,
+            }
+        })
+        .unwrap();
+        assert!(tokens.contains(
+            r#"name:<Stringas::prophet_model::FromRessaAttribute>::from_attribute(object,"name")?"#
+        ));
+    }
+
+    #[test]
+    fn rename_reads_another_attribute() {
+        let tokens = expand_compact(parse_quote! {
+            struct Field {
+                #[ressa(rename = "type")]
+                ty: String,
+            }
+        })
+        .unwrap();
+        assert!(tokens.contains(r#"from_attribute(object,"type")?"#));
+    }
+
+    #[test]
+    fn default_applies_when_absent() {
+        let tokens = expand_compact(parse_quote! {
+            struct Service {
+                #[ressa(default)]
+                calls: Vec<String>,
+            }
+        })
+        .unwrap();
+        assert!(tokens.contains(r#"ifobject.contains("calls")"#));
+        assert!(tokens.contains("else{::std::default::Default::default()}"));
+    }
+
+    #[test]
+    fn from_converts_the_read_type() {
+        let tokens = expand_compact(parse_quote! {
+            struct Entity {
+                #[ressa(from = "String")]
+                ty: DatabaseType,
+            }
+        })
+        .unwrap();
+        assert!(tokens.contains(
+            "<DatabaseTypeas::std::convert::From<String>>::from(<Stringas::prophet_model::FromRessaAttribute>"
+        ));
+    }
+
+    #[test]
+    fn with_reads_the_whole_object() {
+        let tokens = expand_compact(parse_quote! {
+            struct Service {
+                #[ressa(with = "Origin::try_from_tags")]
+                origin: Option<Origin>,
+            }
+        })
+        .unwrap();
+        assert!(tokens.contains("origin:Origin::try_from_tags(object)?"));
+    }
+
+    #[test]
+    fn skip_always_uses_the_default() {
+        let tokens = expand_compact(parse_quote! {
+            struct Entity {
+                #[ressa(skip)]
+                ty: String,
+            }
+        })
+        .unwrap();
+        assert!(tokens.contains("ty:::std::default::Default::default()"));
+        assert!(!tokens.contains("from_attribute"));
+    }
+
+    #[test]
+    fn rejects_misuse() {
+        let unknown = expand_compact(parse_quote! {
+            struct Entity {
+                #[ressa(rename_all = "camelCase")]
+                name: String,
+            }
+        });
+        assert_eq!(Err("unknown ressa attribute".to_string()), unknown);
+
+        let not_a_string = expand_compact(parse_quote! {
+            struct Entity {
+                #[ressa(rename = 1)]
+                name: String,
+            }
+        });
+        assert_eq!(Err("expected a string".to_string()), not_a_string);
+
+        let tuple = expand_compact(parse_quote! {
+            struct Entity(String);
+        });
+        assert_eq!(
+            Err("FromRessa can only be derived for structs with named fields".to_string()),
+            tuple
+        );
+
+        let enumeration = expand_compact(parse_quote! {
+            enum Entity {
+                User,
+            }
+        });
+        assert_eq!(
+            Err("FromRessa can only be derived for structs".to_string()),
+            enumeration
+        );
+    }
+}
@@ -0,0 +1,346 @@
+//! Differences between two analyses of a system, for reviewing architecture drift
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{DatabaseType, Entity, EntityId, Field, ModelDocument, ServiceNode};
+
+/// Everything that changed between two analyses
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ModelDiff {
+    pub added_services: Vec<String>,
+    pub removed_services: Vec<String>,
+    pub added_endpoints: Vec<EndpointRef>,
+    pub removed_endpoints: Vec<EndpointRef>,
+    pub added_calls: Vec<CallRef>,
+    pub removed_calls: Vec<CallRef>,
+    /// The calls in both analyses, for drawing the changes in context
+    pub unchanged_calls: Vec<CallRef>,
+    /// The entities added, by their qualified [`EntityId`](crate::EntityId)
+    pub added_entities: Vec<String>,
+    pub removed_entities: Vec<String>,
+    pub added_fields: Vec<FieldRef>,
+    pub removed_fields: Vec<FieldRef>,
+    /// Fields whose type or cardinality changed
+    pub changed_fields: Vec<FieldChange>,
+    pub changed_database_types: Vec<DatabaseTypeChange>,
+}
+
+/// An endpoint of a service
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct EndpointRef {
+    pub service: String,
+    pub method: String,
+    pub path: String,
+}
+
+/// A call from one service to another, described by its method and path
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct CallRef {
+    pub from: String,
+    pub to: String,
+    /// Such as `GET /api/v1/users`, or `RPC`
+    pub call: String,
+}
+
+/// A field of an entity
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct FieldRef {
+    pub entity: String,
+    pub field: String,
+}
+
+/// A field whose type or cardinality changed
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FieldChange {
+    pub entity: String,
+    pub before: Field,
+    pub after: Field,
+}
+
+/// An entity whose database changed, named by its [`EntityId`] in the service
+/// referencing it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DatabaseTypeChange {
+    pub entity: String,
+    pub before: DatabaseType,
+    pub after: DatabaseType,
+}
+
+impl ModelDiff {
+    /// Finds what changed from one analysis to another
+    pub fn new(before: &ModelDocument, after: &ModelDocument) -> Self {
+        let mut diff = ModelDiff::default();
+
+        let services = |model: &ModelDocument| -> BTreeMap<String, ServiceNode> {
+            model
+                .microservice_graph
+                .nodes()
+                .into_iter()
+                .map(|node| (node.name().to_string(), node))
+                .collect()
+        };
+        let (old_services, new_services) = (services(before), services(after));
+        let (removed, added) = split(old_services.keys().cloned(), new_services.keys().cloned());
+        diff.removed_services = removed;
+        diff.added_services = added;
+
+        let endpoints = |services: &BTreeMap<String, ServiceNode>| {
+            services
+                .values()
+                .filter_map(|node| match node {
+                    ServiceNode::Internal(ms) => Some(ms),
+                    ServiceNode::External(_) => None,
+                })
+                .flat_map(|ms| {
+                    ms.endpoints.iter().map(|endpoint| EndpointRef {
+                        service: ms.name.clone(),
+                        method: endpoint.method.clone(),
+                        path: endpoint.path.clone(),
+                    })
+                })
+                .collect::<Vec<_>>()
+        };
+        let (removed, added) = split(endpoints(&old_services), endpoints(&new_services));
+        diff.removed_endpoints = removed;
+        diff.added_endpoints = added;
+
+        let (old_calls, new_calls) = (calls(before), calls(after));
+        diff.unchanged_calls = old_calls.intersection(&new_calls).cloned().collect();
+        let (removed, added) = split(old_calls, new_calls);
+        diff.removed_calls = removed;
+        diff.added_calls = added;
+
+        let entities = |model: &ModelDocument| -> BTreeMap<String, Entity> {
+            model
+                .entity_graph
+                .nodes()
+                .into_iter()
+                .map(|entity| (entity.id().to_string(), entity))
+                .collect()
+        };
+        let (old_entities, new_entities) = (entities(before), entities(after));
+        let (removed, added) = split(old_entities.keys().cloned(), new_entities.keys().cloned());
+        diff.removed_entities = removed;
+        diff.added_entities = added;
+
+        // The entity graph does not keep the databases, so they are compared as the
+        // services reference them
+        let (old_databases, new_databases) = (databases(before), databases(after));
+        for (name, old) in old_databases.iter() {
+            match new_databases.get(name) {
+                Some(new) if new != old => diff.changed_database_types.push(DatabaseTypeChange {
+                    entity: name.clone(),
+                    before: old.clone(),
+                    after: new.clone(),
+                }),
+                _ => {}
+            }
+        }
+
+        for (name, old) in old_entities.iter() {
+            let new = match new_entities.get(name) {
+                Some(new) => new,
+                None => continue,
+            };
+
+            let fields = |entity: &Entity| -> BTreeMap<String, Field> {
+                entity
+                    .fields
+                    .iter()
+                    .map(|field| (field.name.clone(), field.clone()))
+                    .collect()
+            };
+            let (old_fields, new_fields) = (fields(old), fields(new));
+            let field_ref = |field: String| FieldRef {
+                entity: name.clone(),
+                field,
+            };
+            let (removed, added) = split(old_fields.keys().cloned(), new_fields.keys().cloned());
+            diff.removed_fields
+                .extend(removed.into_iter().map(field_ref));
+            diff.added_fields.extend(added.into_iter().map(field_ref));
+            for (field_name, before) in old_fields.iter() {
+                if let Some(after) = new_fields.get(field_name) {
+                    if after != before {
+                        diff.changed_fields.push(FieldChange {
+                            entity: name.clone(),
+                            before: before.clone(),
+                            after: after.clone(),
+                        });
+                    }
+                }
+            }
+        }
+        diff
+    }
+
+    /// Whether nothing changed
+    pub fn is_empty(&self) -> bool {
+        self.added_services.is_empty()
+            && self.removed_services.is_empty()
+            && self.added_endpoints.is_empty()
+            && self.removed_endpoints.is_empty()
+            && self.added_calls.is_empty()
+            && self.removed_calls.is_empty()
+            && self.added_entities.is_empty()
+            && self.removed_entities.is_empty()
+            && self.added_fields.is_empty()
+            && self.removed_fields.is_empty()
+            && self.changed_fields.is_empty()
+            && self.changed_database_types.is_empty()
+    }
+}
+
+/// Gets the distinct calls in an analysis
+fn calls(model: &ModelDocument) -> BTreeSet<CallRef> {
+    model
+        .microservice_graph
+        .edges()
+        .into_inner()
+        .into_iter()
+        .map(|edge| CallRef {
+            from: edge.from.name().to_string(),
+            to: edge.to.name().to_string(),
+            call: edge.weight.describe(),
+        })
+        .collect()
+}
+
+/// Gets the database of each entity the services reference, by the entity's id within
+/// the service
+fn databases(model: &ModelDocument) -> BTreeMap<String, DatabaseType> {
+    model
+        .microservice_graph
+        .microservices()
+        .into_iter()
+        .flat_map(|ms| {
+            ms.ref_entities
+                .into_iter()
+                .map(|entity| {
+                    let id = EntityId {
+                        service: Some(ms.name.clone()),
+                        ..entity.id()
+                    };
+                    (id.to_string(), entity.ty)
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+/// Gets the distinct items only before and only after, in order
+fn split<T: Ord + Clone>(
+    before: impl IntoIterator<Item = T>,
+    after: impl IntoIterator<Item = T>,
+) -> (Vec<T>, Vec<T>) {
+    let before: BTreeSet<T> = before.into_iter().collect();
+    let after: BTreeSet<T> = after.into_iter().collect();
+    (
+        before.difference(&after).cloned().collect(),
+        after.difference(&before).cloned().collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        CallKind, EntityGraph, ExternalService, Microservice, MicroserviceCall, MicroserviceGraph,
+    };
+    use petgraph::graph::DiGraph;
+
+    /// Models the calls between services, where the `orders` service references the
+    /// entities and the others are external
+    fn model(calls: &[(&str, &str)], entities: &[Entity]) -> ModelDocument {
+        let mut graph = DiGraph::new();
+        let mut nodes = BTreeMap::new();
+        for (from, to) in calls {
+            let mut node = |name: &str| {
+                *nodes.entry(name.to_string()).or_insert_with(|| {
+                    graph.add_node(match name {
+                        "orders" => ServiceNode::Internal(Microservice {
+                            name: name.into(),
+                            language: source_code_parser::Language::Java,
+                            ref_entities: entities.to_vec(),
+                            endpoints: vec![],
+                            origin: None,
+                        }),
+                        _ => ServiceNode::External(ExternalService {
+                            name: name.into(),
+                            host: None,
+                        }),
+                    })
+                })
+            };
+            let (from, to) = (node(from), node(to));
+            graph.add_edge(
+                from,
+                to,
+                MicroserviceCall {
+                    kind: CallKind::Rpc,
+                    path: None,
+                    endpoint: None,
+                },
+            );
+        }
+        ModelDocument::new(
+            MicroserviceGraph(graph),
+            EntityGraph::try_new(entities).unwrap(),
+        )
+    }
+
+    #[test]
+    fn finds_changes() {
+        let before = model(
+            &[("orders", "users"), ("orders", "payments")],
+            &[Entity::new(
+                "Order",
+                vec![
+                    Field::new("items", "Item", false),
+                    Field::new("id", "long", false),
+                ],
+                DatabaseType::MySQL,
+            )],
+        );
+        let after = model(
+            &[("orders", "users"), ("users", "audit")],
+            &[Entity::new(
+                "Order",
+                vec![
+                    Field::new("items", "Item", true),
+                    Field::new("total", "long", false),
+                ],
+                DatabaseType::MongoDB,
+            )],
+        );
+
+        let diff = ModelDiff::new(&before, &after);
+        assert_eq!(vec!["audit"], diff.added_services);
+        assert_eq!(vec!["payments"], diff.removed_services);
+        assert_eq!(
+            vec!["users"],
+            diff.added_calls.iter().map(|c| &c.from).collect::<Vec<_>>()
+        );
+        assert_eq!(1, diff.removed_calls.len());
+        assert_eq!(1, diff.unchanged_calls.len());
+        assert_eq!(
+            vec!["total"],
+            diff.added_fields
+                .iter()
+                .map(|f| &f.field)
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec!["id"],
+            diff.removed_fields
+                .iter()
+                .map(|f| &f.field)
+                .collect::<Vec<_>>()
+        );
+        assert!(diff.changed_fields[0].after.is_collection);
+        assert_eq!("orders/Order", diff.changed_database_types[0].entity);
+        assert_eq!(DatabaseType::MongoDB, diff.changed_database_types[0].after);
+        assert!(ModelDiff::new(&after, &after).is_empty());
+    }
+}
@@ -0,0 +1,35 @@
+//! Builders for the Rune values of ReSSA results and for services, shared by the tests
+//! of this crate and of the crates producing ReSSA results, which enable the `fixtures` feature
+use crate::{DatabaseType, Entity, Microservice, ServiceNode};
+use runestick::{Shared, Value};
+use source_code_parser::Language;
+
+pub fn string(text: &str) -> Value {
+    Value::String(Shared::new(text.to_string()))
+}
+
+pub fn object(attributes: Vec<(&str, Value)>) -> Value {
+    let mut object = runestick::Object::new();
+    for (key, value) in attributes {
+        object.insert(key.to_string(), value);
+    }
+    Value::Object(Shared::new(object))
+}
+
+pub fn vec(items: Vec<Value>) -> Value {
+    Value::Vec(Shared::new(runestick::Vec::from(items)))
+}
+
+/// An internal Java service referencing the named entities
+pub fn service(name: &str, entities: &[&str]) -> ServiceNode {
+    ServiceNode::Internal(Microservice {
+        name: name.into(),
+        language: Language::Java,
+        ref_entities: entities
+            .iter()
+            .map(|entity| Entity::new(entity, vec![], DatabaseType::MySQL))
+            .collect(),
+        endpoints: vec![],
+        origin: None,
+    })
+}
@@ -0,0 +1,360 @@
+//! A versioned JSON format for storing and exchanging the model graphs
+//!
+//! ```text
+//! {
+//!   "version": 2,
+//!   "metadata": { "generator": "prophet-model 0.1.0", ... },
+//!   "microservice_graph": { "nodes": [ServiceNode], "edges": [{ "from", "to", "weight": MicroserviceCall }] },
+//!   "entity_graph": { "nodes": [Entity], "edges": [{ "from", "to", "weight": Relationship }] }
+//! }
+//! ```
+//!
+//! An edge's `from` and `to` are the indices of its nodes in `nodes`.
+//!
+//! Version 1 documents, whose entity edges are a bare `Cardinality` of the entities
+//! referenced, are still read, with each edge becoming an association.
+use std::collections::BTreeMap;
+
+use petgraph::{graph::DiGraph, visit::EdgeRef};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    Cardinality, Entity, EntityGraph, MicroserviceCall, MicroserviceGraph, Relationship,
+    RelationshipKind, ServiceNode,
+};
+
+/// The version of the model format written by [`ModelDocument::to_json`]
+pub const MODEL_FORMAT_VERSION: u32 = 2;
+
+/// An error reading or writing a model document
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum InterchangeError {
+    #[error(
+        "unsupported model format version {0}, expected 1 to {}",
+        MODEL_FORMAT_VERSION
+    )]
+    Version(u32),
+    #[error("invalid model JSON: {0}")]
+    Json(String),
+}
+
+/// The model graphs of an analysis, along with metadata describing it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModelDocument {
+    /// The version of the model format
+    pub version: u32,
+    /// Free-form information about the analysis, such as the tool that produced it
+    #[serde(default)]
+    pub metadata: BTreeMap<String, String>,
+    pub microservice_graph: MicroserviceGraph,
+    pub entity_graph: EntityGraph,
+}
+
+impl ModelDocument {
+    /// Creates a document of the current version for the graphs
+    pub fn new(microservice_graph: MicroserviceGraph, entity_graph: EntityGraph) -> Self {
+        let generator = format!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+        ModelDocument {
+            version: MODEL_FORMAT_VERSION,
+            metadata: BTreeMap::from([("generator".to_string(), generator)]),
+            microservice_graph,
+            entity_graph,
+        }
+    }
+
+    /// Adds an entry to the document's metadata
+    pub fn with_metadata(mut self, key: impl ToString, value: impl ToString) -> Self {
+        self.metadata.insert(key.to_string(), value.to_string());
+        self
+    }
+
+    /// Writes the document as JSON
+    pub fn to_json(&self) -> Result<String, InterchangeError> {
+        serde_json::to_string_pretty(self).map_err(|err| InterchangeError::Json(err.to_string()))
+    }
+
+    /// Reads a document from JSON, checking its version before anything else
+    ///
+    /// A version 1 document is upgraded to the current version.
+    pub fn from_json(json: &str) -> Result<Self, InterchangeError> {
+        let value =
+            serde_json::from_str(json).map_err(|err| InterchangeError::Json(err.to_string()))?;
+        Self::from_value(&value)
+    }
+
+    /// Reads a document from already parsed JSON, such as part of a larger request body
+    pub fn from_value(value: &serde_json::Value) -> Result<Self, InterchangeError> {
+        #[derive(Deserialize)]
+        struct Versioned {
+            version: u32,
+        }
+
+        let json_error = |err: serde_json::Error| InterchangeError::Json(err.to_string());
+        let Versioned { version } = Versioned::deserialize(value).map_err(json_error)?;
+        match version {
+            1 => DocumentV1::deserialize(value)
+                .map_err(json_error)?
+                .upgrade()
+                .map_err(InterchangeError::Json),
+            MODEL_FORMAT_VERSION => ModelDocument::deserialize(value).map_err(json_error),
+            _ => Err(InterchangeError::Version(version)),
+        }
+    }
+}
+
+/// A version 1 document, whose entity edges are the cardinality of the entity referenced
+#[derive(Deserialize)]
+struct DocumentV1 {
+    #[serde(default)]
+    metadata: BTreeMap<String, String>,
+    microservice_graph: MicroserviceGraph,
+    entity_graph: GraphData<Entity, Cardinality>,
+}
+
+impl DocumentV1 {
+    /// Upgrades the document to the current version, making each entity edge an
+    /// association whose fields are unknown
+    fn upgrade(self) -> Result<ModelDocument, String> {
+        let GraphData { nodes, edges } = self.entity_graph;
+        let edges = edges
+            .into_iter()
+            .map(|edge| EdgeData {
+                from: edge.from,
+                to: edge.to,
+                weight: Relationship {
+                    kind: RelationshipKind::Association,
+                    source: None,
+                    target: Some(edge.weight),
+                    source_field: None,
+                    target_field: None,
+                },
+            })
+            .collect();
+        Ok(ModelDocument {
+            version: MODEL_FORMAT_VERSION,
+            metadata: self.metadata,
+            microservice_graph: self.microservice_graph,
+            entity_graph: GraphData { nodes, edges }.try_into()?,
+        })
+    }
+}
+
+/// The serialized form of a graph
+#[derive(Serialize, Deserialize)]
+pub(crate) struct GraphData<N, E> {
+    nodes: Vec<N>,
+    edges: Vec<EdgeData<E>>,
+}
+
+/// The serialized form of an edge, by the indices of its nodes
+#[derive(Serialize, Deserialize)]
+struct EdgeData<E> {
+    from: usize,
+    to: usize,
+    weight: E,
+}
+
+impl<N: Clone, E: Clone> GraphData<N, E> {
+    fn from_graph(graph: &DiGraph<N, E>) -> Self {
+        GraphData {
+            nodes: graph.node_weights().cloned().collect(),
+            edges: graph
+                .edge_references()
+                .map(|edge| EdgeData {
+                    from: edge.source().index(),
+                    to: edge.target().index(),
+                    weight: edge.weight().clone(),
+                })
+                .collect(),
+        }
+    }
+
+    fn into_graph(self) -> Result<DiGraph<N, E>, String> {
+        let mut graph = DiGraph::new();
+        let indices: Vec<_> = self
+            .nodes
+            .into_iter()
+            .map(|node| graph.add_node(node))
+            .collect();
+        for (ndx, edge) in self.edges.into_iter().enumerate() {
+            match (indices.get(edge.from), indices.get(edge.to)) {
+                (Some(from), Some(to)) => {
+                    graph.add_edge(*from, *to, edge.weight);
+                }
+                _ => {
+                    return Err(format!(
+                        "edge {} is between nodes {} and {}, but there are only {} nodes",
+                        ndx,
+                        edge.from,
+                        edge.to,
+                        indices.len()
+                    ))
+                }
+            }
+        }
+        Ok(graph)
+    }
+}
+
+impl From<MicroserviceGraph> for GraphData<ServiceNode, MicroserviceCall> {
+    fn from(graph: MicroserviceGraph) -> Self {
+        GraphData::from_graph(&graph.0)
+    }
+}
+
+impl TryFrom<GraphData<ServiceNode, MicroserviceCall>> for MicroserviceGraph {
+    type Error = String;
+
+    fn try_from(data: GraphData<ServiceNode, MicroserviceCall>) -> Result<Self, Self::Error> {
+        data.into_graph().map(MicroserviceGraph)
+    }
+}
+
+impl From<EntityGraph> for GraphData<Entity, Relationship> {
+    fn from(graph: EntityGraph) -> Self {
+        GraphData::from_graph(&graph.0)
+    }
+}
+
+impl TryFrom<GraphData<Entity, Relationship>> for EntityGraph {
+    type Error = String;
+
+    fn try_from(data: GraphData<Entity, Relationship>) -> Result<Self, Self::Error> {
+        data.into_graph().map(EntityGraph)
+    }
+}
+
+/// Serializes a language as its lowercase name, such as `"java"`, the way ReSSAs name it
+pub(crate) mod language {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use source_code_parser::Language;
+
+    pub fn serialize<S: Serializer>(language: &Language, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format!("{:?}", language).to_lowercase())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Language, D::Error> {
+        Ok(String::deserialize(deserializer)?.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CallKind, DatabaseType, ExternalService, Field, Microservice};
+    use source_code_parser::Language;
+
+    fn entity_graph() -> EntityGraph {
+        EntityGraph::try_new(&[
+            Entity::new(
+                "Order",
+                vec![Field::new("items", "Item", true)],
+                DatabaseType::MySQL,
+            ),
+            Entity::new(
+                "Item",
+                vec![Field::new("sku", "String", false)],
+                DatabaseType::Unknown("Cassandra".into()),
+            ),
+        ])
+        .unwrap()
+    }
+
+    fn microservice_graph() -> MicroserviceGraph {
+        let mut graph = DiGraph::new();
+        let orders = graph.add_node(ServiceNode::Internal(Microservice {
+            name: "orders".into(),
+            language: Language::Java,
+            ref_entities: vec![],
+            endpoints: vec![],
+            origin: None,
+        }));
+        let stripe = graph.add_node(ServiceNode::External(ExternalService {
+            name: "stripe".into(),
+            host: Some("api.stripe.com".into()),
+        }));
+        graph.add_edge(
+            orders,
+            stripe,
+            MicroserviceCall {
+                kind: CallKind::Http(http::Method::POST),
+                path: Some("https://api.stripe.com/v1/charges".into()),
+                endpoint: None,
+            },
+        );
+        MicroserviceGraph(graph)
+    }
+
+    #[test]
+    fn round_trips() {
+        let document =
+            ModelDocument::new(microservice_graph(), entity_graph()).with_metadata("name", "shop");
+        let json = document.to_json().unwrap();
+        let read = ModelDocument::from_json(&json).unwrap();
+
+        assert_eq!(document.metadata, read.metadata);
+        assert_eq!(
+            microservice_graph().nodes(),
+            read.microservice_graph.nodes()
+        );
+        assert_eq!(
+            microservice_graph()
+                .as_ref()
+                .edge_weights()
+                .collect::<Vec<_>>(),
+            read.microservice_graph
+                .as_ref()
+                .edge_weights()
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(entity_graph().nodes(), read.entity_graph.nodes());
+        let edges = |graph: &EntityGraph| {
+            graph
+                .edges()
+                .into_inner()
+                .into_iter()
+                .map(|edge| (edge.from.name, edge.to.name, edge.weight))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(edges(&entity_graph()), edges(&read.entity_graph));
+    }
+
+    #[test]
+    fn reads_version_1() {
+        let document = ModelDocument::new(microservice_graph(), entity_graph());
+        let mut json: serde_json::Value =
+            serde_json::from_str(&document.to_json().unwrap()).unwrap();
+        json["version"] = 1.into();
+        json["entity_graph"]["edges"][0]["weight"] = "Many".into();
+
+        let read = ModelDocument::from_value(&json).unwrap();
+        assert_eq!(MODEL_FORMAT_VERSION, read.version);
+        assert_eq!(
+            vec![Relationship {
+                kind: RelationshipKind::Association,
+                source: None,
+                target: Some(Cardinality::Many),
+                source_field: None,
+                target_field: None,
+            }],
+            read.entity_graph
+                .as_ref()
+                .edge_weights()
+                .cloned()
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn rejects_other_versions() {
+        let document = ModelDocument::new(microservice_graph(), entity_graph());
+        let mut json: serde_json::Value =
+            serde_json::from_str(&document.to_json().unwrap()).unwrap();
+        json["version"] = (MODEL_FORMAT_VERSION + 1).into();
+
+        assert_eq!(
+            Err(InterchangeError::Version(MODEL_FORMAT_VERSION + 1)),
+            ModelDocument::from_json(&json.to_string()).map(|_| ())
+        );
+    }
+}
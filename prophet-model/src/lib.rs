@@ -1,148 +1,480 @@
 //! Types for use across the prophet crates
-use std::{collections::BTreeMap, str::FromStr};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    str::FromStr,
+};
 
 use petgraph::{
     graph::{DiGraph, NodeIndex},
     visit::EdgeRef,
 };
 use runestick::Value;
+use serde::{Deserialize, Serialize};
 use source_code_parser::{ressa, ressa::RessaResult, Language};
 use strum::Display;
 
+mod diff;
+#[cfg(any(test, feature = "fixtures"))]
+pub mod fixtures;
+mod interchange;
+mod metrics;
+mod policy;
+mod query;
+mod relationship;
+mod schema;
+mod smells;
+pub use diff::*;
+pub use interchange::*;
+pub use metrics::*;
+pub use policy::*;
+pub use query::*;
+pub use relationship::*;
+pub use schema::*;
+pub use smells::*;
+
+pub use prophet_model_derive::FromRessa;
+
+// Lets the code `FromRessa` derives refer to this crate by name, even within it
+extern crate self as prophet_model;
+
+#[doc(hidden)]
+pub mod __private {
+    pub use runestick::Value;
+    pub use source_code_parser::ressa::Error as RessaError;
+
+    use std::collections::BTreeMap;
+
+    use crate::{FromRessa, RessaObject};
+
+    /// Reads a type from a bare ReSSA object
+    pub fn from_object<T: FromRessa>(object: &BTreeMap<String, Value>) -> Result<T, RessaError> {
+        T::from_ressa(&RessaObject::new("", object.clone()))
+            .map_err(|err| RessaError::InvalidType(err.to_string()))
+    }
+}
+
 /// A microservice detected from a ReSSA
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, FromRessa)]
 pub struct Microservice {
     pub name: String,
+    #[ressa(from = "String")]
+    #[serde(with = "interchange::language")]
     pub language: Language,
+    #[ressa(rename = "entities", default)]
     pub ref_entities: Vec<Entity>,
+    #[ressa(default)]
+    pub endpoints: Vec<Endpoint>,
+    #[ressa(with = "Origin::try_from_tags")]
+    pub origin: Option<Origin>,
 }
 
-impl TryFrom<&BTreeMap<String, Value>> for Microservice {
-    type Error = ressa::Error;
+/// The attribute added to scoped services and entities naming their repository
+pub const ORIGIN_REPOSITORY: &str = "repository";
+/// The attribute added to scoped services and entities naming their root directory
+pub const ORIGIN_ROOT: &str = "root";
+
+/// The repository root directory a microservice was detected in
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Origin {
+    pub repository: String,
+    pub root: String,
+}
+
+impl Origin {
+    /// Attempts to read the origin tags added to a scoped ReSSA object
+    fn try_from_tags(object: &RessaObject) -> Result<Option<Self>, SchemaError> {
+        let repository = object.opt_string(ORIGIN_REPOSITORY)?;
+        let root = object.opt_string(ORIGIN_ROOT)?;
+        Ok(repository
+            .zip(root)
+            .map(|(repository, root)| Origin { repository, root }))
+    }
+}
+
+/// An endpoint a microservice exposes
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, FromRessa)]
+pub struct Endpoint {
+    /// The HTTP method, or "RPC" for an RPC endpoint
+    pub method: String,
+    /// The path template, such as `/api/v1/orders/{id}`
+    pub path: String,
+    /// The class handling requests to the endpoint
+    pub handler_class: Option<String>,
+    /// The method handling requests to the endpoint
+    pub handler_method: Option<String>,
+    #[ressa(default)]
+    pub parameters: Vec<Parameter>,
+    pub return_type: Option<String>,
+}
+
+impl Endpoint {
+    /// Whether a call of the given kind to the given path reaches the endpoint
+    ///
+    /// Template segments like `{id}` match any segment of the called path, as do
+    /// segments the call only builds at runtime, such as `{}` or `${id}`. Any scheme,
+    /// host or query string on the called path is ignored.
+    pub fn matches(&self, kind: &CallKind, path: &str) -> bool {
+        self.match_score(kind, path).is_some()
+    }
+
+    /// Scores how closely a call matches the endpoint, if it reaches it at all
+    ///
+    /// Segments that are equal, or that are placeholders in both paths, score highest,
+    /// so a call to `/orders/latest` prefers `/orders/latest` over `/orders/{id}`, while
+    /// a call to `/orders/{}` prefers the reverse.
+    fn match_score(&self, kind: &CallKind, path: &str) -> Option<usize> {
+        let method_matches = match kind {
+            CallKind::Http(method) => self.method.eq_ignore_ascii_case(method.as_str()),
+            CallKind::Rpc => self.method.eq_ignore_ascii_case("RPC"),
+        };
+        let template = path_segments(&self.path);
+        let called = path_segments(request_path(path));
+        if !method_matches || template.len() != called.len() {
+            return None;
+        }
 
-    /// Attempts to create a microservice from a ReSSA's object
-    fn try_from(service: &BTreeMap<String, Value>) -> Result<Self, Self::Error> {
-        let name = ressa::extract(service, "name", Value::into_string)?;
-        let language =
-            ressa::extract(service, "language", Value::into_string).map(Language::from)?;
-        let ref_entities = ressa::extract_vec(service, "entities", Value::into_object)?
+        template
             .into_iter()
-            .map(ressa::extract_object)
-            .flat_map(|entity| Entity::try_from(&entity))
-            .collect::<Vec<_>>();
-        Ok(Microservice {
-            name,
-            language,
-            ref_entities,
-        })
+            .zip(called)
+            .map(
+                |(template, called)| match (is_placeholder(template), is_placeholder(called)) {
+                    (true, true) => Some(2),
+                    (false, false) if template == called => Some(2),
+                    (false, false) => None,
+                    (true, false) => Some(1),
+                    (false, true) => Some(0),
+                },
+            )
+            .sum()
+    }
+}
+
+/// A parameter of an endpoint
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, FromRessa)]
+pub struct Parameter {
+    pub name: String,
+    #[ressa(rename = "type")]
+    pub ty: String,
+}
+
+/// Strips any scheme, host and query string from a called URL
+fn request_path(url: &str) -> &str {
+    let path = match url.find("://") {
+        Some(scheme_end) => {
+            let after_scheme = &url[scheme_end + 3..];
+            after_scheme
+                .find('/')
+                .map(|ndx| &after_scheme[ndx..])
+                .unwrap_or("/")
+        }
+        None => url,
+    };
+    path.split(['?', '#']).next().unwrap_or(path)
+}
+
+/// Gets the host, and any port, of a full URL
+fn url_host(url: &str) -> Option<&str> {
+    let after_scheme = &url[url.find("://")? + 3..];
+    let host = after_scheme.split(['/', '?', '#']).next()?;
+    if host.is_empty() {
+        None
+    } else {
+        Some(host)
     }
 }
 
+fn path_segments(path: &str) -> Vec<&str> {
+    path.split('/')
+        .filter(|segment| !segment.is_empty())
+        .collect()
+}
+
+fn is_placeholder(segment: &str) -> bool {
+    segment.contains('{')
+}
+
 /// Represents a call between microservices
-#[derive(Debug, Clone, Display)]
-pub enum MicroserviceCall {
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MicroserviceCall {
+    pub kind: CallKind,
+    /// The path the call requests, if the ReSSA found it
+    pub path: Option<String>,
+    /// The endpoint of the called service the call reaches, if it could be resolved
+    pub endpoint: Option<Endpoint>,
+}
+
+/// How one microservice calls another, written as `"HTTP <method>"` or `"RPC"` in JSON
+#[derive(Debug, Clone, PartialEq, Eq, Display, Serialize, Deserialize)]
+#[serde(into = "String", try_from = "String")]
+pub enum CallKind {
     Http(http::Method),
     #[strum(serialize = "RPC")]
     Rpc,
 }
 
+impl From<CallKind> for String {
+    fn from(kind: CallKind) -> Self {
+        match kind {
+            CallKind::Http(method) => format!("HTTP {}", method),
+            CallKind::Rpc => "RPC".into(),
+        }
+    }
+}
+
+impl TryFrom<String> for CallKind {
+    type Error = String;
+
+    fn try_from(kind: String) -> Result<Self, Self::Error> {
+        match kind.split_once(' ') {
+            Some(("HTTP", method)) => http::Method::from_str(method)
+                .map(CallKind::Http)
+                .map_err(|_| format!("invalid HTTP method {:?}", method)),
+            None if kind == "RPC" => Ok(CallKind::Rpc),
+            _ => Err(format!(
+                "expected \"HTTP <method>\" or \"RPC\", found {:?}",
+                kind
+            )),
+        }
+    }
+}
+
+impl FromRessa for MicroserviceCall {
+    /// Attempts to convert a ReSSA object to a microservice call, without resolving
+    /// its endpoint
+    fn from_ressa(call: &RessaObject) -> Result<Self, SchemaError> {
+        let ty = call.string("type")?;
+        let method = call.opt_string("method")?;
+        let kind = match (&*ty, method) {
+            ("HTTP", Some(method)) => CallKind::Http(
+                http::Method::from_str(&method)
+                    .map_err(|_| call.mismatch("method", "an HTTP method"))?,
+            ),
+            ("HTTP", None) => return Err(call.mismatch("method", "an HTTP method")),
+            ("RPC", None) => CallKind::Rpc,
+            ("RPC", Some(_)) => return Err(call.mismatch("method", "no method for an RPC call")),
+            _ => return Err(call.mismatch("type", "\"HTTP\" or \"RPC\"")),
+        };
+        Ok(MicroserviceCall {
+            kind,
+            path: call.opt_string("path")?,
+            endpoint: None,
+        })
+    }
+}
+
+impl MicroserviceCall {
+    /// Describes the call by its method and the path it reaches, such as
+    /// `GET /api/v1/orders/{id}`, or `RPC`
+    pub fn describe(&self) -> String {
+        let kind = match &self.kind {
+            CallKind::Http(method) => method.to_string(),
+            CallKind::Rpc => "RPC".to_string(),
+        };
+        let path = self
+            .endpoint
+            .as_ref()
+            .map(|endpoint| &endpoint.path)
+            .or(self.path.as_ref());
+        match path {
+            Some(path) => format!("{} {}", kind, path),
+            None => kind,
+        }
+    }
+
+    /// Resolves the call to the called service's endpoint it most closely matches, if
+    /// its path is known
+    fn resolve(&mut self, called: &Microservice) {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return,
+        };
+        self.endpoint = called
+            .endpoints
+            .iter()
+            .filter_map(|endpoint| Some((endpoint.match_score(&self.kind, path)?, endpoint)))
+            .max_by_key(|(score, _)| *score)
+            .map(|(_, endpoint)| endpoint.clone());
+    }
+}
+
 impl TryFrom<&BTreeMap<String, Value>> for MicroserviceCall {
     type Error = ressa::Error;
 
     /// Attempts to convert a ReSSA object to a microservice call
     fn try_from(call: &BTreeMap<String, Value>) -> Result<Self, Self::Error> {
-        let ty = ressa::extract(call, "type", Value::into_string)?;
-        let method = ressa::extract(call, "method", Value::into_string);
-        let call = match method {
-            Ok(method) if ty == "HTTP" => MicroserviceCall::Http(
-                http::Method::from_str(&method)
-                    .map_err(|_| ressa::Error::InvalidType("Bad HTTP method".into()))?,
-            ),
-            Err(_) if ty == "RPC" => MicroserviceCall::Rpc,
-            _ => {
-                return Err(ressa::Error::InvalidType(
-                    "Bad microservice call type".into(),
-                ))
-            }
-        };
-        Ok(call)
+        __private::from_object(call)
+    }
+}
+
+/// A service called by an analyzed microservice that is not one of them, such as a
+/// third-party API or a service outside the analyzed repositories
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ExternalService {
+    /// The name the calls give the service
+    pub name: String,
+    /// The host the calls reach it at, if they give a full URL
+    pub host: Option<String>,
+}
+
+/// A node in a microservice graph
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ServiceNode {
+    /// A microservice detected in the analyzed repositories
+    Internal(Microservice),
+    /// A call target that is not a detected microservice
+    External(ExternalService),
+}
+
+impl ServiceNode {
+    /// The name of the service
+    pub fn name(&self) -> &str {
+        match self {
+            ServiceNode::Internal(ms) => &ms.name,
+            ServiceNode::External(external) => &external.name,
+        }
+    }
+
+    /// Whether the service is external
+    pub fn is_external(&self) -> bool {
+        matches!(self, ServiceNode::External(_))
     }
 }
 
 /// A graph of calls between microservices
-#[derive(Debug, Clone)]
-pub struct MicroserviceGraph(DiGraph<Microservice, MicroserviceCall>);
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(
+    into = "GraphData<ServiceNode, MicroserviceCall>",
+    try_from = "GraphData<ServiceNode, MicroserviceCall>"
+)]
+pub struct MicroserviceGraph(DiGraph<ServiceNode, MicroserviceCall>);
 
 impl MicroserviceGraph {
-    /// Attempts to create a microservice graph from a ReSSA result
-    pub fn try_new(result: &RessaResult) -> Option<MicroserviceGraph> {
-        let ctx = result.get("ctx")?;
-        // Get the services shared vec from the context
-        let services = ressa::extract_vec(ctx, "services", Value::into_object)
-            .ok()?
-            .into_iter()
-            .map(ressa::extract_object)
-            .collect::<Vec<_>>();
+    /// Attempts to create a microservice graph from a ReSSA result, validating the
+    /// result against the [schema](crate::schema)
+    ///
+    /// A call reaches the service of the called name detected in the caller's own
+    /// repository root if there is one, and the first service of that name otherwise.
+    /// Calls to services that are not in the result are kept as calls to external
+    /// services, one node for each name called.
+    pub fn try_new(result: &RessaResult) -> Result<MicroserviceGraph, SchemaErrors> {
+        let ctx = result.get("ctx").cloned().unwrap_or_default();
+        let ctx = RessaObject::new("ctx", ctx);
+
+        // Read each service along with the calls it makes
+        let mut errors = vec![];
+        let mut services = vec![];
+        for service in ctx.objects("services")? {
+            let service = service.named();
+            let read = Microservice::from_ressa(&service).and_then(|mut ms| {
+                for entity in ms.ref_entities.iter_mut() {
+                    entity.service = Some(ms.name.clone());
+                }
+                let calls = service
+                    .opt_objects("calls")?
+                    .into_iter()
+                    .map(|call| Ok((call.string("name")?, MicroserviceCall::from_ressa(&call)?)))
+                    .collect::<Result<Vec<_>, SchemaError>>()?;
+                Ok((ms, calls))
+            });
+            match read {
+                Ok(service) => services.push(service),
+                Err(err) => errors.push(err),
+            }
+        }
+        if !errors.is_empty() {
+            return Err(SchemaErrors(errors));
+        }
 
         // Create the graph with the service nodes
-        let mut graph: DiGraph<Microservice, MicroserviceCall> = DiGraph::new();
-        let indices = add_nodes(&mut graph, &services);
-
-        // Get the calls each of the services makes
-        let services = services.iter().flat_map(|service| {
-            let name = Microservice::try_from(service)?.name;
-            let calls = ressa::extract_vec(service, "calls", Value::into_object)?
-                .into_iter()
-                .map(ressa::result::extract_object)
-                .collect::<Vec<_>>();
-            Ok::<_, ressa::Error>((name, calls))
-        });
-
-        // Add directed edges between services in the graph
-        for (service_name, calls) in services {
-            let service_ndx = indices
+        let mut graph: DiGraph<ServiceNode, MicroserviceCall> = DiGraph::new();
+        let indices = add_nodes_inner(
+            &mut graph,
+            services
                 .iter()
-                .find(|ndx| graph[**ndx].name == service_name)?;
+                .map(|(ms, _)| ServiceNode::Internal(ms.clone())),
+        );
 
-            for call in calls.iter() {
-                let called_name = ressa::extract(call, "name", Value::into_string).ok()?;
-                let called_service_ndx = indices
+        // Add directed edges between services in the graph, adding a node for each
+        // external service the first time it is called, with the host of the first of
+        // its calls to give a full URL
+        for (service_ndx, (ms, calls)) in indices.iter().zip(services) {
+            for (called_name, mut call) in calls {
+                let named: Vec<_> = graph
+                    .node_indices()
+                    .filter(|ndx| graph[*ndx].name() == called_name)
+                    .collect();
+                let called_ndx = named
                     .iter()
-                    .find(|ndx| graph[**ndx].name == called_name)?;
-                let call = call.try_into().ok()?;
-
-                graph.add_edge(*service_ndx, *called_service_ndx, call);
+                    .find(|ndx| match &graph[**ndx] {
+                        ServiceNode::Internal(called) => {
+                            ms.origin.is_some() && called.origin == ms.origin
+                        }
+                        ServiceNode::External(_) => false,
+                    })
+                    .or_else(|| named.first())
+                    .copied();
+                let called_ndx = match called_ndx {
+                    Some(ndx) => ndx,
+                    None => graph.add_node(ServiceNode::External(ExternalService {
+                        name: called_name,
+                        host: None,
+                    })),
+                };
+                match &mut graph[called_ndx] {
+                    ServiceNode::Internal(called) => call.resolve(called),
+                    ServiceNode::External(external) => {
+                        if external.host.is_none() {
+                            external.host =
+                                call.path.as_deref().and_then(url_host).map(String::from);
+                        }
+                    }
+                }
+                graph.add_edge(*service_ndx, called_ndx, call);
             }
         }
 
-        Some(MicroserviceGraph(graph))
+        Ok(MicroserviceGraph(graph))
     }
 
     /// Gets the directed edges for the microservice graph
-    pub fn edges(&self) -> Edges<Microservice, MicroserviceCall> {
+    pub fn edges(&self) -> Edges<ServiceNode, MicroserviceCall> {
         Edges::from(&self.0)
     }
 
-    // Gets all of the nodes in the graph
-    pub fn nodes(&self) -> Vec<Microservice> {
+    /// Gets all of the nodes in the graph, internal and external
+    pub fn nodes(&self) -> Vec<ServiceNode> {
         get_nodes(&self.0)
     }
+
+    /// Gets the microservices detected in the analyzed repositories
+    pub fn microservices(&self) -> Vec<Microservice> {
+        self.0
+            .node_weights()
+            .filter_map(|node| match node {
+                ServiceNode::Internal(ms) => Some(ms.clone()),
+                ServiceNode::External(_) => None,
+            })
+            .collect()
+    }
+
+    /// Gets the external services the microservices call
+    pub fn external_services(&self) -> Vec<ExternalService> {
+        self.0
+            .node_weights()
+            .filter_map(|node| match node {
+                ServiceNode::External(external) => Some(external.clone()),
+                ServiceNode::Internal(_) => None,
+            })
+            .collect()
+    }
 }
 
 fn get_nodes<N: Clone, E>(graph: &DiGraph<N, E>) -> Vec<N> {
     graph.node_indices().map(|ndx| graph[ndx].clone()).collect()
 }
 
-fn add_nodes<'a, N, E>(
-    graph: &mut DiGraph<N, E>,
-    services: &'a [BTreeMap<String, Value>],
-) -> Vec<NodeIndex>
-where
-    N: TryFrom<&'a BTreeMap<String, Value>>,
-{
-    add_nodes_inner(graph, services.iter().flat_map(N::try_from))
-}
-
 fn add_nodes_inner<N, E>(
     graph: &mut DiGraph<N, E>,
     services: impl Iterator<Item = N>,
@@ -152,18 +484,34 @@ fn add_nodes_inner<N, E>(
         .collect::<Vec<_>>()
 }
 
-impl AsRef<DiGraph<Microservice, MicroserviceCall>> for MicroserviceGraph {
-    fn as_ref(&self) -> &DiGraph<Microservice, MicroserviceCall> {
+impl AsRef<DiGraph<ServiceNode, MicroserviceCall>> for MicroserviceGraph {
+    fn as_ref(&self) -> &DiGraph<ServiceNode, MicroserviceCall> {
         &self.0
     }
 }
 
 /// Represents an entity from the ReSSA
-#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize, FromRessa)]
 pub struct Entity {
     pub name: String,
     pub fields: Vec<Field>,
+    #[ressa(rename = "type", from = "String")]
     pub ty: DatabaseType,
+    /// The microservice the entity is defined in, if known
+    #[ressa(skip)]
+    #[serde(default)]
+    pub service: Option<String>,
+    /// The package or namespace the entity is declared in, if any
+    #[serde(default)]
+    pub namespace: Option<String>,
+    /// What the entity's source file imports, such as `com.shop.Order`, `com.shop.*` or,
+    /// for a whole namespace, `Shop.Orders`
+    #[ressa(default)]
+    #[serde(default)]
+    pub imports: Vec<String>,
+    /// The type of the entity it extends, if any
+    #[serde(default)]
+    pub extends: Option<String>,
 }
 
 impl Entity {
@@ -172,35 +520,118 @@ impl Entity {
             name: name.to_string(),
             fields,
             ty,
+            service: None,
+            namespace: None,
+            imports: vec![],
+            extends: None,
         }
     }
-}
 
-impl TryFrom<&BTreeMap<String, Value>> for Entity {
-    type Error = ressa::Error;
+    /// Places the entity in a microservice
+    pub fn with_service(mut self, service: impl ToString) -> Self {
+        self.service = Some(service.to_string());
+        self
+    }
 
-    /// Attempts to create an Entity from a ReSSA object
-    fn try_from(entity: &BTreeMap<String, Value>) -> Result<Self, Self::Error> {
-        let name = ressa::extract(entity, "name", Value::into_string)?;
-        let ty: DatabaseType = ressa::extract(entity, "type", Value::into_string)?.into();
+    /// Places the entity in a package or namespace
+    pub fn with_namespace(mut self, namespace: impl ToString) -> Self {
+        self.namespace = Some(namespace.to_string());
+        self
+    }
 
-        let fields = ressa::extract_vec(entity, "fields", Value::into_object)?
-            .into_iter()
-            .map(ressa::extract_object)
-            .flat_map(|f| Field::try_from(&f))
-            .collect::<Vec<_>>();
+    /// Gets the entity's qualified identity
+    pub fn id(&self) -> EntityId {
+        EntityId {
+            service: self.service.clone(),
+            namespace: self.namespace.clone(),
+            name: self.name.clone(),
+        }
+    }
 
-        Ok(Entity { name, fields, ty })
+    /// Gets the entity's name within its namespace, such as `com.shop.Order`
+    pub fn qualified_name(&self) -> String {
+        match &self.namespace {
+            Some(namespace) => format!("{}.{}", namespace, self.name),
+            None => self.name.clone(),
+        }
+    }
+
+    /// How strongly the entity's source refers to another entity by its simple name:
+    /// importing it by name, sharing its namespace, importing its whole namespace, or not
+    /// at all
+    fn reference_strength(&self, other: &Entity) -> usize {
+        let namespace = match &other.namespace {
+            Some(namespace) => namespace,
+            None => return 0,
+        };
+        if self.imports.contains(&other.qualified_name()) {
+            3
+        } else if self.namespace.as_ref() == Some(namespace) {
+            2
+        } else if self
+            .imports
+            .iter()
+            .any(|import| import.strip_suffix(".*").unwrap_or(import) == namespace)
+        {
+            1
+        } else {
+            0
+        }
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Hash, Clone, Display)]
+/// Identifies an entity by the microservice and namespace it is defined in, along with
+/// its name, so same-named entities elsewhere stay distinct
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct EntityId {
+    pub service: Option<String>,
+    pub namespace: Option<String>,
+    pub name: String,
+}
+
+impl EntityId {
+    /// Whether the ids may identify the same entity: their names are equal, and so are
+    /// their services and namespaces wherever both are known
+    pub fn matches(&self, other: &EntityId) -> bool {
+        fn agree(a: &Option<String>, b: &Option<String>) -> bool {
+            a.is_none() || b.is_none() || a == b
+        }
+        self.name == other.name
+            && agree(&self.service, &other.service)
+            && agree(&self.namespace, &other.namespace)
+    }
+}
+
+/// Written as `service/namespace.Name`, leaving out the parts that are not known
+impl fmt::Display for EntityId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(service) = &self.service {
+            write!(f, "{}/", service)?;
+        }
+        if let Some(namespace) = &self.namespace {
+            write!(f, "{}.", namespace)?;
+        }
+        write!(f, "{}", self.name)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Display, Serialize, Deserialize)]
+#[serde(into = "String", from = "String")]
 pub enum DatabaseType {
     MySQL,
     MongoDB,
     Unknown(String),
 }
 
+impl From<DatabaseType> for String {
+    fn from(ty: DatabaseType) -> Self {
+        match ty {
+            DatabaseType::Unknown(name) => name,
+            ty => ty.to_string(),
+        }
+    }
+}
+
 impl From<String> for DatabaseType {
     fn from(value: String) -> Self {
         match &*value {
@@ -211,11 +642,24 @@ impl From<String> for DatabaseType {
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize, FromRessa)]
 pub struct Field {
     pub name: String,
+    #[ressa(rename = "type")]
     pub ty: String,
     pub is_collection: bool,
+    /// Whether the field may hold nothing
+    #[ressa(default)]
+    #[serde(default)]
+    pub is_optional: bool,
+    /// How the entity holds the entities the field references
+    #[ressa(with = "Aggregation::from_field")]
+    #[serde(default)]
+    pub aggregation: Aggregation,
+    /// The field of the referenced entity that references this one back, as JPA's
+    /// `mappedBy` names it
+    #[serde(default)]
+    pub mapped_by: Option<String>,
 }
 
 impl Field {
@@ -224,27 +668,16 @@ impl Field {
             name: name.to_string(),
             ty: ty.to_string(),
             is_collection,
+            is_optional: false,
+            aggregation: Aggregation::None,
+            mapped_by: None,
         }
     }
 }
 
-impl TryFrom<&BTreeMap<String, Value>> for Field {
-    type Error = ressa::Error;
-
-    fn try_from(entity: &BTreeMap<String, Value>) -> Result<Self, Self::Error> {
-        let name = ressa::extract(entity, "name", Value::into_string)?;
-        let ty = ressa::extract(entity, "type", Value::into_string)?;
-        let is_collection = ressa::extract_primitive(entity, "is_collection", Value::into_bool)?;
-        Ok(Field {
-            name,
-            ty,
-            is_collection,
-        })
-    }
-}
-
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Cardinality {
+    ZeroOrOne,
     One,
     Many,
 }
@@ -253,6 +686,7 @@ impl ToString for Cardinality {
     fn to_string(&self) -> String {
         use Cardinality::*;
         match self {
+            ZeroOrOne => "0..1",
             One => "1",
             Many => "*",
         }
@@ -260,45 +694,86 @@ impl ToString for Cardinality {
     }
 }
 
-#[derive(Debug, Clone)]
-pub struct EntityGraph(DiGraph<Entity, Cardinality>);
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(
+    into = "GraphData<Entity, Relationship>",
+    try_from = "GraphData<Entity, Relationship>"
+)]
+pub struct EntityGraph(DiGraph<Entity, Relationship>);
 
 impl EntityGraph {
     /// Attempts to create an entity graph from a list of combined Entities
+    ///
+    /// Each distinct [`EntityId`] gets its own node, so same-named entities in different
+    /// microservices or namespaces stay apart, and field types are resolved to them the
+    /// way the entity's source would (see [`EntityGraph::resolve_type`]). Each edge is a
+    /// [`Relationship`], with the fields two entities reference each other by merged
+    /// into one.
     pub fn try_new(entities: &[Entity]) -> Option<EntityGraph> {
         let mut graph = DiGraph::new();
+        let mut ids = BTreeSet::new();
+        let entities: Vec<_> = entities
+            .iter()
+            .filter(|entity| ids.insert(entity.id()))
+            .cloned()
+            .collect();
         let indices = add_nodes_inner(&mut graph, entities.iter().cloned());
 
-        // Add entity nodes to the graph
-        for entity in entities {
-            let entity_ndx = indices
-                .iter()
-                .find(|ndx| graph[**ndx].name == entity.name)?;
+        // Relate the entity nodes in the graph
+        for (from, to, relationship) in relationships(&entities) {
+            graph.add_edge(indices[from], indices[to], relationship);
+        }
 
-            for field in entity.fields.iter() {
-                // Get the matching entity for the field
-                let other_entity_ndx = indices.iter().find(|ndx| graph[**ndx].name == field.ty);
-                let other_entity_ndx = match other_entity_ndx {
-                    Some(ndx) => ndx,
-                    _ => continue,
-                };
+        Some(EntityGraph(graph))
+    }
 
-                let other_cardinality = if field.is_collection {
-                    Cardinality::Many
-                } else {
-                    Cardinality::One
-                };
+    /// Finds the index of the entity a field type of an entity names, if exactly one
+    ///
+    /// A qualified type, such as `com.shop.Order`, names the entity in that namespace.
+    /// A simple type names an entity of the same microservice if there is one, preferring
+    /// those imported by name, then those in the same namespace, then those whose whole
+    /// namespace is imported. A type that is still ambiguous names no entity.
+    pub(crate) fn resolve_type(entities: &[Entity], from: &Entity, ty: &str) -> Option<usize> {
+        let (namespace, name) = match ty.rsplit_once('.') {
+            Some((namespace, name)) => (Some(namespace), name),
+            None => (None, ty),
+        };
+        let candidates: Vec<_> = entities
+            .iter()
+            .enumerate()
+            .filter(|(_, entity)| {
+                entity.name == name
+                    && (namespace.is_none() || entity.namespace.as_deref() == namespace)
+            })
+            .collect();
 
-                graph.add_edge(*entity_ndx, *other_entity_ndx, other_cardinality);
-                //graph.add_edge(*other_entity_ndx, *entity_ndx, Cardinality::One);
-            }
-        }
+        // Only look in other microservices when the entity's own has no match
+        let same_service: Vec<_> = candidates
+            .iter()
+            .filter(|(_, entity)| from.service.is_some() && entity.service == from.service)
+            .cloned()
+            .collect();
+        let candidates = if same_service.is_empty() {
+            candidates
+        } else {
+            same_service
+        };
 
-        Some(EntityGraph(graph))
+        let strongest = candidates
+            .iter()
+            .map(|(_, entity)| from.reference_strength(entity))
+            .max()?;
+        let mut strongest = candidates
+            .into_iter()
+            .filter(|(_, entity)| from.reference_strength(entity) == strongest);
+        match (strongest.next(), strongest.next()) {
+            (Some((ndx, _)), None) => Some(ndx),
+            _ => None,
+        }
     }
 
     /// Gets the directed edges for the entity graph
-    pub fn edges(&self) -> Edges<Entity, Cardinality> {
+    pub fn edges(&self) -> Edges<Entity, Relationship> {
         Edges::from(&self.0)
     }
 
@@ -307,28 +782,31 @@ impl EntityGraph {
         get_nodes(&self.0)
     }
 
-    /// Filters an entity graph to contain certain entities
+    /// Filters an entity graph to contain certain entities, matching them by their
+    /// [`EntityId`]s
     pub fn filter_entities(&mut self, entities: &[Entity]) {
         let graph = &mut self.0;
+        let ids: Vec<_> = entities.iter().map(Entity::id).collect();
 
         // Graph::remove_node invalidates the last node index, so we need to repeatedly find the
         // entities that should be filtered out so we have valid indices that can remove the nodes.
         while let Some(ndx) = graph.node_indices().find_map(|ndx| {
-            if entities.iter().any(|e| *e == graph[ndx]) {
+            let id = graph[ndx].id();
+            if !ids.iter().any(|other| other.matches(&id)) {
                 Some(ndx)
             } else {
                 None
             }
         }) {
-            // We know the node is in the list since we just found its index and the graph has not
-            // been mutated elsewhere before this statement, so the index is valid
+            // The node matches none of the kept ids, and its index is valid since we just found
+            // it and the graph has not been mutated elsewhere before this statement
             graph.remove_node(ndx);
         }
     }
 }
 
-impl AsRef<DiGraph<Entity, Cardinality>> for EntityGraph {
-    fn as_ref(&self) -> &DiGraph<Entity, Cardinality> {
+impl AsRef<DiGraph<Entity, Relationship>> for EntityGraph {
+    fn as_ref(&self) -> &DiGraph<Entity, Relationship> {
         &self.0
     }
 }
@@ -372,3 +850,229 @@ where
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{object, string, vec};
+
+    #[test]
+    fn resolves_calls_to_endpoints() {
+        let endpoint = |method: &str, path: &str, handler: &str| {
+            object(vec![
+                ("method", string(method)),
+                ("path", string(path)),
+                ("handler_method", string(handler)),
+            ])
+        };
+        let orders = object(vec![
+            ("name", string("orders")),
+            ("language", string("java")),
+            (
+                "endpoints",
+                vec(vec![
+                    endpoint("GET", "/api/v1/orders/{id}", "getOrder"),
+                    endpoint("GET", "/api/v1/orders/latest", "getLatest"),
+                ]),
+            ),
+        ]);
+        let call = |path: &str| {
+            object(vec![
+                ("name", string("orders")),
+                ("type", string("HTTP")),
+                ("method", string("GET")),
+                ("path", string(path)),
+            ])
+        };
+        let users = object(vec![
+            ("name", string("users")),
+            ("language", string("java")),
+            (
+                "calls",
+                vec(vec![
+                    call("http://ts-order-service:12031/api/v1/orders/{}?full=true"),
+                    call("/api/v1/orders/latest"),
+                    call("/api/v1/users"),
+                ]),
+            ),
+        ]);
+        let mut result = RessaResult::default();
+        result.insert(
+            "ctx".to_string(),
+            BTreeMap::from([("services".to_string(), vec(vec![orders, users]))]),
+        );
+
+        let graph = MicroserviceGraph::try_new(&result).unwrap();
+        let handlers = graph
+            .edges()
+            .into_inner()
+            .into_iter()
+            .map(|edge| edge.weight.endpoint.and_then(|e| e.handler_method))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                Some("getOrder".to_string()),
+                Some("getLatest".to_string()),
+                None
+            ],
+            handlers
+        );
+    }
+
+    #[test]
+    fn keeps_unknown_call_targets_as_external() {
+        let call = object(vec![
+            ("name", string("stripe")),
+            ("type", string("HTTP")),
+            ("method", string("POST")),
+            ("path", string("https://api.stripe.com/v1/charges")),
+        ]);
+        // The host is taken from whichever call gives a full URL
+        let relative = object(vec![
+            ("name", string("stripe")),
+            ("type", string("HTTP")),
+            ("method", string("GET")),
+            ("path", string("/v1/balance")),
+        ]);
+        let service = object(vec![
+            ("name", string("payments")),
+            ("language", string("java")),
+            ("calls", vec(vec![relative, call])),
+        ]);
+        let mut result = RessaResult::default();
+        result.insert(
+            "ctx".to_string(),
+            BTreeMap::from([("services".to_string(), vec(vec![service]))]),
+        );
+
+        let graph = MicroserviceGraph::try_new(&result).unwrap();
+        assert_eq!(1, graph.microservices().len());
+        assert_eq!(
+            vec![ExternalService {
+                name: "stripe".into(),
+                host: Some("api.stripe.com".into()),
+            }],
+            graph.external_services()
+        );
+        assert_eq!(2, graph.edges().into_inner().len());
+    }
+
+    #[test]
+    fn resolves_calls_within_the_callers_root_first() {
+        let service = |name: &str, root: &str, calls: Vec<Value>| {
+            object(vec![
+                ("name", string(name)),
+                ("language", string("java")),
+                ("calls", vec(calls)),
+                (ORIGIN_REPOSITORY, string("https://github.com/a/a.git")),
+                (ORIGIN_ROOT, string(root)),
+            ])
+        };
+        let call = object(vec![("name", string("users")), ("type", string("RPC"))]);
+        let mut result = RessaResult::default();
+        result.insert(
+            "ctx".to_string(),
+            BTreeMap::from([(
+                "services".to_string(),
+                vec(vec![
+                    service("users", "first", vec![]),
+                    service("users", "second", vec![]),
+                    service("orders", "second", vec![call]),
+                ]),
+            )]),
+        );
+
+        let graph = MicroserviceGraph::try_new(&result).unwrap();
+        let called = graph
+            .edges()
+            .into_inner()
+            .into_iter()
+            .map(|edge| match edge.to {
+                ServiceNode::Internal(ms) => ms.origin.map(|origin| origin.root),
+                ServiceNode::External(_) => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(vec![Some("second".to_string())], called);
+    }
+
+    #[test]
+    fn keeps_same_named_entities_apart() {
+        let entity = |name: &str, namespace: &str, fields: Vec<Value>, imports: Vec<Value>| {
+            object(vec![
+                ("name", string(name)),
+                ("type", string("MySQL")),
+                ("namespace", string(namespace)),
+                ("fields", vec(fields)),
+                ("imports", vec(imports)),
+            ])
+        };
+        let field = |name: &str, ty: &str| {
+            object(vec![
+                ("name", string(name)),
+                ("type", string(ty)),
+                ("is_collection", Value::Bool(false)),
+            ])
+        };
+        let service = |name: &str, entities: Vec<Value>| {
+            object(vec![
+                ("name", string(name)),
+                ("language", string("java")),
+                ("entities", vec(entities)),
+            ])
+        };
+        let orders = service(
+            "orders",
+            vec![
+                entity("Order", "com.shop.orders", vec![], vec![]),
+                entity(
+                    "Invoice",
+                    "com.shop.billing",
+                    vec![field("order", "Order")],
+                    vec![string("com.shop.orders.*")],
+                ),
+            ],
+        );
+        let shipping = service(
+            "shipping",
+            vec![
+                entity("Order", "com.shop.shipping", vec![], vec![]),
+                entity(
+                    "Shipment",
+                    "com.shop.shipping",
+                    vec![
+                        field("order", "Order"),
+                        field("source", "com.shop.orders.Order"),
+                    ],
+                    vec![],
+                ),
+            ],
+        );
+        let mut result = RessaResult::default();
+        result.insert(
+            "ctx".to_string(),
+            BTreeMap::from([("services".to_string(), vec(vec![orders, shipping]))]),
+        );
+
+        let graph = MicroserviceGraph::try_new(&result).unwrap();
+        let entities: Vec<_> = graph
+            .microservices()
+            .into_iter()
+            .flat_map(|ms| ms.ref_entities)
+            .collect();
+        let entity_graph = EntityGraph::try_new(&entities).unwrap();
+        assert_eq!(4, entity_graph.nodes().len());
+        assert_eq!(
+            vec![
+                ("Invoice", "orders/com.shop.orders.Order".to_string()),
+                ("Shipment", "shipping/com.shop.shipping.Order".to_string()),
+                ("Shipment", "orders/com.shop.orders.Order".to_string()),
+            ],
+            entity_graph
+                .edges()
+                .into_inner()
+                .iter()
+                .map(|edge| (edge.from.name.as_str(), edge.to.id().to_string()))
+                .collect::<Vec<_>>()
+        );
+    }
+}
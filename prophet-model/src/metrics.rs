@@ -0,0 +1,208 @@
+//! Coupling and size metrics of a system's microservices
+use std::collections::{BTreeSet, HashSet};
+
+use petgraph::Direction;
+use serde::{Deserialize, Serialize};
+
+use crate::{Entity, EntityGraph, MicroserviceGraph, ServiceNode};
+
+/// The metrics of a system and each of its microservices
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ArchitectureMetrics {
+    /// One row for each microservice, in the graph's order
+    pub services: Vec<ServiceMetrics>,
+    pub system: SystemMetrics,
+}
+
+/// The metrics of a single microservice
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ServiceMetrics {
+    pub service: String,
+    /// The number of calls other services make to it
+    pub fan_in: usize,
+    /// The number of calls it makes to other services
+    pub fan_out: usize,
+    /// The number of distinct services that call it
+    pub afferent_coupling: usize,
+    /// The number of distinct services it calls
+    pub efferent_coupling: usize,
+    /// Its efferent coupling over its total coupling, from 0 (stable) to 1 (unstable)
+    pub instability: f64,
+    /// The number of endpoints it exposes
+    pub endpoints: usize,
+    /// The number of entities it references
+    pub entities: usize,
+    /// The number of its entities that other services also reference
+    pub shared_entities: usize,
+    /// The number of other services referencing any of its entities
+    pub entity_overlap: usize,
+}
+
+/// The metrics of the whole system
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SystemMetrics {
+    pub services: usize,
+    pub external_services: usize,
+    pub calls: usize,
+    /// The share of the possible caller and callee pairs that have a call, counting
+    /// external services
+    pub density: f64,
+    /// The number of groups of services that call each other in a cycle
+    pub cycles: usize,
+    /// The average number of calls between two services where one reaches the other
+    pub average_path_length: f64,
+    /// The number of entities in the system's entity graph
+    pub entities: usize,
+    /// The number of relationships between the entities
+    pub entity_relationships: usize,
+}
+
+impl ArchitectureMetrics {
+    /// Measures a system from its microservice graph and its (bounded) entity graph
+    pub fn new(ms_graph: &MicroserviceGraph, entity_graph: &EntityGraph) -> Self {
+        let graph = ms_graph.as_ref();
+
+        // The qualified names of the entities each microservice references
+        let entity_names: Vec<(String, BTreeSet<String>)> = ms_graph
+            .microservices()
+            .into_iter()
+            .map(|ms| {
+                let names = ms.ref_entities.iter().map(Entity::qualified_name).collect();
+                (ms.name, names)
+            })
+            .collect();
+
+        let services = graph
+            .node_indices()
+            .filter_map(|ndx| match &graph[ndx] {
+                ServiceNode::Internal(ms) => Some((ndx, ms)),
+                ServiceNode::External(_) => None,
+            })
+            .map(|(ndx, ms)| {
+                let distinct = |direction| {
+                    graph
+                        .neighbors_directed(ndx, direction)
+                        .filter(|other| *other != ndx)
+                        .collect::<HashSet<_>>()
+                        .len()
+                };
+                let afferent_coupling = distinct(Direction::Incoming);
+                let efferent_coupling = distinct(Direction::Outgoing);
+                let instability = match afferent_coupling + efferent_coupling {
+                    0 => 0.0,
+                    total => efferent_coupling as f64 / total as f64,
+                };
+
+                let own: BTreeSet<_> = ms.ref_entities.iter().map(Entity::qualified_name).collect();
+                let others = entity_names.iter().filter(|(name, _)| *name != ms.name);
+                let shared_entities = own
+                    .iter()
+                    .filter(|entity| others.clone().any(|(_, names)| names.contains(*entity)))
+                    .count();
+                let entity_overlap = others
+                    .filter(|(_, names)| own.iter().any(|entity| names.contains(entity)))
+                    .count();
+
+                ServiceMetrics {
+                    service: ms.name.clone(),
+                    fan_in: graph.edges_directed(ndx, Direction::Incoming).count(),
+                    fan_out: graph.edges_directed(ndx, Direction::Outgoing).count(),
+                    afferent_coupling,
+                    efferent_coupling,
+                    instability,
+                    endpoints: ms.endpoints.len(),
+                    entities: ms.ref_entities.len(),
+                    shared_entities,
+                    entity_overlap,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        // Count each pair of services with a call between them once
+        let nodes = graph.node_count();
+        let pairs = graph
+            .edge_indices()
+            .filter_map(|edge| graph.edge_endpoints(edge))
+            .filter(|(from, to)| from != to)
+            .collect::<HashSet<_>>()
+            .len();
+        let density = if nodes > 1 {
+            pairs as f64 / (nodes * (nodes - 1)) as f64
+        } else {
+            0.0
+        };
+
+        // Average the distance of every service from those it reaches
+        let distances: Vec<usize> = graph
+            .node_indices()
+            .flat_map(|ndx| ms_graph.reachable(ndx, Direction::Outgoing))
+            .map(|(_, distance)| distance)
+            .collect();
+        let average_path_length = if distances.is_empty() {
+            0.0
+        } else {
+            distances.iter().sum::<usize>() as f64 / distances.len() as f64
+        };
+
+        ArchitectureMetrics {
+            system: SystemMetrics {
+                services: services.len(),
+                external_services: nodes - services.len(),
+                calls: graph.edge_count(),
+                density,
+                cycles: ms_graph.cycles().len(),
+                average_path_length,
+                entities: entity_graph.as_ref().node_count(),
+                entity_relationships: entity_graph.as_ref().edge_count(),
+            },
+            services,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{fixtures::service, CallKind, ExternalService, MicroserviceCall};
+    use petgraph::graph::DiGraph;
+
+    #[test]
+    fn measures_services() {
+        let mut graph = DiGraph::new();
+        let orders = graph.add_node(service("orders", &["Order", "User"]));
+        let users = graph.add_node(service("users", &["User"]));
+        let stripe = graph.add_node(ServiceNode::External(ExternalService {
+            name: "stripe".into(),
+            host: None,
+        }));
+        let call = MicroserviceCall {
+            kind: CallKind::Rpc,
+            path: None,
+            endpoint: None,
+        };
+        graph.add_edge(orders, users, call.clone());
+        graph.add_edge(orders, users, call.clone());
+        graph.add_edge(orders, stripe, call);
+        let ms_graph = MicroserviceGraph(graph);
+
+        let metrics = ArchitectureMetrics::new(&ms_graph, &EntityGraph::try_new(&[]).unwrap());
+        let orders = &metrics.services[0];
+        assert_eq!((0, 3), (orders.fan_in, orders.fan_out));
+        assert_eq!((0, 2), (orders.afferent_coupling, orders.efferent_coupling));
+        assert_eq!(1.0, orders.instability);
+        assert_eq!(
+            (2, 1, 1),
+            (
+                orders.entities,
+                orders.shared_entities,
+                orders.entity_overlap
+            )
+        );
+        assert_eq!(0.0, metrics.services[1].instability);
+
+        assert_eq!(2, metrics.system.services);
+        assert_eq!(1, metrics.system.external_services);
+        assert_eq!(2.0 / 6.0, metrics.system.density);
+        assert_eq!(1.0, metrics.system.average_path_length);
+    }
+}
@@ -0,0 +1,424 @@
+//! Architecture rules, declared in a policy file and checked against the model graphs
+//!
+//! A policy is JSON or YAML, by its extension, and every rule is optional:
+//! ```yaml
+//! # Calls that must not be made, with `*` matching any run of characters in a name
+//! forbidden:
+//!   - { from: payment-service, to: user-service }
+//! # The only services a service may call, for the services listed
+//! allowed:
+//!   gateway: [order-service, user-service]
+//! # Layers from the top down; a service may only call its own layer or those below
+//! layers:
+//!   - { name: edge, services: [gateway] }
+//!   - { name: domain, services: [order-service, payment-service, user-service] }
+//! # The most distinct services any service may call
+//! max_fan_out: 5
+//! # No entity may be shared between services, limited to the given databases if any
+//! shared_entities:
+//!   databases: [MySQL]
+//! ```
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    DatabaseType, EntityGraph, EntityId, MicroserviceCall, MicroserviceGraph, ServiceNode,
+};
+
+/// An error reading a policy
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum PolicyError {
+    #[error("could not read policy: {0}")]
+    Io(String),
+    #[error("invalid policy: {0}")]
+    Parse(String),
+}
+
+/// The architecture rules a system must follow
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Policy {
+    pub forbidden: Vec<DependencyRule>,
+    pub allowed: BTreeMap<String, Vec<String>>,
+    pub layers: Vec<Layer>,
+    pub max_fan_out: Option<usize>,
+    pub shared_entities: Option<SharedEntityRule>,
+}
+
+/// A dependency from one service, or services, to another
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DependencyRule {
+    pub from: String,
+    pub to: String,
+}
+
+/// A named layer of services
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Layer {
+    pub name: String,
+    pub services: Vec<String>,
+}
+
+/// Forbids services from sharing entities
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SharedEntityRule {
+    /// The databases whose entities may not be shared, or all if empty
+    pub databases: Vec<String>,
+}
+
+/// The rule a violation breaks
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PolicyRule {
+    Forbidden,
+    Allowed,
+    Layers,
+    MaxFanOut,
+    SharedEntities,
+}
+
+/// Where a violation is, as precisely as the model knows
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Location {
+    pub service: String,
+    pub repository: Option<String>,
+    pub root: Option<String>,
+    /// The offending call, such as `GET /api/v1/users`
+    pub call: Option<String>,
+}
+
+/// A broken rule
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Violation {
+    pub rule: PolicyRule,
+    pub message: String,
+    pub locations: Vec<Location>,
+}
+
+/// The result of checking a policy
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PolicyReport {
+    /// Whether no rule was broken
+    pub passed: bool,
+    pub violations: Vec<Violation>,
+}
+
+impl Policy {
+    /// Reads a policy from a JSON or YAML file
+    pub fn from_path(path: &Path) -> Result<Policy, PolicyError> {
+        let source =
+            std::fs::read_to_string(path).map_err(|err| PolicyError::Io(err.to_string()))?;
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("yaml" | "yml") => {
+                serde_yaml::from_str(&source).map_err(|err| PolicyError::Parse(err.to_string()))
+            }
+            _ => serde_json::from_str(&source).map_err(|err| PolicyError::Parse(err.to_string())),
+        }
+    }
+
+    /// Checks the system against the policy
+    pub fn check(&self, ms_graph: &MicroserviceGraph, entity_graph: &EntityGraph) -> PolicyReport {
+        let graph = ms_graph.as_ref();
+        let mut violations = vec![];
+        let mut fan_out: BTreeMap<&str, BTreeSet<&str>> = BTreeMap::new();
+
+        for edge in graph.edge_indices() {
+            let (from, to) = match graph.edge_endpoints(edge) {
+                Some(endpoints) => (&graph[endpoints.0], &graph[endpoints.1]),
+                None => continue,
+            };
+            let (from_name, to_name) = (from.name(), to.name());
+            if from_name != to_name {
+                fan_out.entry(from_name).or_default().insert(to_name);
+            }
+            let location = || vec![call_location(from, &graph[edge])];
+
+            if let Some(rule) = self
+                .forbidden
+                .iter()
+                .find(|rule| name_matches(&rule.from, from_name) && name_matches(&rule.to, to_name))
+            {
+                violations.push(Violation {
+                    rule: PolicyRule::Forbidden,
+                    message: format!(
+                        "{} calls {}, which is forbidden by {} -> {}",
+                        from_name, to_name, rule.from, rule.to
+                    ),
+                    locations: location(),
+                });
+            }
+
+            if let Some(allowed) = self.allowed.get(from_name) {
+                if !allowed.iter().any(|pattern| name_matches(pattern, to_name)) {
+                    violations.push(Violation {
+                        rule: PolicyRule::Allowed,
+                        message: format!(
+                            "{} calls {}, which is not among the services it may call",
+                            from_name, to_name
+                        ),
+                        locations: location(),
+                    });
+                }
+            }
+
+            if let (Some(from_layer), Some(to_layer)) =
+                (self.layer_of(from_name), self.layer_of(to_name))
+            {
+                if to_layer < from_layer {
+                    violations.push(Violation {
+                        rule: PolicyRule::Layers,
+                        message: format!(
+                            "{} in layer {} calls {} in the higher layer {}",
+                            from_name,
+                            self.layers[from_layer].name,
+                            to_name,
+                            self.layers[to_layer].name
+                        ),
+                        locations: location(),
+                    });
+                }
+            }
+        }
+
+        if let Some(max) = self.max_fan_out {
+            for node in graph.node_weights() {
+                let called = fan_out.get(node.name()).map(BTreeSet::len).unwrap_or(0);
+                if called > max {
+                    violations.push(Violation {
+                        rule: PolicyRule::MaxFanOut,
+                        message: format!(
+                            "{} calls {} services, more than the {} allowed",
+                            node.name(),
+                            called,
+                            max
+                        ),
+                        locations: vec![service_location(node)],
+                    });
+                }
+            }
+        }
+
+        if let Some(rule) = &self.shared_entities {
+            violations.extend(rule.check(ms_graph, entity_graph));
+        }
+
+        PolicyReport {
+            passed: violations.is_empty(),
+            violations,
+        }
+    }
+
+    /// Gets the index of the layer a service is in, if any
+    fn layer_of(&self, name: &str) -> Option<usize> {
+        self.layers.iter().position(|layer| {
+            layer
+                .services
+                .iter()
+                .any(|pattern| name_matches(pattern, name))
+        })
+    }
+}
+
+impl SharedEntityRule {
+    /// Checks the entities the services reference, whose database types come from
+    /// the services rather than the entity graph, which may have merged them away
+    fn check(&self, ms_graph: &MicroserviceGraph, entity_graph: &EntityGraph) -> Vec<Violation> {
+        let microservices = ms_graph.microservices();
+        let mut checked = BTreeSet::new();
+        entity_graph
+            .nodes()
+            .into_iter()
+            .filter_map(|entity| {
+                // The entity as any service references it
+                let id = EntityId {
+                    service: None,
+                    ..entity.id()
+                };
+                if !checked.insert(id.clone()) {
+                    return None;
+                }
+                let sharing: Vec<_> = microservices
+                    .iter()
+                    .filter_map(|ms| {
+                        let referenced = ms
+                            .ref_entities
+                            .iter()
+                            .find(|e| id.matches(&e.id()) && self.covers(&e.ty))?;
+                        Some((ms, String::from(referenced.ty.clone())))
+                    })
+                    .collect();
+                if sharing.len() < 2 {
+                    return None;
+                }
+                let mut databases: Vec<_> = sharing.iter().map(|(_, db)| db.as_str()).collect();
+                databases.sort_unstable();
+                databases.dedup();
+                let names: Vec<_> = sharing.iter().map(|(ms, _)| ms.name.as_str()).collect();
+                Some(Violation {
+                    rule: PolicyRule::SharedEntities,
+                    message: format!(
+                        "the {} entity {} is shared by {}",
+                        databases.join("/"),
+                        entity.qualified_name(),
+                        names.join(", ")
+                    ),
+                    locations: sharing
+                        .into_iter()
+                        .map(|(ms, _)| service_location(&ServiceNode::Internal(ms.clone())))
+                        .collect(),
+                })
+            })
+            .collect()
+    }
+
+    /// Whether the rule covers entities in the given database
+    fn covers(&self, ty: &DatabaseType) -> bool {
+        let database = String::from(ty.clone());
+        self.databases.is_empty()
+            || self
+                .databases
+                .iter()
+                .any(|db| db.eq_ignore_ascii_case(&database))
+    }
+}
+
+/// Whether a name matches a pattern, where `*` matches any run of characters
+fn name_matches(pattern: &str, name: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let mut rest = match name.strip_prefix(parts.next().unwrap_or_default()) {
+        Some(rest) => rest,
+        None => return false,
+    };
+    let parts: Vec<_> = parts.collect();
+    let (last, middle) = match parts.split_last() {
+        Some(split) => split,
+        None => return rest.is_empty(),
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(ndx) => rest = &rest[ndx + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
+fn service_location(node: &ServiceNode) -> Location {
+    let origin = match node {
+        ServiceNode::Internal(ms) => ms.origin.clone(),
+        ServiceNode::External(_) => None,
+    };
+    Location {
+        service: node.name().to_string(),
+        repository: origin.as_ref().map(|origin| origin.repository.clone()),
+        root: origin.map(|origin| origin.root),
+        call: None,
+    }
+}
+
+fn call_location(from: &ServiceNode, call: &MicroserviceCall) -> Location {
+    Location {
+        call: Some(call.describe()),
+        ..service_location(from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CallKind, Entity, ExternalService, Microservice};
+    use petgraph::graph::DiGraph;
+
+    #[test]
+    fn reports_violations() {
+        let mut graph = DiGraph::new();
+        let mut add = |name: &str| {
+            graph.add_node(ServiceNode::External(ExternalService {
+                name: name.into(),
+                host: None,
+            }))
+        };
+        let (gateway, payments, users) =
+            (add("gateway"), add("payment-service"), add("user-service"));
+        let call = MicroserviceCall {
+            kind: CallKind::Http(http::Method::GET),
+            path: Some("/api/v1/users".into()),
+            endpoint: None,
+        };
+        graph.add_edge(gateway, payments, call.clone());
+        graph.add_edge(payments, users, call.clone());
+        graph.add_edge(users, gateway, call);
+        let ms_graph = MicroserviceGraph(graph);
+        let entity_graph =
+            EntityGraph::try_new(&[Entity::new("User", vec![], DatabaseType::MySQL)]).unwrap();
+
+        let policy: Policy = serde_yaml::from_str(
+            r#"
+forbidden:
+  - { from: payment-service, to: user-* }
+layers:
+  - { name: edge, services: [gateway] }
+  - { name: domain, services: ["*-service"] }
+max_fan_out: 1
+"#,
+        )
+        .unwrap();
+        let report = policy.check(&ms_graph, &entity_graph);
+
+        assert!(!report.passed);
+        assert_eq!(
+            vec![PolicyRule::Forbidden, PolicyRule::Layers],
+            report
+                .violations
+                .iter()
+                .map(|violation| violation.rule)
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            Some("GET /api/v1/users".to_string()),
+            report.violations[0].locations[0].call
+        );
+        assert!(Policy::default().check(&ms_graph, &entity_graph).passed);
+    }
+
+    #[test]
+    fn reports_entities_shared_within_a_database() {
+        let service = |name: &str| Microservice {
+            name: name.into(),
+            language: source_code_parser::Language::Java,
+            ref_entities: vec![Entity::new("User", vec![], DatabaseType::MySQL)],
+            endpoints: vec![],
+            origin: None,
+        };
+        let mut graph = DiGraph::new();
+        graph.add_node(ServiceNode::Internal(service("orders")));
+        graph.add_node(ServiceNode::Internal(service("users")));
+        let ms_graph = MicroserviceGraph(graph);
+        // As the bounded context merges them, without their database
+        let entity_graph = EntityGraph::try_new(&[Entity::new(
+            "User",
+            vec![],
+            DatabaseType::Unknown(String::new()),
+        )])
+        .unwrap();
+
+        let policy = |databases: &[&str]| Policy {
+            shared_entities: Some(SharedEntityRule {
+                databases: databases.iter().map(|db| db.to_string()).collect(),
+            }),
+            ..Policy::default()
+        };
+        let report = policy(&["mysql"]).check(&ms_graph, &entity_graph);
+        assert_eq!(1, report.violations.len());
+        assert_eq!(
+            "the MySQL entity User is shared by orders, users",
+            report.violations[0].message
+        );
+        assert!(policy(&["MongoDB"]).check(&ms_graph, &entity_graph).passed);
+    }
+}
@@ -0,0 +1,390 @@
+//! Queries over the services in a microservice graph
+//!
+//! Services are named by [`ServiceNode::name`], qualified by their origin as
+//! [`ServiceNode::qualified_name`] where several services share a name. A service that
+//! is not in the graph has no dependencies, dependents or paths.
+use std::collections::{hash_map::Entry, HashMap, VecDeque};
+
+use petgraph::{algo, graph::NodeIndex, Direction};
+
+use crate::{MicroserviceGraph, ServiceNode};
+
+/// An error finding the service a query names
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum QueryError {
+    #[error("`{name}` names several services, qualify it as one of {}", candidates.join(", "))]
+    Ambiguous {
+        name: String,
+        /// The qualified names of the services it names
+        candidates: Vec<String>,
+    },
+}
+
+impl ServiceNode {
+    /// The service's name qualified by the repository root it was found in, as
+    /// `repository/root::name`, or its bare name if its origin is unknown
+    pub fn qualified_name(&self) -> String {
+        match self {
+            ServiceNode::Internal(ms) => match &ms.origin {
+                Some(origin) => format!("{}/{}::{}", origin.repository, origin.root, ms.name),
+                None => ms.name.clone(),
+            },
+            ServiceNode::External(external) => external.name.clone(),
+        }
+    }
+}
+
+/// A service affected by a change to, or failure of, another
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Affected {
+    pub service: ServiceNode,
+    /// How many calls away from the changed service it is
+    pub distance: usize,
+}
+
+impl MicroserviceGraph {
+    /// Gets the services a service calls directly
+    pub fn dependencies(&self, name: &str) -> Result<Vec<ServiceNode>, QueryError> {
+        self.neighbors(name, Direction::Outgoing)
+    }
+
+    /// Gets the services that call a service directly
+    pub fn dependents(&self, name: &str) -> Result<Vec<ServiceNode>, QueryError> {
+        self.neighbors(name, Direction::Incoming)
+    }
+
+    /// Gets every service a service calls, directly or through others
+    pub fn transitive_dependencies(&self, name: &str) -> Result<Vec<ServiceNode>, QueryError> {
+        Ok(self
+            .reachable_from(name, Direction::Outgoing)?
+            .into_iter()
+            .map(|(ndx, _)| self.0[ndx].clone())
+            .collect())
+    }
+
+    /// Gets every service that calls a service, directly or through others
+    pub fn transitive_dependents(&self, name: &str) -> Result<Vec<ServiceNode>, QueryError> {
+        Ok(self
+            .reachable_from(name, Direction::Incoming)?
+            .into_iter()
+            .map(|(ndx, _)| self.0[ndx].clone())
+            .collect())
+    }
+
+    /// Gets the services affected if a service fails or changes: every service that
+    /// calls it, directly or through others, nearest first
+    pub fn blast_radius(&self, name: &str) -> Result<Vec<Affected>, QueryError> {
+        Ok(self
+            .reachable_from(name, Direction::Incoming)?
+            .into_iter()
+            .map(|(ndx, distance)| Affected {
+                service: self.0[ndx].clone(),
+                distance,
+            })
+            .collect())
+    }
+
+    /// Gets the shortest chain of calls from one service to another, including both
+    pub fn shortest_path(
+        &self,
+        from: &str,
+        to: &str,
+    ) -> Result<Option<Vec<ServiceNode>>, QueryError> {
+        let (from, to) = match (self.index_of(from)?, self.index_of(to)?) {
+            (Some(from), Some(to)) => (from, to),
+            _ => return Ok(None),
+        };
+        Ok(algo::astar(&self.0, from, |ndx| ndx == to, |_| 1, |_| 0)
+            .map(|(_, path)| path.into_iter().map(|ndx| self.0[ndx].clone()).collect()))
+    }
+
+    /// Gets the strongly connected components of the graph, each a set of services
+    /// that can all reach each other, with every component's dependencies before it
+    pub fn strongly_connected_components(&self) -> Vec<Vec<ServiceNode>> {
+        algo::tarjan_scc(&self.0)
+            .into_iter()
+            .map(|component| {
+                component
+                    .into_iter()
+                    .map(|ndx| self.0[ndx].clone())
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Gets the components with a cycle of calls: those of several services, or of one
+    /// service that calls itself
+    pub fn cycles(&self) -> Vec<Vec<ServiceNode>> {
+        algo::tarjan_scc(&self.0)
+            .into_iter()
+            .filter(|component| {
+                component.len() > 1 || self.0.contains_edge(component[0], component[0])
+            })
+            .map(|component| {
+                component
+                    .into_iter()
+                    .map(|ndx| self.0[ndx].clone())
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Layers the services so each calls only services in lower layers, or in its own
+    /// layer if they are in a cycle together
+    ///
+    /// The first layer holds the services that call no others.
+    pub fn layers(&self) -> Vec<Vec<ServiceNode>> {
+        // Tarjan's algorithm gives each component after every component it calls
+        let components = algo::tarjan_scc(&self.0);
+        let mut layer_of: HashMap<NodeIndex, usize> = HashMap::new();
+        let mut layers: Vec<Vec<ServiceNode>> = vec![];
+        for component in components {
+            let layer = component
+                .iter()
+                .flat_map(|ndx| self.0.neighbors_directed(*ndx, Direction::Outgoing))
+                .filter(|called| !component.contains(called))
+                .filter_map(|called| layer_of.get(&called))
+                .map(|layer| layer + 1)
+                .max()
+                .unwrap_or(0);
+
+            if layers.len() <= layer {
+                layers.resize(layer + 1, vec![]);
+            }
+            for ndx in component {
+                layer_of.insert(ndx, layer);
+                layers[layer].push(self.0[ndx].clone());
+            }
+        }
+        layers
+    }
+
+    /// Finds the service with the qualified name, or else the only service with the
+    /// bare name
+    fn index_of(&self, name: &str) -> Result<Option<NodeIndex>, QueryError> {
+        let graph = &self.0;
+        if let Some(ndx) = graph
+            .node_indices()
+            .find(|ndx| graph[*ndx].qualified_name() == name)
+        {
+            return Ok(Some(ndx));
+        }
+
+        let named: Vec<_> = graph
+            .node_indices()
+            .filter(|ndx| graph[*ndx].name() == name)
+            .collect();
+        match named.as_slice() {
+            [] => Ok(None),
+            [ndx] => Ok(Some(*ndx)),
+            _ => Err(QueryError::Ambiguous {
+                name: name.to_string(),
+                candidates: named
+                    .iter()
+                    .map(|ndx| graph[*ndx].qualified_name())
+                    .collect(),
+            }),
+        }
+    }
+
+    /// Gets a service's distinct neighbors in a direction, in the graph's node order
+    fn neighbors(&self, name: &str, direction: Direction) -> Result<Vec<ServiceNode>, QueryError> {
+        let ndx = match self.index_of(name)? {
+            Some(ndx) => ndx,
+            None => return Ok(vec![]),
+        };
+        let mut neighbors: Vec<_> = self.0.neighbors_directed(ndx, direction).collect();
+        neighbors.sort();
+        neighbors.dedup();
+        Ok(neighbors
+            .into_iter()
+            .map(|ndx| self.0[ndx].clone())
+            .collect())
+    }
+
+    /// Gets the services reachable from a named service in a direction
+    fn reachable_from(
+        &self,
+        name: &str,
+        direction: Direction,
+    ) -> Result<Vec<(NodeIndex, usize)>, QueryError> {
+        Ok(match self.index_of(name)? {
+            Some(start) => self.reachable(start, direction),
+            None => vec![],
+        })
+    }
+
+    /// Gets the services reachable from a service in a direction, other than itself,
+    /// along with their distance from it, nearest first
+    pub(crate) fn reachable(
+        &self,
+        start: NodeIndex,
+        direction: Direction,
+    ) -> Vec<(NodeIndex, usize)> {
+        let mut distances = HashMap::from([(start, 0)]);
+        let mut queue = VecDeque::from([start]);
+        let mut reached = vec![];
+        while let Some(ndx) = queue.pop_front() {
+            let distance = distances[&ndx] + 1;
+            let mut neighbors: Vec<_> = self.0.neighbors_directed(ndx, direction).collect();
+            neighbors.sort();
+            for neighbor in neighbors {
+                if let Entry::Vacant(entry) = distances.entry(neighbor) {
+                    entry.insert(distance);
+                    reached.push((neighbor, distance));
+                    queue.push_back(neighbor);
+                }
+            }
+        }
+        reached
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CallKind, ExternalService, Microservice, MicroserviceCall, Origin};
+    use petgraph::graph::DiGraph;
+    use source_code_parser::Language;
+
+    /// gateway -> orders -> payments <-> ledger, gateway -> users
+    fn graph() -> MicroserviceGraph {
+        let mut graph = DiGraph::new();
+        let mut add = |name: &str| {
+            graph.add_node(ServiceNode::External(ExternalService {
+                name: name.into(),
+                host: None,
+            }))
+        };
+        let (gateway, orders, payments, ledger, users) = (
+            add("gateway"),
+            add("orders"),
+            add("payments"),
+            add("ledger"),
+            add("users"),
+        );
+        for (from, to) in [
+            (gateway, orders),
+            (orders, payments),
+            (payments, ledger),
+            (ledger, payments),
+            (gateway, users),
+        ] {
+            graph.add_edge(
+                from,
+                to,
+                MicroserviceCall {
+                    kind: CallKind::Rpc,
+                    path: None,
+                    endpoint: None,
+                },
+            );
+        }
+        MicroserviceGraph(graph)
+    }
+
+    fn names(nodes: Vec<ServiceNode>) -> Vec<String> {
+        nodes.iter().map(|node| node.name().to_string()).collect()
+    }
+
+    #[test]
+    fn queries_services() {
+        let graph = graph();
+        assert_eq!(
+            vec!["orders", "users"],
+            names(graph.dependencies("gateway").unwrap())
+        );
+        assert_eq!(
+            vec!["orders", "ledger"],
+            names(graph.dependents("payments").unwrap())
+        );
+        assert_eq!(
+            vec!["orders", "users", "payments", "ledger"],
+            names(graph.transitive_dependencies("gateway").unwrap())
+        );
+        assert_eq!(
+            vec!["gateway", "orders", "payments", "ledger"],
+            names(graph.shortest_path("gateway", "ledger").unwrap().unwrap())
+        );
+        assert_eq!(Ok(None), graph.shortest_path("users", "gateway"));
+        assert_eq!(
+            vec![
+                ("orders".to_string(), 1),
+                ("ledger".to_string(), 1),
+                ("gateway".to_string(), 2)
+            ],
+            graph
+                .blast_radius("payments")
+                .unwrap()
+                .into_iter()
+                .map(|affected| (affected.service.name().to_string(), affected.distance))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(1, graph.cycles().len());
+
+        let layers: Vec<_> = graph
+            .layers()
+            .into_iter()
+            .map(|layer| {
+                let mut layer = names(layer);
+                layer.sort();
+                layer
+            })
+            .collect();
+        assert_eq!(
+            vec![
+                vec!["ledger", "payments", "users"],
+                vec!["orders"],
+                vec!["gateway"]
+            ],
+            layers
+        );
+    }
+
+    #[test]
+    fn finds_services_sharing_a_name_by_origin() {
+        let service = |root: &str| {
+            ServiceNode::Internal(Microservice {
+                name: "orders".into(),
+                language: Language::Java,
+                ref_entities: vec![],
+                endpoints: vec![],
+                origin: Some(Origin {
+                    repository: "shop".into(),
+                    root: root.into(),
+                }),
+            })
+        };
+        let mut graph = DiGraph::new();
+        let v1 = graph.add_node(service("v1"));
+        let v2 = graph.add_node(service("v2"));
+        graph.add_edge(
+            v2,
+            v1,
+            MicroserviceCall {
+                kind: CallKind::Rpc,
+                path: None,
+                endpoint: None,
+            },
+        );
+        let graph = MicroserviceGraph(graph);
+
+        assert_eq!(
+            Err(QueryError::Ambiguous {
+                name: "orders".into(),
+                candidates: vec!["shop/v1::orders".into(), "shop/v2::orders".into()],
+            }),
+            graph.dependencies("orders")
+        );
+        assert_eq!(
+            vec!["shop/v1::orders"],
+            graph
+                .dependencies("shop/v2::orders")
+                .unwrap()
+                .iter()
+                .map(ServiceNode::qualified_name)
+                .collect::<Vec<_>>()
+        );
+        assert!(graph.dependencies("shop/v1::orders").unwrap().is_empty());
+    }
+}
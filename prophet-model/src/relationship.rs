@@ -0,0 +1,322 @@
+//! The relationships between entities, as a class diagram draws them
+use serde::{Deserialize, Serialize};
+
+use crate::{Cardinality, Entity, EntityGraph, Field, RessaObject, SchemaError};
+
+/// How an entity holds the entities a field references, after UML's aggregation kinds
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum Aggregation {
+    /// A plain reference
+    #[default]
+    None,
+    /// A part that may be shared with other wholes, or outlive its whole
+    Shared,
+    /// A part owned by its whole, living and dying with it
+    Composite,
+}
+
+impl Aggregation {
+    /// Reads a field's `aggregation` attribute, if it has one, as `"none"`, `"shared"` or
+    /// `"composite"`
+    pub(crate) fn from_field(field: &RessaObject) -> Result<Self, SchemaError> {
+        match field.opt_string("aggregation")?.as_deref() {
+            None | Some("none") => Ok(Aggregation::None),
+            Some("shared") => Ok(Aggregation::Shared),
+            Some("composite") => Ok(Aggregation::Composite),
+            Some(_) => Err(field.mismatch("aggregation", "\"none\", \"shared\" or \"composite\"")),
+        }
+    }
+}
+
+/// A kind of relationship between entities
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RelationshipKind {
+    /// One entity references another
+    Association,
+    /// One entity holds others as parts it may share
+    Aggregation,
+    /// One entity owns others as parts
+    Composition,
+    /// One entity extends another
+    Inheritance,
+}
+
+/// A relationship from one entity to another, such as from a whole to its parts or from
+/// a subclass to its superclass
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Relationship {
+    pub kind: RelationshipKind,
+    /// How many of the source entity each target relates to, if known
+    pub source: Option<Cardinality>,
+    /// How many of the target entity each source relates to, unless an inheritance
+    pub target: Option<Cardinality>,
+    /// The field of the source referencing the target, unless an inheritance
+    pub source_field: Option<String>,
+    /// The field of the target referencing the source, if the relationship is
+    /// bidirectional
+    pub target_field: Option<String>,
+}
+
+impl Relationship {
+    /// Whether both entities reference each other
+    pub fn is_bidirectional(&self) -> bool {
+        self.target_field.is_some()
+    }
+
+    /// Relates an entity to the one its field references, through the field referencing
+    /// it back if there is one
+    fn new(field: &Field, back: Option<&Field>) -> Self {
+        let kind = match field.aggregation {
+            Aggregation::None => RelationshipKind::Association,
+            Aggregation::Shared => RelationshipKind::Aggregation,
+            Aggregation::Composite => RelationshipKind::Composition,
+        };
+        let source = match back {
+            Some(back) => Some(back.cardinality()),
+            // A part belongs to one whole
+            None if kind == RelationshipKind::Composition => Some(Cardinality::One),
+            None => None,
+        };
+        Relationship {
+            kind,
+            source,
+            target: Some(field.cardinality()),
+            source_field: Some(field.name.clone()),
+            target_field: back.map(|back| back.name.clone()),
+        }
+    }
+
+    fn inheritance() -> Self {
+        Relationship {
+            kind: RelationshipKind::Inheritance,
+            source: None,
+            target: None,
+            source_field: None,
+            target_field: None,
+        }
+    }
+}
+
+impl Field {
+    /// How many entities the field holds
+    pub fn cardinality(&self) -> Cardinality {
+        if self.is_collection {
+            Cardinality::Many
+        } else if self.is_optional {
+            Cardinality::ZeroOrOne
+        } else {
+            Cardinality::One
+        }
+    }
+}
+
+/// A field referencing an entity, as the indices of its own entity and the one referenced
+type Reference<'a> = (usize, &'a Field, usize);
+
+/// Finds the relationships between entities, as the indices of the entities they relate:
+/// one for each field referencing an entity, merged with the field referencing it back
+/// if there is one, and one for each entity extending another
+///
+/// A merged relationship runs from the whole to the part if either field holds the other
+/// entity as a part, and from the first field's entity otherwise.
+pub(crate) fn relationships(entities: &[Entity]) -> Vec<(usize, usize, Relationship)> {
+    let references: Vec<Reference> = entities
+        .iter()
+        .enumerate()
+        .flat_map(|(ndx, entity)| {
+            entity.fields.iter().filter_map(move |field| {
+                EntityGraph::resolve_type(entities, entity, &field.ty).map(|to| (ndx, field, to))
+            })
+        })
+        .collect();
+
+    let mut relationships = vec![];
+    let mut paired = vec![false; references.len()];
+    for ndx in 0..references.len() {
+        if paired[ndx] {
+            continue;
+        }
+        paired[ndx] = true;
+        let inverse = inverse_of(&references, &paired, ndx);
+        if let Some(inverse) = inverse {
+            paired[inverse] = true;
+        }
+
+        let (from, field, to) = references[ndx];
+        relationships.push(match inverse.map(|inverse| references[inverse].1) {
+            Some(back) if back.aggregation > field.aggregation => {
+                (to, from, Relationship::new(back, Some(field)))
+            }
+            back => (from, to, Relationship::new(field, back)),
+        });
+    }
+
+    for (ndx, entity) in entities.iter().enumerate() {
+        let parent = entity
+            .extends
+            .as_deref()
+            .and_then(|ty| EntityGraph::resolve_type(entities, entity, ty));
+        if let Some(parent) = parent.filter(|parent| *parent != ndx) {
+            relationships.push((ndx, parent, Relationship::inheritance()));
+        }
+    }
+    relationships
+}
+
+/// Finds the unpaired field referencing back to the entity of a reference: the one the
+/// reference's `mapped_by` names, or whose own `mapped_by` names the reference, or else
+/// the only one, if the reference is the only one between two different entities
+fn inverse_of(references: &[Reference], paired: &[bool], ndx: usize) -> Option<usize> {
+    let (from, field, to) = references[ndx];
+    let back: Vec<_> = references
+        .iter()
+        .enumerate()
+        .filter(|(other, (other_from, _, other_to))| {
+            !paired[*other] && *other_from == to && *other_to == from
+        })
+        .map(|(other, (_, field, _))| (other, *field))
+        .collect();
+
+    if let Some(mapped_by) = &field.mapped_by {
+        return back
+            .iter()
+            .find(|(_, back)| back.name == *mapped_by)
+            .map(|(other, _)| *other);
+    }
+    if let Some((other, _)) = back
+        .iter()
+        .find(|(_, back)| back.mapped_by.as_ref() == Some(&field.name))
+    {
+        return Some(*other);
+    }
+
+    let count = |a: usize, b: usize| {
+        references
+            .iter()
+            .filter(|(other_from, _, other_to)| *other_from == a && *other_to == b)
+            .count()
+    };
+    match back.as_slice() {
+        [(other, back)]
+            if from != to
+                && back.mapped_by.is_none()
+                && count(from, to) == 1
+                && count(to, from) == 1 =>
+        {
+            Some(*other)
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DatabaseType, FromRessa};
+    use runestick::{Shared, Value};
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn rejects_unknown_aggregations() {
+        let field = |aggregation: &str| {
+            let attributes = BTreeMap::from([
+                (
+                    "name".to_string(),
+                    Value::String(Shared::new("items".into())),
+                ),
+                (
+                    "type".to_string(),
+                    Value::String(Shared::new("Item".into())),
+                ),
+                ("is_collection".to_string(), Value::Bool(true)),
+                (
+                    "aggregation".to_string(),
+                    Value::String(Shared::new(aggregation.into())),
+                ),
+            ]);
+            Field::from_ressa(&RessaObject::new("field", attributes))
+        };
+        assert_eq!(
+            Aggregation::Composite,
+            field("composite").unwrap().aggregation
+        );
+        assert_eq!(
+            SchemaError {
+                path: "field.aggregation".into(),
+                expected: "\"none\", \"shared\" or \"composite\"".into(),
+                found: "the string \"owned\"".into(),
+            },
+            field("owned").unwrap_err()
+        );
+    }
+
+    #[test]
+    fn pairs_fields_referencing_each_other() {
+        let graph = EntityGraph::try_new(&[
+            Entity::new(
+                "Order",
+                vec![
+                    Field::new("billing", "Address", false),
+                    Field::new("shipping", "Address", false),
+                ],
+                DatabaseType::MySQL,
+            ),
+            Entity::new(
+                "Address",
+                vec![Field::new("order", "Order", false)],
+                DatabaseType::MySQL,
+            ),
+            Entity::new(
+                "Category",
+                vec![
+                    Field {
+                        is_optional: true,
+                        ..Field::new("parent", "Category", false)
+                    },
+                    Field {
+                        mapped_by: Some("parent".into()),
+                        ..Field::new("children", "Category", true)
+                    },
+                ],
+                DatabaseType::MySQL,
+            ),
+        ])
+        .unwrap();
+
+        let relationships: Vec<_> = graph
+            .edges()
+            .into_inner()
+            .into_iter()
+            .map(|edge| {
+                (
+                    edge.from.name,
+                    edge.to.name,
+                    edge.weight.source,
+                    edge.weight.target,
+                )
+            })
+            .collect();
+        let relationship = |from: &str, to: &str, source, target| {
+            (from.to_string(), to.to_string(), source, target)
+        };
+        assert_eq!(
+            vec![
+                // The address's order could be the other end of either of the order's addresses
+                relationship("Order", "Address", None, Some(Cardinality::One)),
+                relationship("Order", "Address", None, Some(Cardinality::One)),
+                relationship("Address", "Order", None, Some(Cardinality::One)),
+                relationship(
+                    "Category",
+                    "Category",
+                    Some(Cardinality::Many),
+                    Some(Cardinality::ZeroOrOne)
+                ),
+            ],
+            relationships
+        );
+    }
+}
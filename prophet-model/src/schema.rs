@@ -0,0 +1,434 @@
+//! The schema of the `ctx` object a ReSSA bundle must produce
+//!
+//! ```text
+//! ctx
+//! └── services: [Service]
+//!
+//! Service
+//! ├── name: string
+//! ├── language: string            the name of the service's language
+//! ├── entities: [Entity]          optional
+//! ├── endpoints: [Endpoint]        optional
+//! ├── calls: [Call]               optional
+//! ├── repository: string          optional, added when the run is scoped
+//! └── root: string                optional, added when the run is scoped
+//!
+//! Entity                          belongs to the service it is listed under
+//! ├── name: string
+//! ├── type: string                the database, such as "MySQL" or "MongoDB"
+//! ├── fields: [Field]
+//! ├── namespace: string           optional, the package or namespace it is declared in
+//! ├── imports: [string]           optional, what its source file imports, such as
+//! │                               "com.shop.Order" or "com.shop.*"
+//! └── extends: string             optional, the type of the entity it extends
+//!
+//! Field
+//! ├── name: string
+//! ├── type: string
+//! ├── is_collection: bool
+//! ├── is_optional: bool           optional, whether it may hold nothing
+//! ├── aggregation: string         optional, "shared" for a part it may share, or
+//! │                               "composite" for a part it owns
+//! └── mapped_by: string           optional, the field of the referenced entity that
+//!                                 references this one back
+//!
+//! Endpoint
+//! ├── method: string              the HTTP method, or "RPC"
+//! ├── path: string                the path template, such as "/api/v1/orders/{id}"
+//! ├── handler_class: string       optional
+//! ├── handler_method: string      optional
+//! ├── parameters: [Parameter]     optional
+//! └── return_type: string         optional
+//!
+//! Parameter
+//! ├── name: string
+//! └── type: string
+//!
+//! Call
+//! ├── name: string                the name of the called service, which is kept as an
+//! │                               external service if it is not in `services`
+//! ├── type: string                "HTTP" or "RPC"
+//! ├── method: string              the HTTP method, for HTTP calls
+//! └── path: string                optional, the called path, resolved against the
+//!                                 called service's endpoints
+//! ```
+//!
+//! Any attribute may also be given as an option, with `None` counting as absent.
+use std::{collections::BTreeMap, fmt};
+
+use runestick::Value;
+
+/// An object in a ReSSA result that does not match the schema
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("{path}: expected {expected}, found {found}")]
+pub struct SchemaError {
+    /// The path to the offending attribute, such as `ctx.services[1] ("orders").calls`
+    pub path: String,
+    /// The type the schema expects
+    pub expected: String,
+    /// A description of the value found instead
+    pub found: String,
+}
+
+/// Every mismatch between a ReSSA result and the schema
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SchemaErrors(pub Vec<SchemaError>);
+
+impl fmt::Display for SchemaErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let errors = self.0.iter().map(ToString::to_string).collect::<Vec<_>>();
+        write!(f, "{}", errors.join("; "))
+    }
+}
+
+impl std::error::Error for SchemaErrors {}
+
+impl From<SchemaError> for SchemaErrors {
+    fn from(error: SchemaError) -> Self {
+        SchemaErrors(vec![error])
+    }
+}
+
+/// A type that can be read from an object in a ReSSA result
+pub trait FromRessa: Sized {
+    /// Reads the type from the object, naming the offending attribute on a mismatch
+    fn from_ressa(object: &RessaObject) -> Result<Self, SchemaError>;
+}
+
+/// A type that can be read from an attribute of an object in a ReSSA result
+pub trait FromRessaAttribute: Sized {
+    /// Reads the type from the object's attribute, naming it on a mismatch
+    fn from_attribute(object: &RessaObject, key: &str) -> Result<Self, SchemaError>;
+}
+
+impl FromRessaAttribute for String {
+    fn from_attribute(object: &RessaObject, key: &str) -> Result<Self, SchemaError> {
+        object.string(key)
+    }
+}
+
+impl FromRessaAttribute for bool {
+    fn from_attribute(object: &RessaObject, key: &str) -> Result<Self, SchemaError> {
+        object.bool(key)
+    }
+}
+
+impl FromRessaAttribute for Vec<String> {
+    fn from_attribute(object: &RessaObject, key: &str) -> Result<Self, SchemaError> {
+        object.strings(key)
+    }
+}
+
+impl<T: FromRessa> FromRessaAttribute for Vec<T> {
+    fn from_attribute(object: &RessaObject, key: &str) -> Result<Self, SchemaError> {
+        object.vec_of(key)
+    }
+}
+
+impl<T: FromRessaAttribute> FromRessaAttribute for Option<T> {
+    fn from_attribute(object: &RessaObject, key: &str) -> Result<Self, SchemaError> {
+        if object.contains(key) {
+            T::from_attribute(object, key).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+/// An object in a ReSSA result, along with its path for error messages
+#[derive(Debug, Clone)]
+pub struct RessaObject {
+    path: String,
+    attributes: BTreeMap<String, Value>,
+}
+
+impl RessaObject {
+    pub fn new(path: impl ToString, attributes: BTreeMap<String, Value>) -> Self {
+        RessaObject {
+            path: path.to_string(),
+            attributes,
+        }
+    }
+
+    /// The object's path in the ReSSA result
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// The object's attributes
+    pub fn attributes(&self) -> &BTreeMap<String, Value> {
+        &self.attributes
+    }
+
+    /// Whether the object has an attribute, other than `None`
+    pub fn contains(&self, key: &str) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Adds the object's name to its path, so errors say which object they are in
+    pub fn named(mut self) -> Self {
+        if let Ok(Some(name)) = self.opt_string("name") {
+            self.path = format!("{} ({:?})", self.path, name);
+        }
+        self
+    }
+
+    /// Gets a required string attribute
+    pub fn string(&self, key: &str) -> Result<String, SchemaError> {
+        self.opt_string(key)?
+            .ok_or_else(|| self.mismatch(key, "a string"))
+    }
+
+    /// Gets an optional string attribute
+    pub fn opt_string(&self, key: &str) -> Result<Option<String>, SchemaError> {
+        match self.get(key) {
+            None => Ok(None),
+            Some(value) => match string_value(&value) {
+                Some(string) => Ok(Some(string)),
+                None => Err(self.mismatch(key, "a string")),
+            },
+        }
+    }
+
+    /// Gets a required vector of strings
+    pub fn strings(&self, key: &str) -> Result<Vec<String>, SchemaError> {
+        let items = match self.get(key) {
+            Some(Value::Vec(items)) => match items.borrow_ref() {
+                Ok(items) => items.iter().cloned().collect::<Vec<_>>(),
+                Err(_) => return Err(self.mismatch(key, "a vector of strings")),
+            },
+            _ => return Err(self.mismatch(key, "a vector of strings")),
+        };
+
+        items
+            .into_iter()
+            .enumerate()
+            .map(|(ndx, item)| {
+                string_value(&item).ok_or_else(|| SchemaError {
+                    path: format!("{}[{}]", self.key_path(key), ndx),
+                    expected: "a string".into(),
+                    found: describe(Some(&item)),
+                })
+            })
+            .collect()
+    }
+
+    /// Gets a required bool attribute
+    pub fn bool(&self, key: &str) -> Result<bool, SchemaError> {
+        self.opt_bool(key)?
+            .ok_or_else(|| self.mismatch(key, "a bool"))
+    }
+
+    /// Gets an optional bool attribute
+    pub fn opt_bool(&self, key: &str) -> Result<Option<bool>, SchemaError> {
+        match self.get(key) {
+            None => Ok(None),
+            Some(Value::Bool(b)) => Ok(Some(b)),
+            Some(_) => Err(self.mismatch(key, "a bool")),
+        }
+    }
+
+    /// Gets a required vector of objects
+    pub fn objects(&self, key: &str) -> Result<Vec<RessaObject>, SchemaError> {
+        match self.get(key) {
+            None => Err(self.mismatch(key, "a vector of objects")),
+            Some(_) => self.opt_objects(key),
+        }
+    }
+
+    /// Gets an optional vector of objects, which is empty if absent
+    pub fn opt_objects(&self, key: &str) -> Result<Vec<RessaObject>, SchemaError> {
+        let items = match self.get(key) {
+            None => return Ok(vec![]),
+            Some(Value::Vec(items)) => match items.borrow_ref() {
+                Ok(items) => items.iter().cloned().collect::<Vec<_>>(),
+                Err(_) => return Err(self.mismatch(key, "a vector of objects")),
+            },
+            Some(_) => return Err(self.mismatch(key, "a vector of objects")),
+        };
+
+        items
+            .into_iter()
+            .enumerate()
+            .map(|(ndx, item)| {
+                let path = format!("{}[{}]", self.key_path(key), ndx);
+                match object_attributes(&item) {
+                    Some(attributes) => Ok(RessaObject::new(path, attributes)),
+                    None => Err(SchemaError {
+                        path,
+                        expected: "an object".into(),
+                        found: describe(Some(&item)),
+                    }),
+                }
+            })
+            .collect()
+    }
+
+    /// Reads a required vector of objects as a type
+    pub fn vec_of<T: FromRessa>(&self, key: &str) -> Result<Vec<T>, SchemaError> {
+        self.objects(key)?
+            .into_iter()
+            .map(|object| T::from_ressa(&object.named()))
+            .collect()
+    }
+
+    /// Reads an optional vector of objects as a type, which is empty if absent
+    pub fn opt_vec_of<T: FromRessa>(&self, key: &str) -> Result<Vec<T>, SchemaError> {
+        self.opt_objects(key)?
+            .into_iter()
+            .map(|object| T::from_ressa(&object.named()))
+            .collect()
+    }
+
+    /// Creates an error for an attribute that is not of the expected type
+    pub fn mismatch(&self, key: &str, expected: impl ToString) -> SchemaError {
+        SchemaError {
+            path: self.key_path(key),
+            expected: expected.to_string(),
+            found: describe(self.get(key).as_ref()),
+        }
+    }
+
+    /// Gets an attribute, looking through options
+    fn get(&self, key: &str) -> Option<Value> {
+        let mut value = self.attributes.get(key)?.clone();
+        while let Value::Option(option) = &value {
+            let inner = (*option.borrow_ref().ok()?).clone()?;
+            value = inner;
+        }
+        Some(value)
+    }
+
+    fn key_path(&self, key: &str) -> String {
+        if self.path.is_empty() {
+            key.to_string()
+        } else {
+            format!("{}.{}", self.path, key)
+        }
+    }
+}
+
+/// Gets the contents of a string value
+fn string_value(value: &Value) -> Option<String> {
+    match value {
+        Value::String(string) => string.borrow_ref().ok().map(|string| string.to_string()),
+        Value::StaticString(string) => Some(string.as_str().to_string()),
+        _ => None,
+    }
+}
+
+/// Gets the attributes of an object value
+fn object_attributes(value: &Value) -> Option<BTreeMap<String, Value>> {
+    match value {
+        Value::Object(object) => Some(
+            object
+                .borrow_ref()
+                .ok()?
+                .iter()
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect(),
+        ),
+        _ => None,
+    }
+}
+
+/// Describes a value for an error message
+fn describe(value: Option<&Value>) -> String {
+    match value {
+        None => "nothing".into(),
+        Some(Value::String(string)) => match string.borrow_ref() {
+            Ok(string) => format!("the string {:?}", &*string),
+            Err(_) => "a string".into(),
+        },
+        Some(Value::StaticString(string)) => format!("the string {:?}", string.as_str()),
+        Some(Value::Bool(b)) => format!("the bool {}", b),
+        Some(Value::Integer(i)) => format!("the integer {}", i),
+        Some(Value::Float(f)) => format!("the float {}", f),
+        Some(Value::Vec(_)) => "a vector".into(),
+        Some(Value::Object(_)) => "an object".into(),
+        Some(other) => match other.type_info() {
+            Ok(info) => format!("a value of type {}", info),
+            Err(_) => "an unknown value".into(),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fixtures::{object, string, vec},
+        MicroserviceGraph,
+    };
+    use source_code_parser::ressa::RessaResult;
+
+    #[test]
+    fn names_the_offending_service_and_key() {
+        let call = object(vec![("name", string("orders")), ("type", string("HTTP"))]);
+        let service = object(vec![
+            ("name", string("users")),
+            ("language", string("java")),
+            ("calls", vec(vec![call])),
+        ]);
+        let mut result = RessaResult::default();
+        result.insert(
+            "ctx".to_string(),
+            BTreeMap::from([("services".to_string(), vec(vec![service]))]),
+        );
+
+        let errors = MicroserviceGraph::try_new(&result).unwrap_err();
+        assert_eq!(
+            vec![SchemaError {
+                path: r#"ctx.services[0] ("users").calls[0].method"#.into(),
+                expected: "an HTTP method".into(),
+                found: "nothing".into(),
+            }],
+            errors.0
+        );
+    }
+
+    /// A struct using every `#[ressa(...)]` attribute
+    #[derive(Debug, PartialEq, FromRessa)]
+    struct Derived {
+        name: String,
+        #[ressa(rename = "type")]
+        ty: String,
+        #[ressa(default)]
+        aliases: Vec<String>,
+        #[ressa(from = "String")]
+        language: source_code_parser::Language,
+        #[ressa(with = "attribute_count")]
+        attributes: usize,
+        #[ressa(skip)]
+        skipped: bool,
+    }
+
+    fn attribute_count(object: &RessaObject) -> Result<usize, SchemaError> {
+        Ok(object.attributes().len())
+    }
+
+    #[test]
+    fn derives_every_attribute() {
+        let attributes = BTreeMap::from([
+            ("name".to_string(), string("orders")),
+            ("type".to_string(), string("service")),
+            ("language".to_string(), string("java")),
+            ("skipped".to_string(), Value::Bool(true)),
+        ]);
+        let derived = Derived::from_ressa(&RessaObject::new("ctx", attributes)).unwrap();
+        assert_eq!(
+            Derived {
+                name: "orders".into(),
+                ty: "service".into(),
+                aliases: vec![],
+                language: source_code_parser::Language::Java,
+                attributes: 4,
+                skipped: false,
+            },
+            derived
+        );
+
+        let missing = RessaObject::new("ctx", BTreeMap::from([("name".into(), string("orders"))]));
+        assert_eq!("ctx.type", Derived::from_ressa(&missing).unwrap_err().path);
+    }
+}
@@ -0,0 +1,267 @@
+//! Detection of common microservice architecture smells
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{ArchitectureMetrics, EntityGraph, MicroserviceGraph, ServiceNode};
+
+/// A kind of architecture smell
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SmellKind {
+    /// Services that call each other in a cycle
+    CyclicDependency,
+    /// An entity referenced by several services
+    SharedPersistence,
+    /// A service that many services call and that calls many services
+    HubLikeService,
+    /// Two services with many calls between them
+    ChattyPair,
+    /// A service too small to be worth its own deployment
+    NanoService,
+    /// A service too large to be one microservice
+    MegaService,
+    /// A service no other service calls
+    NoInboundCalls,
+}
+
+/// How much a smell is likely to hurt
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    Low,
+    Medium,
+    High,
+}
+
+/// A smell found in a system
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Smell {
+    pub kind: SmellKind,
+    pub severity: Severity,
+    /// The services involved
+    pub services: Vec<String>,
+    /// The entities involved, if any
+    pub entities: Vec<String>,
+    pub description: String,
+}
+
+/// The thresholds the smells are detected at
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SmellThresholds {
+    /// The distinct callers and callees a service needs, each, to be hub-like
+    pub hub_coupling: usize,
+    /// The calls two services need between them to be chatty
+    pub chatty_calls: usize,
+    /// The most endpoints a nano service has
+    pub nano_endpoints: usize,
+    /// The most entities a nano service has
+    pub nano_entities: usize,
+    /// The endpoints that make a service a mega service
+    pub mega_endpoints: usize,
+    /// The entities that make a service a mega service
+    pub mega_entities: usize,
+}
+
+impl Default for SmellThresholds {
+    fn default() -> Self {
+        SmellThresholds {
+            hub_coupling: 4,
+            chatty_calls: 5,
+            nano_endpoints: 1,
+            nano_entities: 0,
+            mega_endpoints: 30,
+            mega_entities: 15,
+        }
+    }
+}
+
+/// Detects the smells in a system, most severe first
+pub fn detect_smells(
+    ms_graph: &MicroserviceGraph,
+    entity_graph: &EntityGraph,
+    thresholds: &SmellThresholds,
+) -> Vec<Smell> {
+    let metrics = ArchitectureMetrics::new(ms_graph, entity_graph);
+    let mut smells = vec![];
+
+    for cycle in ms_graph.cycles() {
+        let mut services: Vec<_> = cycle.iter().map(|node| node.name().to_string()).collect();
+        services.sort();
+        smells.push(Smell {
+            kind: SmellKind::CyclicDependency,
+            severity: Severity::High,
+            description: format!("{} call each other in a cycle", services.join(", ")),
+            services,
+            entities: vec![],
+        });
+    }
+
+    // Find the services referencing each entity, by its name within its namespace
+    let mut referencing: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+    for ms in ms_graph.microservices() {
+        for entity in ms.ref_entities {
+            referencing
+                .entry(entity.qualified_name())
+                .or_default()
+                .insert(ms.name.clone());
+        }
+    }
+    for (entity, services) in referencing {
+        if services.len() > 1 {
+            let services: Vec<_> = services.into_iter().collect();
+            smells.push(Smell {
+                kind: SmellKind::SharedPersistence,
+                severity: if services.len() > 2 {
+                    Severity::High
+                } else {
+                    Severity::Medium
+                },
+                description: format!("{} is persisted by {}", entity, services.join(", ")),
+                services,
+                entities: vec![entity],
+            });
+        }
+    }
+
+    for service in metrics.services.iter() {
+        let name = || vec![service.service.clone()];
+        if service.afferent_coupling >= thresholds.hub_coupling
+            && service.efferent_coupling >= thresholds.hub_coupling
+        {
+            smells.push(Smell {
+                kind: SmellKind::HubLikeService,
+                severity: Severity::High,
+                services: name(),
+                entities: vec![],
+                description: format!(
+                    "{} is called by {} services and calls {}",
+                    service.service, service.afferent_coupling, service.efferent_coupling
+                ),
+            });
+        }
+        if service.endpoints <= thresholds.nano_endpoints
+            && service.entities <= thresholds.nano_entities
+        {
+            smells.push(Smell {
+                kind: SmellKind::NanoService,
+                severity: Severity::Low,
+                services: name(),
+                entities: vec![],
+                description: format!(
+                    "{} has {} endpoint(s) and {} entities",
+                    service.service, service.endpoints, service.entities
+                ),
+            });
+        }
+        let large_api = service.endpoints >= thresholds.mega_endpoints;
+        let large_model = service.entities >= thresholds.mega_entities;
+        if large_api || large_model {
+            smells.push(Smell {
+                kind: SmellKind::MegaService,
+                severity: if large_api && large_model {
+                    Severity::High
+                } else {
+                    Severity::Medium
+                },
+                services: name(),
+                entities: vec![],
+                description: format!(
+                    "{} has {} endpoints and {} entities",
+                    service.service, service.endpoints, service.entities
+                ),
+            });
+        }
+        if service.afferent_coupling == 0 {
+            smells.push(Smell {
+                kind: SmellKind::NoInboundCalls,
+                severity: Severity::Low,
+                services: name(),
+                entities: vec![],
+                description: format!("no service calls {}", service.service),
+            });
+        }
+    }
+
+    // Count the calls between each pair of internal services, either way
+    let graph = ms_graph.as_ref();
+    let mut calls: BTreeMap<(String, String), usize> = BTreeMap::new();
+    for edge in graph.edge_indices() {
+        let (from, to) = match graph.edge_endpoints(edge) {
+            Some(endpoints) => endpoints,
+            None => continue,
+        };
+        if let (ServiceNode::Internal(from), ServiceNode::Internal(to)) = (&graph[from], &graph[to])
+        {
+            if from.name == to.name {
+                continue;
+            }
+            let mut pair = [from.name.clone(), to.name.clone()];
+            pair.sort();
+            let [first, second] = pair;
+            *calls.entry((first, second)).or_default() += 1;
+        }
+    }
+    for ((first, second), count) in calls {
+        if count >= thresholds.chatty_calls {
+            smells.push(Smell {
+                kind: SmellKind::ChattyPair,
+                severity: Severity::Medium,
+                description: format!(
+                    "{} and {} make {} calls to each other",
+                    first, second, count
+                ),
+                services: vec![first, second],
+                entities: vec![],
+            });
+        }
+    }
+
+    smells.sort_by(|a, b| b.severity.cmp(&a.severity).then(a.kind.cmp(&b.kind)));
+    smells
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{fixtures::service, CallKind, MicroserviceCall};
+    use petgraph::graph::DiGraph;
+
+    #[test]
+    fn detects_smells() {
+        let mut graph = DiGraph::new();
+        let orders = graph.add_node(service("orders", &["Order", "User"]));
+        let users = graph.add_node(service("users", &["User"]));
+        let call = MicroserviceCall {
+            kind: CallKind::Rpc,
+            path: None,
+            endpoint: None,
+        };
+        for _ in 0..5 {
+            graph.add_edge(orders, users, call.clone());
+        }
+        graph.add_edge(users, orders, call);
+        let ms_graph = MicroserviceGraph(graph);
+
+        let thresholds = SmellThresholds {
+            nano_entities: 1,
+            ..Default::default()
+        };
+        let smells = detect_smells(&ms_graph, &EntityGraph::try_new(&[]).unwrap(), &thresholds);
+        let found: Vec<_> = smells
+            .iter()
+            .map(|smell| (smell.kind, smell.services.join(",")))
+            .collect();
+        assert_eq!(
+            vec![
+                (SmellKind::CyclicDependency, "orders,users".to_string()),
+                (SmellKind::SharedPersistence, "orders,users".to_string()),
+                (SmellKind::ChattyPair, "orders,users".to_string()),
+                (SmellKind::NanoService, "users".to_string()),
+            ],
+            found
+        );
+        assert_eq!(vec!["User".to_string()], smells[1].entities);
+    }
+}
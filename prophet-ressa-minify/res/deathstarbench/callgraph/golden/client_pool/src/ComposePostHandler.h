@@ -0,0 +1,4 @@
+class ComposePostHandler : public ComposePostServiceIf {
+ private:
+  ClientPool<ThriftClient<UserServiceClient>> _user_service_client_pool;
+};
@@ -0,0 +1,8 @@
+class UserHandler : public UserServiceIf {
+ public:
+  void Register(const std::string &username) {
+    mongoc_collection_t *collection =
+        mongoc_client_get_collection(_mongodb_client, "user", "user");
+    bson_t *query = BCON_NEW("username", BCON_UTF8(username.c_str()));
+  }
+};
@@ -0,0 +1,5 @@
+class UserHandler : public UserServiceIf {
+ public:
+  void Login(std::string &_return, const std::string &username) {}
+  void Register(const std::string &username) {}
+};
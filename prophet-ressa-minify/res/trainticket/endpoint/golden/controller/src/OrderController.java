@@ -0,0 +1,13 @@
+package order.controller;
+
+import org.springframework.http.HttpEntity;
+import org.springframework.web.bind.annotation.*;
+
+@RestController
+@RequestMapping("/api/v1/orderservice")
+public class OrderController {
+    @GetMapping(path = "/orders/{id}")
+    public HttpEntity getOrder(@PathVariable String id) {
+        return null;
+    }
+}
@@ -0,0 +1,9 @@
+package order.entity;
+
+import lombok.Data;
+
+@Data
+public class Order {
+    private String id;
+    private int price;
+}
@@ -0,0 +1,22 @@
+//! Converts a ReSSA definition between JSON, YAML and TOML
+//!
+//! Usage: `ressa-convert <from> <to>`, with the formats taken from the extensions
+use std::path::Path;
+
+use prophet_ressa_minify::convert_ressa;
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let (from, to) = match args.as_slice() {
+        [from, to] => (from, to),
+        _ => {
+            eprintln!("usage: ressa-convert <from.json|yaml|toml> <to.json|yaml|toml>");
+            std::process::exit(2);
+        }
+    };
+
+    if let Err(err) = convert_ressa(Path::new(from), Path::new(to)) {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,127 @@
+//! The callback of a pattern in a ReSSA definition
+use std::path::{Component, Path, PathBuf};
+
+use serde_json::Value;
+
+use crate::MinifyError;
+
+/// A pattern's callback as written in a ReSSA definition
+///
+/// A callback is either a bare string naming a script file relative to the bundle,
+/// or an object stating explicitly whether it names a file or holds the script:
+/// ```json
+/// "callback": "callback.rn"
+/// "callback": { "file": "callback.rn" }
+/// "callback": { "inline": "ctx.make_object(ctx.get_variable(\"name\").unwrap());" }
+/// ```
+/// An empty or `null` callback is the same as no callback.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Callback {
+    /// A script file, relative to the bundle directory
+    File(PathBuf),
+    /// The script itself
+    Inline(String),
+}
+
+impl Callback {
+    /// Parses a pattern's `callback` value, if it has one
+    pub fn from_json(value: Option<&Value>) -> Result<Option<Callback>, MinifyError> {
+        let callback = match value {
+            None | Some(Value::Null) => return Ok(None),
+            Some(Value::String(path)) => Callback::File(path.into()),
+            Some(Value::Object(obj)) => match (obj.get("file"), obj.get("inline")) {
+                (Some(Value::String(path)), None) => Callback::File(path.into()),
+                (None, Some(Value::String(script))) => Callback::Inline(script.clone()),
+                _ => {
+                    return Err(MinifyError::Callback(format!(
+                        "expected exactly one of a `file` or `inline` string, found {}",
+                        Value::Object(obj.clone())
+                    )))
+                }
+            },
+            Some(other) => {
+                return Err(MinifyError::Callback(format!(
+                    "expected a file name or callback object, found {}",
+                    other
+                )))
+            }
+        };
+
+        // Empty callbacks are the same as none
+        match &callback {
+            Callback::File(path) if path.as_os_str().is_empty() => Ok(None),
+            Callback::Inline(script) if script.trim().is_empty() => Ok(None),
+            _ => Ok(Some(callback)),
+        }
+    }
+
+    /// Loads the callback's script, along with the path to report errors against
+    pub fn load(&self, bundle_dir: &Path) -> Result<(PathBuf, String), MinifyError> {
+        match self {
+            Callback::File(relative) => {
+                let path = resolve_in_bundle(bundle_dir, relative)?;
+                let script = std::fs::read_to_string(&path).map_err(|err| {
+                    MinifyError::Callback(format!("could not read {:?}: {}", path, err))
+                })?;
+                Ok((path, script))
+            }
+            Callback::Inline(script) => Ok((bundle_dir.join("<inline>"), script.clone())),
+        }
+    }
+}
+
+/// Quotes a string as a Rune string literal
+pub fn rune_str(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Gets the variables a pattern string binds, such as `name` in `#{name}Handler`
+pub fn pattern_variables(pattern: &str) -> Vec<String> {
+    let mut variables = vec![];
+    let mut rest = pattern;
+    while let Some(hash) = rest.find('#') {
+        let after = &rest[hash + 1..];
+        let after = after.strip_prefix('&').unwrap_or(after);
+        rest = match after.strip_prefix('{') {
+            Some(body) => match body.find('}') {
+                Some(close) => {
+                    variables.push(body[..close].to_string());
+                    &body[close + 1..]
+                }
+                None => break,
+            },
+            None => after,
+        };
+    }
+    variables
+}
+
+/// Resolves a path relative to the bundle directory, rejecting any that escape it
+pub fn resolve_in_bundle(bundle_dir: &Path, relative: &Path) -> Result<PathBuf, MinifyError> {
+    let escapes = || {
+        MinifyError::Callback(format!(
+            "{:?} is outside the bundle directory {:?}",
+            relative, bundle_dir
+        ))
+    };
+
+    // Check the path itself, without following links
+    let mut depth = 0usize;
+    for component in relative.components() {
+        match component {
+            Component::Normal(_) => depth += 1,
+            Component::CurDir => {}
+            Component::ParentDir => depth = depth.checked_sub(1).ok_or_else(escapes)?,
+            Component::RootDir | Component::Prefix(_) => return Err(escapes()),
+        }
+    }
+
+    // Check where the path really leads, if it exists
+    let path = bundle_dir.join(relative);
+    if let (Ok(bundle), Ok(resolved)) = (bundle_dir.canonicalize(), path.canonicalize()) {
+        if !resolved.starts_with(bundle) {
+            return Err(escapes());
+        }
+    }
+    Ok(path)
+}
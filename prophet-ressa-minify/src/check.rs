@@ -0,0 +1,131 @@
+//! Compile checks for ReSSA callbacks, so that script errors surface when a bundle is
+//! loaded rather than partway through a ReSSA run
+use std::path::Path;
+
+use rune::{Diagnostic, FatalDiagnosticKind, LinkerError, Spanned};
+
+use crate::{lexer::line_col, MinifyError, SourceMap};
+
+/// Callbacks are run as the body of a function taking the parser context, as the
+/// parser wraps them
+const CALLBACK_PREFIX: &str = "pub fn main(ctx) {";
+const CALLBACK_SUFFIX: &str = "}";
+
+/// Compiles a minified callback after the bundle's library prelude, reporting any error
+/// at its position in the original script file
+pub fn check_script(
+    path: &Path,
+    original: &str,
+    minified: &str,
+    map: &SourceMap,
+    prelude: &str,
+) -> Result<(), MinifyError> {
+    let context = runestick::Context::with_default_modules()
+        .map_err(|err| MinifyError::Context(err.to_string()))?;
+
+    let mut sources = rune::Sources::new();
+    sources.insert(rune::Source::new(
+        path.display().to_string(),
+        format!(
+            "{}{}{}{}",
+            CALLBACK_PREFIX, prelude, minified, CALLBACK_SUFFIX
+        ),
+    ));
+    let mut diagnostics = rune::Diagnostics::new();
+    let result = rune::load_sources(
+        &context,
+        &rune::Options::default(),
+        &mut sources,
+        &mut diagnostics,
+    );
+    if result.is_ok() {
+        return Ok(());
+    }
+
+    // Report the first fatal diagnostic at its original position
+    let (message, offset) = diagnostics
+        .diagnostics()
+        .iter()
+        .find_map(|diagnostic| match diagnostic {
+            Diagnostic::Fatal(fatal) => {
+                Some((fatal.kind().to_string(), fatal_offset(fatal.kind())))
+            }
+            _ => None,
+        })
+        .unwrap_or_else(|| ("unknown compile error".to_string(), None));
+
+    let (line, column) = match offset {
+        Some(offset) => {
+            let offset = offset.saturating_sub(CALLBACK_PREFIX.len() + prelude.len());
+            line_col(original, map.original_offset(offset))
+        }
+        None => (1, 1),
+    };
+    Err(MinifyError::Compile {
+        file: path.display().to_string(),
+        line,
+        column,
+        message,
+    })
+}
+
+/// Gets the byte offset in the compiled source a fatal diagnostic points to
+fn fatal_offset(kind: &FatalDiagnosticKind) -> Option<usize> {
+    let span = match kind {
+        FatalDiagnosticKind::ParseError(error) => error.span(),
+        FatalDiagnosticKind::CompileError(error) => error.span(),
+        FatalDiagnosticKind::QueryError(error) => error.span(),
+        FatalDiagnosticKind::LinkError(LinkerError::MissingFunction { spans, .. }) => {
+            spans.first()?.0
+        }
+        _ => return None,
+    };
+    Some(usize::from(span.start))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::minify_script_mapped;
+    use source_code_parser::{
+        parse_project_context,
+        ressa::{run_ressa_parse, NodePattern},
+        Directory,
+    };
+
+    #[test]
+    fn reports_errors_at_their_original_position() {
+        let path = Path::new("res/broken.rn");
+        let script = "let a = 1;\n\n// a comment\nlet b = );\n";
+        let (minified, map) = minify_script_mapped(script).unwrap();
+        match check_script(path, script, &minified, &map, "fn lib() {}") {
+            Err(MinifyError::Compile {
+                file, line, column, ..
+            }) => assert_eq!(("res/broken.rn".to_string(), 4, 9), (file, line, column)),
+            other => panic!("expected a compile error, found {:?}", other),
+        }
+    }
+
+    #[test]
+    fn callbacks_run_as_the_body_of_main() {
+        // Returning early only compiles in a function body, and the callback only has an
+        // effect if the parser passes it the context as `ctx`
+        let callback = r#"let name=ctx.get_variable("name").unwrap();if name!="UserHandler"{return;}ctx.make_object(name);"#;
+        let path = Path::new("<callback>");
+        check_script(path, callback, callback, &SourceMap::default(), "").unwrap();
+
+        let dir = Path::new("res/deathstarbench/simple/golden/handler/src");
+        let files = vec![dir.join("UserHandler.h")];
+        let mut laast = parse_project_context(&Directory::new(files, vec![], dir.into())).unwrap();
+        let pattern: NodePattern = serde_json::from_value(serde_json::json!({
+            "identifier": "ClassOrInterface",
+            "pattern": "#{name}",
+            "subpatterns": [],
+            "callback": callback,
+            "essential": true
+        }))
+        .unwrap();
+        let result = run_ressa_parse(&mut laast.modules, vec![pattern]);
+        assert!(result.contains_key("UserHandler"));
+    }
+}
@@ -0,0 +1,159 @@
+//! The file formats a ReSSA definition can be written in
+use std::path::{Path, PathBuf};
+
+use serde_json::Value;
+
+use crate::MinifyError;
+
+/// The names a bundle's ReSSA definition may have, in order of preference
+pub const RESSA_FILES: &[&str] = &["ressa.json", "ressa.yaml", "ressa.yml", "ressa.toml"];
+
+/// The key TOML definitions keep their patterns under, since a TOML document must be a
+/// table rather than an array
+pub const TOML_PATTERNS_KEY: &str = "patterns";
+
+/// A format a ReSSA definition is written in
+///
+/// Every format describes the same patterns as the JSON form. Since a TOML document
+/// cannot be an array, TOML definitions list their patterns as `[[patterns]]` tables.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RessaFormat {
+    Json,
+    Yaml,
+    Toml,
+}
+
+impl RessaFormat {
+    /// Determines the format from a file's extension
+    pub fn from_path(path: &Path) -> Option<RessaFormat> {
+        match path.extension()?.to_str()? {
+            "json" => Some(RessaFormat::Json),
+            "yaml" | "yml" => Some(RessaFormat::Yaml),
+            "toml" => Some(RessaFormat::Toml),
+            _ => None,
+        }
+    }
+
+    /// Parses a definition into its JSON form
+    pub fn parse(self, source: &str) -> Result<Value, MinifyError> {
+        match self {
+            RessaFormat::Json => Ok(serde_json::from_str(source)?),
+            RessaFormat::Yaml => serde_yaml::from_str(source)
+                .map_err(|err| MinifyError::Deserialize(err.to_string())),
+            RessaFormat::Toml => {
+                let mut table: Value = toml::from_str(source)
+                    .map_err(|err| MinifyError::Deserialize(err.to_string()))?;
+                table
+                    .get_mut(TOML_PATTERNS_KEY)
+                    .map(Value::take)
+                    .ok_or_else(|| {
+                        MinifyError::Deserialize(format!(
+                            "expected a `{}` array of tables",
+                            TOML_PATTERNS_KEY
+                        ))
+                    })
+            }
+        }
+    }
+
+    /// Writes a definition given in its JSON form
+    pub fn render(self, ressa: &Value) -> Result<String, MinifyError> {
+        let serialize = |err: &dyn std::fmt::Display| MinifyError::Serialize(err.to_string());
+        match self {
+            RessaFormat::Json => serde_json::to_string_pretty(ressa).map_err(|err| serialize(&err)),
+            RessaFormat::Yaml => serde_yaml::to_string(ressa).map_err(|err| serialize(&err)),
+            RessaFormat::Toml => {
+                // TOML has no null, so absent values are left out instead
+                let mut table = serde_json::Map::new();
+                table.insert(TOML_PATTERNS_KEY.into(), strip_nulls(ressa.clone()));
+                let table =
+                    toml::Value::try_from(Value::Object(table)).map_err(|err| serialize(&err))?;
+                toml::to_string_pretty(&table).map_err(|err| serialize(&err))
+            }
+        }
+    }
+}
+
+/// Finds the ReSSA definition in a bundle directory, in whichever format it is written
+pub fn find_ressa_file(bundle_dir: &Path) -> Option<PathBuf> {
+    RESSA_FILES
+        .iter()
+        .map(|name| bundle_dir.join(name))
+        .find(|path| path.is_file())
+}
+
+/// Reads a ReSSA definition into its JSON form, using the format its extension names
+pub fn read_ressa(path: &Path) -> Result<Value, MinifyError> {
+    let format = format_of(path)?;
+    format.parse(&std::fs::read_to_string(path)?)
+}
+
+/// Converts a ReSSA definition from one format to another, by their extensions
+pub fn convert_ressa(from: &Path, to: &Path) -> Result<(), MinifyError> {
+    let ressa = read_ressa(from)?;
+    std::fs::write(to, format_of(to)?.render(&ressa)?)?;
+    Ok(())
+}
+
+fn format_of(path: &Path) -> Result<RessaFormat, MinifyError> {
+    RessaFormat::from_path(path).ok_or_else(|| {
+        MinifyError::Deserialize(format!("{:?} is not a JSON, YAML or TOML file", path))
+    })
+}
+
+/// Removes null values from objects, recursively
+fn strip_nulls(value: Value) -> Value {
+    match value {
+        Value::Array(items) => Value::Array(items.into_iter().map(strip_nulls).collect()),
+        Value::Object(obj) => Value::Object(
+            obj.into_iter()
+                .filter(|(_, value)| !value.is_null())
+                .map(|(key, value)| (key, strip_nulls(value)))
+                .collect(),
+        ),
+        value => value,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_through_every_format() {
+        let ressa = read_ressa(Path::new("res/trainticket/endpoint/ressa.json")).unwrap();
+        for format in [RessaFormat::Yaml, RessaFormat::Toml, RessaFormat::Json] {
+            let rendered = format.render(&ressa).unwrap();
+            assert_eq!(
+                strip_nulls(ressa.clone()),
+                strip_nulls(format.parse(&rendered).unwrap()),
+                "{:?} changed the definition",
+                format
+            );
+        }
+    }
+
+    #[test]
+    fn yaml_with_inline_script() {
+        let yaml = r##"
+- identifier: ClassOrInterface
+  pattern: "#{name}Handler"
+  subpatterns: []
+  essential: true
+  callback:
+    inline: |
+      let name = ctx.get_variable("name").unwrap();
+      ctx.make_object(name);
+"##;
+        let ressa = crate::minify_ressa_value(
+            RessaFormat::Yaml.parse(yaml).unwrap(),
+            Path::new("res/deathstarbench/simple"),
+            &crate::Parameters::new(),
+        )
+        .unwrap();
+        assert_eq!(
+            Some(r#"let name=ctx.get_variable("name").unwrap();ctx.make_object(name);"#.into()),
+            ressa[0].callback
+        );
+    }
+}
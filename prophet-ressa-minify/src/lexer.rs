@@ -0,0 +1,338 @@
+//! A minimal Rune lexer, just precise enough to minify scripts without changing them
+use crate::MinifyError;
+
+/// Multi-character operators, longest first so they are matched greedily
+const OPERATORS: &[&str] = &[
+    "<<=", ">>=", "...", "..=", "::", "->", "=>", "==", "!=", "<=", ">=", "&&", "||", "+=", "-=",
+    "*=", "/=", "%=", "^=", "&=", "|=", "<<", ">>", "..",
+];
+
+/// The kind of a lexed token
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum TokenKind {
+    /// An identifier or keyword
+    Ident,
+    /// A numeric literal
+    Number,
+    /// A string or byte string literal
+    Str,
+    /// A template literal, including any `${...}` expressions in it
+    Template,
+    /// A character or byte literal
+    Char,
+    /// A loop label such as `'outer`
+    Label,
+    /// An operator or delimiter
+    Punct,
+}
+
+/// A token and where it was found in the original script
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Token {
+    pub kind: TokenKind,
+    pub text: String,
+    /// The byte offset of the token in the original script
+    pub offset: usize,
+}
+
+impl Token {
+    /// Whether the token would merge with an adjacent word-like token
+    fn is_wordlike(&self) -> bool {
+        matches!(
+            self.kind,
+            TokenKind::Ident | TokenKind::Number | TokenKind::Label
+        )
+    }
+
+    /// Whether the token would merge with an adjacent operator
+    fn is_operator(&self) -> bool {
+        self.kind == TokenKind::Punct && self.text.chars().all(|c| "+-*/%=<>!&|^.:?".contains(c))
+    }
+}
+
+/// Splits a script into its tokens, dropping whitespace and comments
+pub(crate) fn tokenize(script: &str) -> Result<Vec<Token>, MinifyError> {
+    let bytes = script.as_bytes();
+    let mut tokens = vec![];
+    let mut ndx = 0;
+
+    while ndx < bytes.len() {
+        let start = ndx;
+        let c = bytes[ndx];
+        let rest = &script[ndx..];
+
+        let kind = if c.is_ascii_whitespace() {
+            ndx += 1;
+            continue;
+        } else if rest.starts_with("//") {
+            ndx += rest.find('\n').unwrap_or(rest.len());
+            continue;
+        } else if rest.starts_with("/*") {
+            ndx += block_comment_len(script, start)?;
+            continue;
+        } else if c == b'"' {
+            ndx += quoted_len(script, start, b'"')?;
+            TokenKind::Str
+        } else if c == b'b' && bytes.get(ndx + 1) == Some(&b'"') {
+            ndx += 1 + quoted_len(script, start + 1, b'"')?;
+            TokenKind::Str
+        } else if c == b'`' {
+            ndx += template_len(script, start)?;
+            TokenKind::Template
+        } else if c == b'\'' {
+            match char_len(script, start) {
+                Some(len) => {
+                    ndx += len;
+                    TokenKind::Char
+                }
+                None => {
+                    ndx += 1 + ident_len(&script[ndx + 1..]);
+                    TokenKind::Label
+                }
+            }
+        } else if c.is_ascii_digit() {
+            ndx += number_len(rest);
+            TokenKind::Number
+        } else if c == b'_' || matches!(rest.chars().next(), Some(c) if c.is_alphanumeric()) {
+            ndx += ident_len(rest);
+            TokenKind::Ident
+        } else {
+            ndx += OPERATORS
+                .iter()
+                .find(|op| rest.starts_with(**op))
+                .map(|op| op.len())
+                .unwrap_or_else(|| rest.chars().next().map(char::len_utf8).unwrap_or(1));
+            TokenKind::Punct
+        };
+
+        tokens.push(Token {
+            kind,
+            text: script[start..ndx].to_string(),
+            offset: start,
+        });
+    }
+
+    Ok(tokens)
+}
+
+/// Joins tokens back into a script, separating them only where they would otherwise
+/// merge into different tokens
+pub(crate) fn join_tokens(tokens: &[Token]) -> (String, SourceMap) {
+    let mut script = String::new();
+    let mut map = SourceMap::default();
+    let mut prev: Option<&Token> = None;
+    for token in tokens {
+        if let Some(prev) = prev {
+            let merges = (prev.is_wordlike() && token.is_wordlike())
+                || (prev.is_operator() && token.is_operator());
+            if merges {
+                script.push(' ');
+            }
+        }
+        map.push(script.len(), token.offset, token.text.len());
+        script.push_str(&token.text);
+        prev = Some(token);
+    }
+    (script, map)
+}
+
+/// Minifies a Rune script, stripping comments and whitespace but keeping literals intact
+pub fn minify_script(script: &str) -> Result<String, MinifyError> {
+    Ok(minify_script_mapped(script)?.0)
+}
+
+/// Minifies a Rune script, also returning the map from the minified to the original script
+pub fn minify_script_mapped(script: &str) -> Result<(String, SourceMap), MinifyError> {
+    Ok(join_tokens(&tokenize(script)?))
+}
+
+/// Maps byte offsets in a minified or substituted script back to the original script
+#[derive(Debug, Clone, Default)]
+pub struct SourceMap(Vec<(usize, usize, usize)>);
+
+impl SourceMap {
+    /// Records that `len` bytes at `offset` were copied from `original` in the original
+    pub(crate) fn push(&mut self, offset: usize, original: usize, len: usize) {
+        self.0.push((offset, original, len));
+    }
+
+    /// Maps this map's original offsets further back through the map of the script
+    /// they point into, such as from a minified script to the template it was
+    /// substituted from
+    pub fn through(&self, earlier: &SourceMap) -> SourceMap {
+        let spans = self
+            .0
+            .iter()
+            .map(|&(offset, original, len)| {
+                let start = earlier.original_offset(original);
+                let end = earlier.original_offset(original + len);
+                (offset, start, end.saturating_sub(start))
+            })
+            .collect();
+        SourceMap(spans)
+    }
+
+    /// Gets the offset in the original script of an offset in the minified script
+    pub fn original_offset(&self, offset: usize) -> usize {
+        // Find the token containing the offset, or the last one before it
+        let ndx = self
+            .0
+            .partition_point(|(minified, _, _)| *minified <= offset);
+        match ndx.checked_sub(1).map(|ndx| self.0[ndx]) {
+            Some((minified, original, len)) => original + (offset - minified).min(len),
+            None => 0,
+        }
+    }
+}
+
+/// The length of the identifier at the start of `rest`
+fn ident_len(rest: &str) -> usize {
+    rest.char_indices()
+        .find(|(_, c)| !(c.is_alphanumeric() || *c == '_'))
+        .map(|(ndx, _)| ndx)
+        .unwrap_or(rest.len())
+}
+
+/// The length of the number at the start of `rest`, including any fraction or suffix
+fn number_len(rest: &str) -> usize {
+    let bytes = rest.as_bytes();
+    let mut ndx = 0;
+    while ndx < bytes.len() {
+        let c = bytes[ndx];
+        let is_fraction = c == b'.'
+            && matches!(bytes.get(ndx + 1), Some(d) if d.is_ascii_digit())
+            && !rest[..ndx].contains('.');
+        if c.is_ascii_alphanumeric() || c == b'_' || is_fraction {
+            ndx += 1;
+        } else {
+            break;
+        }
+    }
+    ndx
+}
+
+/// The length of the literal starting with `quote` at `start`, including both quotes
+fn quoted_len(script: &str, start: usize, quote: u8) -> Result<usize, MinifyError> {
+    let bytes = script.as_bytes();
+    let mut ndx = start + 1;
+    while ndx < bytes.len() {
+        match bytes[ndx] {
+            b'\\' => ndx += 2,
+            c if c == quote => return Ok(ndx + 1 - start),
+            _ => ndx += 1,
+        }
+    }
+    Err(lex_error(script, start, "unterminated literal"))
+}
+
+/// The length of a character literal at `start`, or None if it is a label
+fn char_len(script: &str, start: usize) -> Option<usize> {
+    let rest = &script[start + 1..];
+    let mut chars = rest.char_indices();
+    let (_, first) = chars.next()?;
+    if first == '\\' {
+        return rest.get(2..)?.find('\'').map(|ndx| ndx + 4);
+    }
+    match chars.next() {
+        Some((ndx, '\'')) => Some(ndx + 2),
+        _ => None,
+    }
+}
+
+/// The length of a template literal at `start`, including any nested expressions
+fn template_len(script: &str, start: usize) -> Result<usize, MinifyError> {
+    let bytes = script.as_bytes();
+    let mut ndx = start + 1;
+    let mut depth = 0;
+    while ndx < bytes.len() {
+        match bytes[ndx] {
+            b'\\' => ndx += 2,
+            b'`' if depth == 0 => return Ok(ndx + 1 - start),
+            b'`' => ndx += template_len(script, ndx)?,
+            b'"' if depth > 0 => ndx += quoted_len(script, ndx, b'"')?,
+            b'$' if bytes.get(ndx + 1) == Some(&b'{') => {
+                depth += 1;
+                ndx += 2;
+            }
+            b'{' if depth > 0 => {
+                depth += 1;
+                ndx += 1;
+            }
+            b'}' if depth > 0 => {
+                depth -= 1;
+                ndx += 1;
+            }
+            _ => ndx += 1,
+        }
+    }
+    Err(lex_error(script, start, "unterminated template literal"))
+}
+
+/// The length of a (possibly nested) block comment at `start`
+fn block_comment_len(script: &str, start: usize) -> Result<usize, MinifyError> {
+    let mut ndx = start + 2;
+    let mut depth = 1;
+    while ndx < script.len() {
+        let rest = &script[ndx..];
+        if rest.starts_with("/*") {
+            depth += 1;
+            ndx += 2;
+        } else if rest.starts_with("*/") {
+            depth -= 1;
+            ndx += 2;
+            if depth == 0 {
+                return Ok(ndx - start);
+            }
+        } else {
+            ndx += rest.chars().next().map(char::len_utf8).unwrap_or(1);
+        }
+    }
+    Err(lex_error(script, start, "unterminated block comment"))
+}
+
+/// Gets the 1-based line and column of a byte offset in a script
+pub(crate) fn line_col(script: &str, offset: usize) -> (usize, usize) {
+    let before = &script[..offset.min(script.len())];
+    let line = before.matches('\n').count() + 1;
+    let col = before
+        .rfind('\n')
+        .map_or(before.len(), |ndx| before.len() - ndx - 1)
+        + 1;
+    (line, col)
+}
+
+fn lex_error(script: &str, offset: usize, message: &str) -> MinifyError {
+    let (line, col) = line_col(script, offset);
+    MinifyError::Lex(format!("{} at {}:{}", message, line, col))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_literals_and_strips_comments() {
+        let script = r#"let a = "two  spaces"; // a comment
+let b = `${a}  x`; /* block /* nested */ */ let c = 'c';"#;
+        assert_eq!(
+            minify_script(script).unwrap(),
+            r#"let a="two  spaces";let b=`${a}  x`;let c='c';"#
+        );
+    }
+
+    #[test]
+    fn maps_offsets_to_original() {
+        let script = "let  a = 1;\n// comment\nlet b = a;";
+        let (minified, map) = minify_script_mapped(script).unwrap();
+        assert_eq!(minified, "let a=1;let b=a;");
+        let b = minified.rfind('b').unwrap();
+        assert_eq!(line_col(script, map.original_offset(b)), (3, 5));
+    }
+
+    #[test]
+    fn separates_tokens_that_would_merge() {
+        assert_eq!(minify_script("return - -1").unwrap(), "return- -1");
+        assert_eq!(minify_script("a / / b\n").unwrap(), "a/ /b");
+        assert_eq!(minify_script("x != y").unwrap(), "x!=y");
+    }
+}
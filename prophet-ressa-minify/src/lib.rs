@@ -1,6 +1,28 @@
 //! A library for constructing a ReSSA with minified Rune script files
-use std::{fs::File, io::Read, path::Path};
+use std::path::Path;
 
+mod callback;
+pub use callback::*;
+
+mod format;
+pub use format::*;
+
+mod lexer;
+pub use lexer::{minify_script, minify_script_mapped, SourceMap};
+
+mod check;
+pub use check::*;
+
+mod manifest;
+pub use manifest::*;
+
+mod template;
+pub use template::*;
+
+mod package;
+pub use package::*;
+
+use serde_json::Value;
 use source_code_parser::ressa::NodePattern;
 use thiserror::Error;
 
@@ -12,6 +34,25 @@ pub enum MinifyError {
     Io(String),
     #[error("Could not deserialize ReSSA JSON: {0}")]
     Deserialize(String),
+    #[error("Could not serialize ReSSA: {0}")]
+    Serialize(String),
+    #[error("Invalid bundle package: {0}")]
+    Package(String),
+    #[error("Invalid parameter: {0}")]
+    Parameter(String),
+    #[error("Invalid callback: {0}")]
+    Callback(String),
+    #[error("Could not lex Rune script: {0}")]
+    Lex(String),
+    #[error("Could not build the Rune context to check scripts in: {0}")]
+    Context(String),
+    #[error("Could not compile {file}:{line}:{column}: {message}")]
+    Compile {
+        file: String,
+        line: usize,
+        column: usize,
+        message: String,
+    },
 }
 
 impl From<std::io::Error> for MinifyError {
@@ -26,43 +67,110 @@ impl From<serde_json::Error> for MinifyError {
     }
 }
 
-/// Creates a minified ReSSA from the provided ReSSA path
+/// Creates a minified ReSSA from the ReSSA definition in a bundle directory, overriding
+/// the defaults of the given bundle parameters
+pub fn try_minify_bundle<P: AsRef<Path>>(
+    bundle_dir: P,
+    overrides: &Parameters,
+) -> Result<Vec<NodePattern>, MinifyError> {
+    let path = find_ressa_file(bundle_dir.as_ref()).ok_or_else(|| {
+        MinifyError::Io(format!(
+            "no ReSSA definition ({}) in {:?}",
+            RESSA_FILES.join(", "),
+            bundle_dir.as_ref()
+        ))
+    })?;
+    try_minify_ressa_with(path, overrides)
+}
+
+/// Creates a minified ReSSA from the provided ReSSA path, which may be JSON, YAML or TOML
 pub fn try_minify_ressa<P: AsRef<Path>>(path: P) -> Result<Vec<NodePattern>, MinifyError> {
-    // Deserialize
-    let ressa_file: File = File::open(path.as_ref())?;
+    try_minify_ressa_with(path, &Parameters::new())
+}
 
-    let mut ressa: Vec<NodePattern> = serde_json::from_reader(ressa_file)?;
+/// Creates a minified ReSSA from the provided ReSSA path, overriding the defaults of the
+/// given bundle parameters
+pub fn try_minify_ressa_with<P: AsRef<Path>>(
+    path: P,
+    overrides: &Parameters,
+) -> Result<Vec<NodePattern>, MinifyError> {
+    // Deserialize
+    let ressa = read_ressa(path.as_ref())?;
     let mut base_path = path.as_ref().to_path_buf();
     base_path.pop();
 
+    minify_ressa_value(ressa, &base_path, overrides)
+}
+
+/// Creates a minified ReSSA from a ReSSA definition, resolving its callbacks against
+/// the bundle directory and substituting in the bundle's parameters
+pub fn minify_ressa_value(
+    mut ressa: Value,
+    bundle_dir: &Path,
+    overrides: &Parameters,
+) -> Result<Vec<NodePattern>, MinifyError> {
+    let manifest = BundleManifest::load(bundle_dir)?;
+    let params = manifest.resolve_parameters(overrides)?;
+    substitute_value(&mut ressa, &params);
+
+    // Load the shared libraries every callback is given
+    let prelude = manifest.library_prelude(bundle_dir, &params)?;
+
     // Minify and replace
-    for pat in ressa.iter_mut() {
-        minify_ressa_script(pat, &base_path)?;
+    let patterns = ressa
+        .as_array_mut()
+        .ok_or_else(|| MinifyError::Deserialize("expected an array of patterns".into()))?;
+    for pat in patterns.iter_mut() {
+        minify_ressa_script(pat, bundle_dir, &prelude, &params)?;
     }
 
-    Ok(ressa)
+    Ok(serde_json::from_value(ressa)?)
 }
 
-fn minify_ressa_script(pat: &mut NodePattern, base_path: &Path) -> Result<(), MinifyError> {
-    // Minify script if there is one
-    if let Some(callback_path) = pat.callback.as_mut() {
-        let mut path = base_path.to_path_buf();
-        path.push(&callback_path);
-        let mut script_file: File = File::open(path)?;
-        let mut script = String::new();
+fn minify_ressa_script(
+    pat: &mut Value,
+    base_path: &Path,
+    prelude: &LibraryPrelude,
+    params: &ResolvedParameters,
+) -> Result<(), MinifyError> {
+    let pat = match pat.as_object_mut() {
+        Some(pat) => pat,
+        None => return Ok(()),
+    };
 
-        script_file.read_to_string(&mut script)?;
-
-        *callback_path = script
-            .replace("    ", "")
-            .replace("  ", "")
-            .replace('\n', "")
-            .replace('\r', "");
+    // Minify script if there is one
+    match Callback::from_json(pat.get("callback"))? {
+        Some(callback) => {
+            let (path, template) = callback.load(base_path)?;
+            let (script, substitution) = substitute_mapped(&template, params, Escape::Script);
+            let (minified, map) = minify_script_mapped(&script).map_err(|err| match err {
+                MinifyError::Lex(message) => MinifyError::Lex(format!("{:?}: {}", path, message)),
+                err => err,
+            })?;
+            // Give the callback only the library functions it calls
+            let prelude = prelude.for_script(&minified)?;
+            check_script(
+                &path,
+                &template,
+                &minified,
+                &map.through(&substitution),
+                &prelude,
+            )?;
+            pat.insert(
+                "callback".into(),
+                Value::String(format!("{}{}", prelude, minified)),
+            );
+        }
+        None => {
+            pat.remove("callback");
+        }
     }
 
     // Minify subpattern scripts
-    for subpattern in pat.subpatterns.iter_mut() {
-        minify_ressa_script(subpattern, base_path)?
+    if let Some(Value::Array(subpatterns)) = pat.get_mut("subpatterns") {
+        for subpattern in subpatterns.iter_mut() {
+            minify_ressa_script(subpattern, base_path, prelude, params)?
+        }
     }
     Ok(())
 }
@@ -75,10 +183,174 @@ mod tests {
     fn minify_simple() {
         let ressa =
             try_minify_ressa("res/deathstarbench/simple/ressa.json").expect("Failed to minify");
-        let expected = r#"let endpoint = ctx.get_variable("endpoint").unwrap();let service = ctx.get_variable("service_name").unwrap();if (!endpoint.ends_with("Handler")) {ctx.make_attribute(service, endpoint, None);}"#;
+        let expected = r#"let endpoint=ctx.get_variable("endpoint").unwrap();let service=ctx.get_variable("service_name").unwrap();if(!endpoint.ends_with("Handler")){ctx.make_attribute(service,endpoint,None);}"#;
         assert_eq!(
             Some(expected.to_string()),
             ressa.get(0).unwrap().subpatterns.get(0).unwrap().callback
         );
     }
+
+    #[test]
+    fn minify_with_library() {
+        let ressa =
+            try_minify_ressa("res/deathstarbench/entity/ressa.json").expect("Failed to minify");
+        let bcon_new = find_pattern(&ressa, "BCON_NEW").expect("No BCON_NEW pattern");
+        let callback = bcon_new.callback.as_ref().unwrap();
+        assert!(callback.starts_with("fn done(ndx,tokens){"));
+        assert!(callback.ends_with("cleanup(ctx);"));
+
+        // The token callbacks call no library functions, so are given none
+        let tokens = bcon_new.subpatterns[0].callback.as_ref().unwrap();
+        assert!(!tokens.contains("fn "));
+    }
+
+    #[test]
+    fn libraries_stay_inside_the_bundle() {
+        let manifest = BundleManifest {
+            libraries: vec!["../simple/ressa.json".into()],
+            ..BundleManifest::default()
+        };
+        let prelude =
+            manifest.library_prelude(Path::new("res/deathstarbench/entity"), &Parameters::new());
+        assert!(matches!(prelude, Err(MinifyError::Callback(_))));
+    }
+
+    /// Finds the first pattern, depth first, matching the given pattern text
+    fn find_pattern<'a>(patterns: &'a [NodePattern], text: &str) -> Option<&'a NodePattern> {
+        patterns.iter().find_map(|pat| {
+            if pat.pattern == text {
+                Some(pat)
+            } else {
+                find_pattern(&pat.subpatterns, text)
+            }
+        })
+    }
+
+    #[test]
+    fn substitute_parameters() {
+        let dir = Path::new("res/trainticket/entity");
+        let ressa = try_minify_bundle(dir, &Parameters::new()).expect("Failed to minify");
+        assert_eq!("@Data", ressa[0].subpatterns[0].pattern);
+
+        let overrides = Parameters::from([("entity_annotation".into(), "Document".into())]);
+        let ressa = try_minify_bundle(dir, &overrides).expect("Failed to minify");
+        assert_eq!("@Document", ressa[0].subpatterns[0].pattern);
+
+        let unknown = Parameters::from([("entity".into(), "Document".into())]);
+        assert!(matches!(
+            try_minify_bundle(dir, &unknown),
+            Err(MinifyError::Parameter(_))
+        ));
+    }
+
+    #[test]
+    fn substitute_regex_parameters() {
+        let dir = Path::new("res/trainticket/endpoint");
+        let overrides =
+            Parameters::from([("request_mapping".into(), "RequestMapping|OrderApi".into())]);
+        let ressa = try_minify_bundle(dir, &overrides).expect("Failed to minify");
+        assert_eq!(
+            "@(?:RequestMapping|OrderApi)",
+            ressa[0].subpatterns[0].pattern
+        );
+
+        let broken = Parameters::from([("request_mapping".into(), "(Request".into())]);
+        assert!(matches!(
+            try_minify_bundle(dir, &broken),
+            Err(MinifyError::Parameter(_))
+        ));
+    }
+
+    fn pattern(callback: Value) -> Value {
+        serde_json::json!([{
+            "identifier": "ClassOrInterface",
+            "pattern": "#{name}",
+            "subpatterns": [],
+            "callback": callback,
+            "essential": true
+        }])
+    }
+
+    #[test]
+    fn minify_inline_and_empty_callbacks() {
+        let dir = Path::new("res/deathstarbench/simple");
+        let inline = serde_json::json!({ "inline": "let name = ctx.get_variable(\"name\");" });
+        let ressa =
+            minify_ressa_value(pattern(inline), dir, &Parameters::new()).expect("Failed to minify");
+        assert_eq!(
+            Some(r#"let name=ctx.get_variable("name");"#.to_string()),
+            ressa[0].callback
+        );
+
+        let ressa = minify_ressa_value(pattern("".into()), dir, &Parameters::new())
+            .expect("Failed to minify");
+        assert_eq!(None, ressa[0].callback);
+    }
+
+    #[test]
+    fn reject_escaping_callback() {
+        let dir = Path::new("res/deathstarbench/simple");
+        let escaping = serde_json::json!({ "file": "../entity/tokens.rn" });
+        assert!(matches!(
+            minify_ressa_value(pattern(escaping), dir, &Parameters::new()),
+            Err(MinifyError::Callback(_))
+        ));
+    }
+
+    /// Collects the callbacks of the patterns and their subpatterns
+    fn callbacks(patterns: &[NodePattern]) -> Vec<String> {
+        patterns
+            .iter()
+            .flat_map(|pat| {
+                let mut found = callbacks(&pat.subpatterns);
+                found.extend(pat.callback.clone());
+                found
+            })
+            .collect()
+    }
+
+    #[test]
+    fn minified_callbacks_of_every_bundle_compile() {
+        let mut compiled = 0;
+        for system in std::fs::read_dir("res").unwrap() {
+            for bundle in std::fs::read_dir(system.unwrap().path()).unwrap() {
+                let bundle = bundle.unwrap().path();
+                let ressa = try_minify_bundle(&bundle, &Parameters::new()).unwrap();
+                for callback in callbacks(&ressa) {
+                    assert!(!callback.contains('\n'), "{:?} kept a newline", bundle);
+                    // The callback already holds the bundle's libraries
+                    let path = bundle.join("<callback>");
+                    let map = SourceMap::default();
+                    if let Err(err) = check_script(&path, &callback, &callback, &map, "") {
+                        panic!("{} in\n{}", err, callback);
+                    }
+                    compiled += 1;
+                }
+            }
+        }
+        assert!(compiled > 0);
+    }
+
+    #[test]
+    fn compile_errors_point_into_the_template() {
+        let dir = Path::new("res/trainticket/entity");
+        let broken =
+            serde_json::json!({ "inline": "let a = \"{{entity_annotation}}\"; let b = );" });
+        let overrides = Parameters::from([("entity_annotation".into(), "Document".into())]);
+        match minify_ressa_value(pattern(broken), dir, &overrides) {
+            Err(MinifyError::Compile { line, column, .. }) => assert_eq!((1, 42), (line, column)),
+            other => panic!("expected a compile error, found {:?}", other),
+        }
+    }
+
+    #[test]
+    fn lex_errors_name_the_file_once() {
+        let dir = Path::new("res/deathstarbench/simple");
+        let unterminated = serde_json::json!({ "inline": "let name = \"name;" });
+        let err = minify_ressa_value(pattern(unterminated), dir, &Parameters::new()).unwrap_err();
+        assert_eq!(
+            r#"Could not lex Rune script: "res/deathstarbench/simple/<inline>": unterminated literal at 1:12"#,
+            err.to_string()
+        );
+    }
 }
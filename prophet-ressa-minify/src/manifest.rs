@@ -0,0 +1,287 @@
+//! The optional manifest describing a ReSSA bundle beyond its patterns
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs::File,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    check_script,
+    lexer::{tokenize, Token, TokenKind},
+    minify_script_mapped, resolve_in_bundle, substitute_mapped, Escape, MinifyError, Parameter,
+    ParameterKind, Parameters, ResolvedParameters,
+};
+
+/// The manifest file inside a bundle directory
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+/// A bundle's manifest
+///
+/// ```json
+/// {
+///   "libraries": ["lib/query_parser.rn"],
+///   "parameters": {
+///     "entity_annotation": "Data",
+///     "request_mapping": { "default": "RequestMapping", "kind": "regex" }
+///   }
+/// }
+/// ```
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BundleManifest {
+    /// Rune files, relative to the bundle, whose functions every callback may call
+    pub libraries: Vec<PathBuf>,
+    /// Parameters substituted for their `{{name}}` placeholders in the bundle's patterns
+    /// and scripts, with their default values
+    pub parameters: BTreeMap<String, ParameterDecl>,
+}
+
+/// A parameter a bundle declares, by its default value alone for a literal, or along
+/// with its kind
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ParameterDecl {
+    Literal(String),
+    Typed {
+        default: String,
+        #[serde(default)]
+        kind: ParameterKind,
+    },
+}
+
+impl ParameterDecl {
+    /// The value the parameter takes unless it is overridden
+    pub fn default_value(&self) -> &str {
+        match self {
+            ParameterDecl::Literal(default) | ParameterDecl::Typed { default, .. } => default,
+        }
+    }
+
+    /// How the parameter's value is read
+    pub fn kind(&self) -> ParameterKind {
+        match self {
+            ParameterDecl::Literal(_) => ParameterKind::Literal,
+            ParameterDecl::Typed { kind, .. } => *kind,
+        }
+    }
+}
+
+impl BundleManifest {
+    /// Loads the manifest in the bundle directory, or an empty one if there is none
+    pub fn load(bundle_dir: &Path) -> Result<Self, MinifyError> {
+        let path = bundle_dir.join(MANIFEST_FILE);
+        if !path.is_file() {
+            return Ok(BundleManifest::default());
+        }
+        Ok(serde_json::from_reader(File::open(path)?)?)
+    }
+
+    /// Gets the value of each of the bundle's parameters, taking it from the overrides
+    /// where given and the default otherwise
+    ///
+    /// The value of a regular expression parameter must be a valid regular expression.
+    pub fn resolve_parameters(
+        &self,
+        overrides: &Parameters,
+    ) -> Result<ResolvedParameters, MinifyError> {
+        if let Some(name) = overrides
+            .keys()
+            .find(|name| !self.parameters.contains_key(*name))
+        {
+            return Err(MinifyError::Parameter(format!(
+                "the bundle declares no parameter {:?}",
+                name
+            )));
+        }
+
+        self.parameters
+            .iter()
+            .map(|(name, decl)| {
+                let value = overrides
+                    .get(name)
+                    .map_or(decl.default_value(), String::as_str);
+                if decl.kind() == ParameterKind::Regex {
+                    regex::Regex::new(value).map_err(|err| {
+                        MinifyError::Parameter(format!(
+                            "parameter {:?} is not a regular expression: {}",
+                            name, err
+                        ))
+                    })?;
+                }
+                let param = Parameter {
+                    value: value.to_string(),
+                    kind: decl.kind(),
+                };
+                Ok((name.clone(), param))
+            })
+            .collect()
+    }
+
+    /// Minifies and checks the bundle's libraries, which must lie inside the bundle
+    /// directory, returning them as the prelude the bundle's callbacks are given
+    pub fn library_prelude(
+        &self,
+        bundle_dir: &Path,
+        params: &ResolvedParameters,
+    ) -> Result<LibraryPrelude, MinifyError> {
+        let mut prelude = LibraryPrelude::default();
+        for library in self.libraries.iter() {
+            let path = resolve_in_bundle(bundle_dir, library)?;
+            let template = std::fs::read_to_string(&path)?;
+            let (script, substitution) = substitute_mapped(&template, params, Escape::Script);
+            let (minified, map) = minify_script_mapped(&script).map_err(|err| match err {
+                MinifyError::Lex(message) => MinifyError::Lex(format!("{:?}: {}", path, message)),
+                err => err,
+            })?;
+            check_script(
+                &path,
+                &template,
+                &minified,
+                &map.through(&substitution),
+                &prelude.whole(),
+            )?;
+            prelude.push_library(&minified)?;
+        }
+        Ok(prelude)
+    }
+}
+
+/// A bundle's minified libraries, split into their top-level items
+///
+/// The parser compiles each callback on its own, so the libraries are prepended to
+/// every callback. Only the functions a callback calls, directly or through other
+/// library functions, are prepended to it, along with every item that is not a
+/// function.
+#[derive(Debug, Clone, Default)]
+pub struct LibraryPrelude {
+    items: Vec<LibraryItem>,
+}
+
+#[derive(Debug, Clone)]
+struct LibraryItem {
+    /// The name of the function the item defines, if it is one
+    function: Option<String>,
+    /// The item's minified text
+    text: String,
+    /// The identifiers the item refers to
+    references: BTreeSet<String>,
+}
+
+/// The keywords of the items a closing brace ends, rather than a semicolon
+const BLOCK_ITEMS: &[&str] = &["fn", "struct", "enum", "impl", "mod"];
+
+impl LibraryPrelude {
+    /// Every item of the libraries, in order
+    pub fn whole(&self) -> String {
+        self.items.iter().map(|item| item.text.as_str()).collect()
+    }
+
+    /// The items a minified callback needs, in the libraries' order
+    pub fn for_script(&self, script: &str) -> Result<String, MinifyError> {
+        let mut needed = identifiers(&tokenize(script)?);
+        let mut included = vec![false; self.items.len()];
+        loop {
+            let mut grew = false;
+            for (item, taken) in self.items.iter().zip(included.iter_mut()) {
+                let wanted = match &item.function {
+                    Some(name) => needed.contains(name),
+                    None => true,
+                };
+                if wanted && !*taken {
+                    *taken = true;
+                    needed.extend(item.references.iter().cloned());
+                    grew = true;
+                }
+            }
+            if !grew {
+                break;
+            }
+        }
+
+        Ok(self
+            .items
+            .iter()
+            .zip(included)
+            .filter(|(_, included)| *included)
+            .map(|(item, _)| item.text.as_str())
+            .collect())
+    }
+
+    /// Splits a minified library into its top-level items and adds them
+    fn push_library(&mut self, minified: &str) -> Result<(), MinifyError> {
+        let tokens = tokenize(minified)?;
+        let mut start = 0;
+        let mut depth = 0usize;
+        for (ndx, token) in tokens.iter().enumerate() {
+            let item = &tokens[start..=ndx];
+            let ends = match token.text.as_str() {
+                "{" => {
+                    depth += 1;
+                    false
+                }
+                "}" => {
+                    depth = depth.saturating_sub(1);
+                    depth == 0 && item_keyword(item).map_or(false, |kw| BLOCK_ITEMS.contains(&kw))
+                }
+                ";" => depth == 0,
+                _ => false,
+            };
+            if ends {
+                self.items.push(library_item(minified, item));
+                start = ndx + 1;
+            }
+        }
+        if start < tokens.len() {
+            self.items.push(library_item(minified, &tokens[start..]));
+        }
+        Ok(())
+    }
+}
+
+/// Gets the keyword an item starts with, past any visibility or `async`
+fn item_keyword(item: &[Token]) -> Option<&str> {
+    item.iter()
+        .map(|token| token.text.as_str())
+        .find(|text| !matches!(*text, "pub" | "async"))
+}
+
+fn library_item(minified: &str, item: &[Token]) -> LibraryItem {
+    let (first, last) = (&item[0], &item[item.len() - 1]);
+    let function = match item_keyword(item) {
+        Some("fn") => item
+            .iter()
+            .skip_while(|token| token.text != "fn")
+            .nth(1)
+            .map(|name| name.text.clone()),
+        _ => None,
+    };
+    LibraryItem {
+        function,
+        text: minified[first.offset..last.offset + last.text.len()].to_string(),
+        references: identifiers(item),
+    }
+}
+
+/// Gets the identifiers tokens refer to, including those in template expressions
+fn identifiers(tokens: &[Token]) -> BTreeSet<String> {
+    let mut identifiers = BTreeSet::new();
+    for token in tokens {
+        match token.kind {
+            TokenKind::Ident => {
+                identifiers.insert(token.text.clone());
+            }
+            // Any word in a template may be a call in one of its expressions
+            TokenKind::Template => identifiers.extend(
+                token
+                    .text
+                    .split(|c: char| !(c.is_alphanumeric() || c == '_'))
+                    .filter(|word| !word.is_empty())
+                    .map(str::to_string),
+            ),
+            _ => {}
+        }
+    }
+    identifiers
+}
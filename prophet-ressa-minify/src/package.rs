@@ -0,0 +1,187 @@
+//! Single-file packages of ReSSA bundles, for versioning and distributing them
+use std::{
+    collections::BTreeMap,
+    path::{Component, Path},
+};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{resolve_in_bundle, MinifyError};
+
+/// The extension of a packaged bundle
+pub const PACKAGE_EXTENSION: &str = "rpkg";
+
+/// The version of the package format written by [`pack`]
+pub const PACKAGE_VERSION: u32 = 1;
+
+/// A bundle's definition, scripts, manifest and any other files, packaged into one file
+///
+/// The hash covers every file's path and contents, so a package that was altered after
+/// being packed fails to load.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BundlePackage {
+    /// The version of the package format
+    pub version: u32,
+    /// The hex SHA-256 hash of the files
+    pub hash: String,
+    /// The contents of each file, by its `/`-separated path relative to the bundle
+    pub files: BTreeMap<String, String>,
+}
+
+impl BundlePackage {
+    /// Packages the files of a bundle directory
+    pub fn from_dir(bundle_dir: &Path) -> Result<Self, MinifyError> {
+        let mut files = BTreeMap::new();
+        collect_files(bundle_dir, bundle_dir, &mut files)?;
+        Ok(BundlePackage {
+            version: PACKAGE_VERSION,
+            hash: content_hash(&files),
+            files,
+        })
+    }
+
+    /// Reads a package file, verifying its contents against its hash
+    pub fn read(path: &Path) -> Result<Self, MinifyError> {
+        let package: BundlePackage = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+        package.verify()?;
+        Ok(package)
+    }
+
+    /// Checks the package's format version and that its files match its hash
+    pub fn verify(&self) -> Result<(), MinifyError> {
+        if self.version != PACKAGE_VERSION {
+            return Err(MinifyError::Package(format!(
+                "unsupported package version {}",
+                self.version
+            )));
+        }
+        let actual = content_hash(&self.files);
+        if actual != self.hash {
+            return Err(MinifyError::Package(format!(
+                "content hash {} does not match the recorded hash {}",
+                actual, self.hash
+            )));
+        }
+        Ok(())
+    }
+
+    /// Writes the package's files into a directory
+    pub fn write_to(&self, out_dir: &Path) -> Result<(), MinifyError> {
+        for (name, contents) in self.files.iter() {
+            let path = resolve_in_bundle(out_dir, Path::new(name)).map_err(|_| {
+                MinifyError::Package(format!("{:?} is outside the package directory", name))
+            })?;
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            std::fs::write(path, contents)?;
+        }
+        Ok(())
+    }
+}
+
+/// Packs a bundle directory into a package file, returning the package's hash
+pub fn pack(bundle_dir: &Path, package_file: &Path) -> Result<String, MinifyError> {
+    let package = BundlePackage::from_dir(bundle_dir)?;
+    let json = serde_json::to_string_pretty(&package)
+        .map_err(|err| MinifyError::Serialize(err.to_string()))?;
+    std::fs::write(package_file, json)?;
+    Ok(package.hash)
+}
+
+/// Verifies a package file and unpacks it into a directory, returning the package
+///
+/// If an expected hash is given, the package must also have that hash.
+pub fn unpack(
+    package_file: &Path,
+    out_dir: &Path,
+    expected_hash: Option<&str>,
+) -> Result<BundlePackage, MinifyError> {
+    let package = BundlePackage::read(package_file)?;
+    if let Some(expected) = expected_hash {
+        if !expected.eq_ignore_ascii_case(&package.hash) {
+            return Err(MinifyError::Package(format!(
+                "{:?} has hash {}, expected {}",
+                package_file, package.hash, expected
+            )));
+        }
+    }
+    std::fs::create_dir_all(out_dir)?;
+    package.write_to(out_dir)?;
+    Ok(package)
+}
+
+/// Hashes the paths and contents of the files, length-prefixed so that no two sets of
+/// files hash the same input
+fn content_hash(files: &BTreeMap<String, String>) -> String {
+    let mut hasher = Sha256::new();
+    for (name, contents) in files.iter() {
+        for part in [name, contents] {
+            hasher.update((part.len() as u64).to_le_bytes());
+            hasher.update(part.as_bytes());
+        }
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+/// Collects the files under `dir`, by their path relative to the bundle directory
+fn collect_files(
+    bundle_dir: &Path,
+    dir: &Path,
+    files: &mut BTreeMap<String, String>,
+) -> Result<(), MinifyError> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_files(bundle_dir, &path, files)?;
+            continue;
+        }
+
+        let relative = path.strip_prefix(bundle_dir).unwrap_or(&path);
+        let name = relative
+            .components()
+            .filter_map(|component| match component {
+                Component::Normal(part) => Some(part.to_string_lossy()),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+            .join("/");
+        let contents = std::fs::read_to_string(&path).map_err(|err| {
+            MinifyError::Package(format!("could not read {:?} as text: {}", path, err))
+        })?;
+        files.insert(name, contents);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_tampering() {
+        let mut package = BundlePackage::from_dir(Path::new("res/deathstarbench/entity")).unwrap();
+        assert!(package.files.contains_key("lib/query_parser.rn"));
+        package.verify().unwrap();
+
+        package.files.insert("ressa.json".into(), "[]".into());
+        assert!(matches!(package.verify(), Err(MinifyError::Package(_))));
+    }
+
+    #[test]
+    fn rejects_escaping_files() {
+        let mut files = BTreeMap::new();
+        files.insert("../ressa.json".to_string(), "[]".to_string());
+        let package = BundlePackage {
+            version: PACKAGE_VERSION,
+            hash: content_hash(&files),
+            files,
+        };
+        let out = std::env::temp_dir().join("prophet-ressa-escape");
+        assert!(matches!(
+            package.write_to(&out),
+            Err(MinifyError::Package(_))
+        ));
+    }
+}
@@ -0,0 +1,211 @@
+//! Substitution of a bundle's parameters into its patterns and scripts
+use std::{borrow::Cow, collections::BTreeMap};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::SourceMap;
+
+/// Parameter values by parameter name
+pub type Parameters = BTreeMap<String, String>;
+
+/// How a parameter's value is read where its placeholder appears
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ParameterKind {
+    /// Text that patterns match exactly and scripts read as a string
+    Literal,
+    /// A regular expression that patterns match as one, such as
+    /// `RequestMapping|OrderApi`, and scripts read as a string
+    Regex,
+    /// Text spliced in as it is everywhere, such as a Rune expression in a script
+    Raw,
+}
+
+impl Default for ParameterKind {
+    fn default() -> Self {
+        ParameterKind::Literal
+    }
+}
+
+/// A parameter's value, along with how it is read
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Parameter {
+    pub value: String,
+    pub kind: ParameterKind,
+}
+
+/// Parameters by parameter name, with their values resolved
+pub type ResolvedParameters = BTreeMap<String, Parameter>;
+
+/// Where a parameter's placeholder appears, which its value is escaped for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Escape {
+    /// The value is spliced in as it is
+    Verbatim,
+    /// The value is part of a pattern's regular expression, matched literally unless
+    /// it is itself a regular expression
+    Pattern,
+    /// The value is spliced into a string literal in a Rune script, unless it is raw
+    Script,
+}
+
+impl Escape {
+    fn apply(self, param: &Parameter) -> Cow<str> {
+        let value = param.value.as_str();
+        match (self, param.kind) {
+            (Escape::Verbatim, _) | (_, ParameterKind::Raw) => Cow::Borrowed(value),
+            (Escape::Pattern, ParameterKind::Literal) => Cow::Owned(regex::escape(value)),
+            // Grouped so an alternation stays within the placeholder
+            (Escape::Pattern, ParameterKind::Regex) => Cow::Owned(format!("(?:{})", value)),
+            (Escape::Script, _) => {
+                let mut escaped = String::with_capacity(value.len());
+                for c in value.chars() {
+                    match c {
+                        '\\' => escaped.push_str("\\\\"),
+                        '"' => escaped.push_str("\\\""),
+                        '\n' => escaped.push_str("\\n"),
+                        '\r' => escaped.push_str("\\r"),
+                        '\t' => escaped.push_str("\\t"),
+                        '\0' => escaped.push_str("\\0"),
+                        c => escaped.push(c),
+                    }
+                }
+                Cow::Owned(escaped)
+            }
+        }
+    }
+}
+
+/// Replaces each `{{name}}` placeholder naming a parameter with the parameter's value,
+/// escaped for where the placeholder appears
+///
+/// Placeholders that do not name a parameter are left as they are. The text is
+/// substituted in a single pass, so a value containing a placeholder is not itself
+/// substituted.
+pub fn substitute(text: &str, params: &ResolvedParameters, escape: Escape) -> String {
+    substitute_mapped(text, params, escape).0
+}
+
+/// Substitutes the parameters into the text, also returning the map from the substituted
+/// text back to the original text
+pub fn substitute_mapped(
+    text: &str,
+    params: &ResolvedParameters,
+    escape: Escape,
+) -> (String, SourceMap) {
+    let mut substituted = String::with_capacity(text.len());
+    let mut map = SourceMap::default();
+    let mut copied = 0;
+    let mut search = 0;
+    while let Some(open) = text[search..].find("{{").map(|ndx| search + ndx) {
+        let close = match text[open + 2..].find("}}") {
+            Some(ndx) => open + 2 + ndx,
+            None => break,
+        };
+        match params.get(&text[open + 2..close]) {
+            Some(param) => {
+                map.push(substituted.len(), copied, open - copied);
+                substituted.push_str(&text[copied..open]);
+                substituted.push_str(&escape.apply(param));
+                copied = close + 2;
+                search = copied;
+            }
+            // The placeholder may start at the next brace, as in `{{{name}}}`
+            None => search = open + 1,
+        }
+    }
+    map.push(substituted.len(), copied, text.len() - copied);
+    substituted.push_str(&text[copied..]);
+    (substituted, map)
+}
+
+/// Substitutes the parameters into the patterns of a ReSSA definition, and verbatim
+/// into its other strings
+///
+/// Callbacks are left as they are, as their scripts are substituted once loaded.
+pub fn substitute_value(value: &mut Value, params: &ResolvedParameters) {
+    match value {
+        Value::String(text) => *text = substitute(text, params, Escape::Verbatim),
+        Value::Array(items) => items
+            .iter_mut()
+            .for_each(|item| substitute_value(item, params)),
+        Value::Object(obj) => substitute_pattern(obj, params),
+        _ => {}
+    }
+}
+
+fn substitute_pattern(obj: &mut Map<String, Value>, params: &ResolvedParameters) {
+    for (key, value) in obj.iter_mut() {
+        match (key.as_str(), value) {
+            ("callback", _) => {}
+            ("pattern" | "auxiliary_pattern", Value::String(text)) => {
+                *text = substitute(text, params, Escape::Pattern)
+            }
+            (_, value) => substitute_value(value, params),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn param(value: &str, kind: ParameterKind) -> Parameter {
+        Parameter {
+            value: value.into(),
+            kind,
+        }
+    }
+
+    fn params() -> ResolvedParameters {
+        ResolvedParameters::from([
+            ("a".into(), param("{{b}}", ParameterKind::Literal)),
+            ("b".into(), param("x.y\"z", ParameterKind::Literal)),
+            ("mapping".into(), param("Get|Post", ParameterKind::Regex)),
+            ("expr".into(), param("1 + 2", ParameterKind::Raw)),
+        ])
+    }
+
+    #[test]
+    fn substitutes_in_one_pass() {
+        assert_eq!(
+            "{{b}} x.y\"z {{c}} {x.y\"z}",
+            substitute("{{a}} {{b}} {{c}} {{{b}}}", &params(), Escape::Verbatim)
+        );
+    }
+
+    #[test]
+    fn escapes_for_the_context() {
+        assert_eq!(
+            "@x\\.y\"z",
+            substitute("@{{b}}", &params(), Escape::Pattern)
+        );
+        assert_eq!(
+            "let s = \"x.y\\\"z\";",
+            substitute("let s = \"{{b}}\";", &params(), Escape::Script)
+        );
+    }
+
+    #[test]
+    fn escapes_by_the_kind_of_parameter() {
+        assert_eq!(
+            "@(?:Get|Post)Mapping",
+            substitute("@{{mapping}}Mapping", &params(), Escape::Pattern)
+        );
+        assert_eq!(
+            "let n = 1 + 2;",
+            substitute("let n = {{expr}};", &params(), Escape::Script)
+        );
+        assert_eq!("1 + 2", substitute("{{expr}}", &params(), Escape::Pattern));
+    }
+
+    #[test]
+    fn maps_back_to_the_template() {
+        let (substituted, map) = substitute_mapped("ab{{b}}cd", &params(), Escape::Verbatim);
+        assert_eq!("abx.y\"zcd", substituted);
+        assert_eq!(1, map.original_offset(1));
+        assert_eq!(2, map.original_offset(4));
+        assert_eq!(8, map.original_offset(8));
+    }
+}
@@ -0,0 +1,8 @@
+//! Runs a sandboxed ReSSA run, as started by `run_sandboxed`
+//!
+//! The worker reads its request from, and writes its response to, the directory the
+//! `PROPHET_RESSA_SANDBOX_WORKER` environment variable names. It is installed alongside
+//! the binaries running ReSSAs, or found through `PROPHET_RESSA_WORKER`.
+fn main() {
+    prophet_ressa::serve_sandbox_worker();
+}
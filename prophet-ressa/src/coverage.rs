@@ -0,0 +1,167 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::PathBuf,
+};
+
+use serde::Serialize;
+use source_code_parser::{ComponentInfo, Language, ModuleComponent};
+
+use crate::{BundleIndex, BundleMetrics};
+
+/// Which of a project's languages and files the available ReSSAs cover
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CoverageReport {
+    /// The detected languages, with their file counts and applied bundles
+    pub languages: Vec<LanguageCoverage>,
+    /// The parsed source files no bundle was applied to
+    pub unanalyzed_files: Vec<PathBuf>,
+    /// Entries in the ReSSA directory that were not recognized as bundles
+    pub unknown_bundles: Vec<String>,
+    /// How much of each microservice each applied bundle understood
+    pub metrics: Vec<BundleMetrics>,
+}
+
+/// The coverage of a single detected language
+#[derive(Debug, Clone, Serialize)]
+pub struct LanguageCoverage {
+    /// The detected language
+    pub language: String,
+    /// The number of source files in the language
+    pub files: usize,
+    /// The names of the bundles applied to the language
+    pub bundles: Vec<String>,
+}
+
+impl LanguageCoverage {
+    /// Whether no bundle was applied to the language
+    pub fn is_unanalyzed(&self) -> bool {
+        self.bundles.is_empty()
+    }
+}
+
+impl CoverageReport {
+    /// Creates a coverage report for the source files parsed into the LAAST modules
+    /// against the bundles in the index
+    pub fn new<'a>(
+        modules: impl IntoIterator<Item = &'a ModuleComponent>,
+        index: &BundleIndex,
+    ) -> Self {
+        let mut report = CoverageReport {
+            unknown_bundles: index.unknown.clone(),
+            ..Default::default()
+        };
+
+        // Group the parsed source files by the language the parser detected for them
+        let mut by_lang: HashMap<Language, usize> = HashMap::new();
+        for (file, lang) in parsed_files(modules) {
+            *by_lang.entry(lang).or_default() += 1;
+            if index.for_language(lang).next().is_none() {
+                report.unanalyzed_files.push(file);
+            }
+        }
+
+        for (lang, files) in by_lang {
+            let language = language_name(lang);
+            let bundles: Vec<_> = index
+                .for_language(lang)
+                .map(|bundle| bundle.name.clone())
+                .collect();
+            if bundles.is_empty() {
+                tracing::warn!("{} {} file(s) have no matching ReSSA", files, language);
+            }
+            report.languages.push(LanguageCoverage {
+                language,
+                files,
+                bundles,
+            });
+        }
+        report.languages.sort_by(|a, b| a.language.cmp(&b.language));
+
+        report
+    }
+}
+
+/// Collects the source files the LAAST modules were parsed from, each with its
+/// language, from the components declared in them
+fn parsed_files<'a>(
+    modules: impl IntoIterator<Item = &'a ModuleComponent>,
+) -> BTreeMap<PathBuf, Language> {
+    let mut files = BTreeMap::new();
+    let mut add = |info: &ComponentInfo| {
+        if info.language != Language::Unknown && !info.path.is_empty() {
+            files.insert(PathBuf::from(&info.path), info.language);
+        }
+    };
+    for module in modules {
+        module
+            .component
+            .methods
+            .iter()
+            .for_each(|method| add(&method.component));
+        for class in module.classes.iter().chain(module.interfaces.iter()) {
+            add(&class.component.component);
+            class
+                .component
+                .methods
+                .iter()
+                .for_each(|method| add(&method.component));
+        }
+    }
+    files
+}
+
+/// The name of a language, as the ReSSA directory names it
+fn language_name(lang: Language) -> String {
+    format!("{:?}", lang).to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{load_directory, Bundle};
+    use source_code_parser::{parse_project_context, Directory};
+    use std::path::Path;
+
+    #[test]
+    fn reports_languages_without_bundles() {
+        let res = Path::new(env!("CARGO_MANIFEST_DIR")).join("../prophet-ressa-minify/res");
+        let java = res.join("trainticket/entity/golden/data_class/src");
+        let cpp = res.join("deathstarbench/simple/golden/handler/src");
+        let dir = Directory::new(
+            vec![],
+            vec![
+                load_directory(&java).unwrap(),
+                load_directory(&cpp).unwrap(),
+            ],
+            PathBuf::new(),
+        );
+        let laast = parse_project_context(&dir).unwrap();
+        let index = BundleIndex {
+            bundles: vec![Bundle {
+                name: "java".into(),
+                language: Language::Java,
+                path: java,
+            }],
+            unknown: vec!["kotlin".into()],
+            ..BundleIndex::default()
+        };
+
+        let report = CoverageReport::new(&laast.modules, &index);
+        let languages: Vec<_> = report
+            .languages
+            .iter()
+            .map(|lang| (lang.language.as_str(), lang.files, lang.bundles.clone()))
+            .collect();
+        assert_eq!(
+            vec![("cpp", 1, vec![]), ("java", 1, vec!["java".to_string()])],
+            languages
+        );
+        let unanalyzed: Vec<_> = report
+            .unanalyzed_files
+            .iter()
+            .filter_map(|file| file.file_name())
+            .collect();
+        assert_eq!(vec!["UserHandler.h"], unanalyzed);
+        assert_eq!(vec!["kotlin".to_string()], report.unknown_bundles);
+    }
+}
@@ -1,52 +1,206 @@
-use prophet_ressa_minify::try_minify_ressa;
+use prophet_ressa_minify::{
+    find_ressa_file, try_minify_bundle, unpack, Parameters, PACKAGE_EXTENSION,
+};
 use source_code_parser::ressa::NodePattern;
 use source_code_parser::{ressa::Indexable, Language, ModuleComponent};
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashSet};
 use std::fs::DirEntry;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use tempfile::TempDir;
 
 use crate::Error;
 
+/// The JSON ReSSA definition file inside a bundle directory (see [`RESSA_FILES`] for
+/// the other formats a bundle may use)
+///
+/// [`RESSA_FILES`]: prophet_ressa_minify::RESSA_FILES
+pub const RESSA_FILE: &str = "ressa.json";
+
+/// Overrides of bundle parameters, by the name of the bundle declaring them
+pub type BundleParameters = BTreeMap<String, Parameters>;
+
+/// The hex SHA-256 hash each packaged bundle must have, by bundle name
+///
+/// A package only records its own hash, which proves it is intact but not that it is
+/// the package that was meant to be installed, so only pinned packages are unpacked.
+pub type PackageHashes = BTreeMap<String, String>;
+
+/// A ReSSA bundle found in the ReSSA directory
+#[derive(Debug, Clone)]
+pub struct Bundle {
+    /// The bundle's name, relative to the ReSSA directory
+    pub name: String,
+    /// The language the bundle analyzes
+    pub language: Language,
+    /// The bundle directory
+    pub path: PathBuf,
+}
+
+/// The bundles found in a ReSSA directory
+#[derive(Debug, Clone, Default)]
+pub struct BundleIndex {
+    /// The bundles for known languages
+    pub bundles: Vec<Bundle>,
+    /// Entries that do not name a known language, or are not bundle directories
+    pub unknown: Vec<String>,
+    /// The directory packaged bundles were unpacked into, removed once the index and
+    /// its clones are dropped
+    pub(crate) unpacked: Option<Arc<TempDir>>,
+}
+
+impl Bundle {
+    /// Loads the bundle's ReSSA, with any parameter overrides given for it
+    pub fn load(&self, params: &BundleParameters) -> Result<Vec<NodePattern>, Error> {
+        let overrides = params.get(&self.name).cloned().unwrap_or_default();
+        Ok(try_minify_bundle(&self.path, &overrides)?)
+    }
+}
+
+impl BundleIndex {
+    /// Gets the bundles that analyze the given language
+    pub fn for_language(&self, lang: Language) -> impl Iterator<Item = &Bundle> {
+        self.bundles
+            .iter()
+            .filter(move |bundle| bundle.language == lang)
+    }
+}
+
 /// Use the langages in the provided LAAST to determine and load the needed ReSSAs
+/// from the index, overriding their parameters as given
 pub fn extract_ressas(
     ast: &[ModuleComponent],
-    ressa_dir: &Path,
+    index: &BundleIndex,
+    params: &BundleParameters,
 ) -> Result<Vec<NodePattern>, Error> {
-    // Get subdirectories
-    let dirs = get_subdirs(ressa_dir)?;
+    for name in params.keys() {
+        if !index.bundles.iter().any(|bundle| &bundle.name == name) {
+            tracing::warn!("Parameters given for unknown bundle {:?}", name);
+        }
+    }
+
+    // Create ReSSAs for the languages in the project
+    let mut ressas = vec![];
+    for bundle in applicable_bundles(ast, index) {
+        // Verify no errors, abort if error
+        ressas.push(bundle.load(params)?);
+    }
+
+    // Flatten into one vector and return
+    Ok(ressas.into_iter().flatten().collect())
+}
 
+/// Determine the bundles in the index that apply to the languages in the LAAST
+pub fn applicable_bundles<'a>(ast: &[ModuleComponent], index: &'a BundleIndex) -> Vec<&'a Bundle> {
     // Find languages
     let langs = ast
         .iter()
         .flat_map(|module| find_languages(module as &dyn Indexable))
         .collect::<HashSet<Language>>();
 
-    // Create ReSSAs for the languages in the project
-    let mut ressas = vec![];
-    let minified_ressas = dirs
-        .into_iter()
-        .flat_map(|entry| {
-            let name = entry.file_name();
-            let name = name.to_str()?;
-            let lang = to_lang(name)?;
-            Some((name.to_string(), entry.path().is_dir(), lang))
-        })
-        .flat_map(|(name, is_dir, lang)| {
-            if langs.contains(&lang) && is_dir {
-                return Some(try_minify_ressa(name));
-            } else if !is_dir {
+    let mut bundles = vec![];
+    for lang in langs {
+        let mut lang_bundles = index.for_language(lang).peekable();
+        if lang_bundles.peek().is_none() {
+            tracing::warn!("No ReSSA found for language {:?}", lang);
+        }
+        bundles.extend(lang_bundles);
+    }
+    bundles.sort_by(|a, b| a.name.cmp(&b.name));
+    bundles
+}
+
+/// Find the bundles in the ReSSA directory
+///
+/// Each subdirectory names a language, and is either a bundle itself or contains
+/// bundles in its own subdirectories or packaged bundle files. Packages are verified
+/// against their pinned hash and unpacked into a temporary directory owned by the
+/// index, while packages without a pinned hash are skipped.
+pub fn find_bundles(ressa_dir: &Path, pinned: &PackageHashes) -> Result<BundleIndex, Error> {
+    let mut index = BundleIndex::default();
+    for entry in get_subdirs(ressa_dir)? {
+        let name = entry.file_name().to_string_lossy().to_string();
+        let path = entry.path();
+
+        let lang = match to_lang(&name) {
+            Some(lang) if path.is_dir() => lang,
+            Some(lang) => {
                 tracing::warn!("{:?} not a directory, cannot add {:?} ReSSA", name, lang);
+                index.unknown.push(name);
+                continue;
+            }
+            None => {
+                tracing::warn!("{:?} does not name a known language, skipping", name);
+                index.unknown.push(name);
+                continue;
             }
-            None
-        });
+        };
+
+        // The language directory is a bundle
+        if find_ressa_file(&path).is_some() {
+            index.bundles.push(Bundle {
+                name,
+                language: lang,
+                path,
+            });
+            continue;
+        }
 
-    // Verify no errors, abort if error
-    for entry in minified_ressas {
-        ressas.push(entry?);
+        // Otherwise collect the bundles nested in it
+        for sub_entry in get_subdirs(&path)? {
+            let sub_path = sub_entry.path();
+            let sub_name = format!("{}/{}", name, sub_entry.file_name().to_string_lossy());
+            if is_package(&sub_path) {
+                let stem = sub_path.file_stem().unwrap_or_default().to_string_lossy();
+                let bundle_name = format!("{}/{}", name, stem);
+                let expected = match pinned.get(&bundle_name) {
+                    Some(hash) => hash,
+                    None => {
+                        tracing::warn!("No hash pinned for package {:?}, skipping", bundle_name);
+                        index.unknown.push(bundle_name);
+                        continue;
+                    }
+                };
+                let unpacked = match index.unpacked.clone() {
+                    Some(dir) => dir,
+                    None => {
+                        let dir = Arc::new(TempDir::new()?);
+                        index.unpacked = Some(dir.clone());
+                        dir
+                    }
+                };
+                let path = unpacked.path().join(&bundle_name);
+                unpack_package(&sub_path, expected, &path)?;
+                index.bundles.push(Bundle {
+                    name: bundle_name,
+                    language: lang,
+                    path,
+                });
+            } else if find_ressa_file(&sub_path).is_some() {
+                index.bundles.push(Bundle {
+                    name: sub_name,
+                    language: lang,
+                    path: sub_path,
+                });
+            } else {
+                index.unknown.push(sub_name);
+            }
+        }
     }
+    Ok(index)
+}
 
-    // Flatten into one vector and return
-    Ok(ressas.into_iter().flatten().collect())
+/// Whether the path is a packaged bundle file
+fn is_package(path: &Path) -> bool {
+    path.is_file() && matches!(path.extension(), Some(ext) if ext == PACKAGE_EXTENSION)
+}
+
+/// Verify a packaged bundle against its pinned hash and unpack it into the directory
+fn unpack_package(path: &Path, expected: &str, dir: &Path) -> Result<(), Error> {
+    let package = unpack(path, dir, Some(expected))?;
+    tracing::info!("Unpacked {:?} ({}) into {:?}", path, package.hash, dir);
+    Ok(())
 }
 
 /// Retrieve the subdirectories of the directory named by the provided string
@@ -59,6 +213,7 @@ fn get_subdirs(ressa_dir: &Path) -> Result<Vec<DirEntry>, Error> {
     for dir in read_dir {
         dirs.push(dir?);
     }
+    dirs.sort_by_key(|dir| dir.file_name());
     Ok(dirs)
 }
 
@@ -75,9 +230,39 @@ fn find_languages(ast: &dyn Indexable) -> HashSet<Language> {
 
 // Convert to an enum describing the language the string describes
 /// (unknown being coerced to None)
-fn to_lang(string: &str) -> Option<Language> {
+pub(crate) fn to_lang(string: &str) -> Option<Language> {
     match string.to_string().into() {
         Language::Unknown => None,
         string => Some(string),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prophet_ressa_minify::pack;
+
+    #[test]
+    fn unpacks_only_pinned_packages_until_dropped() {
+        let ressa_dir = TempDir::new().unwrap();
+        let lang_dir = ressa_dir.path().join("cpp");
+        std::fs::create_dir(&lang_dir).unwrap();
+        let simple = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("../prophet-ressa-minify/res/deathstarbench/simple");
+        let hash = pack(&simple, &lang_dir.join("simple.rpkg")).unwrap();
+
+        let index = find_bundles(ressa_dir.path(), &PackageHashes::new()).unwrap();
+        assert!(index.bundles.is_empty());
+        assert_eq!(vec!["cpp/simple".to_string()], index.unknown);
+
+        let wrong = PackageHashes::from([("cpp/simple".into(), "0".repeat(64))]);
+        assert!(find_bundles(ressa_dir.path(), &wrong).is_err());
+
+        let pinned = PackageHashes::from([("cpp/simple".into(), hash)]);
+        let index = find_bundles(ressa_dir.path(), &pinned).unwrap();
+        let unpacked = index.bundles[0].path.clone();
+        assert!(find_ressa_file(&unpacked).is_some());
+        drop(index);
+        assert!(!unpacked.exists());
+    }
+}
@@ -0,0 +1,413 @@
+//! A golden-test harness for ReSSA bundles
+//!
+//! A bundle may ship regression cases under `<bundle>/golden/<case>/`, where each
+//! case contains a `src/` directory of fixture source files and a `ctx.json`
+//! snapshot of the `ctx` object the bundle is expected to produce for them.
+//!
+//! A bundle that records what it finds in objects of its own, rather than in `ctx`,
+//! opts those objects into the snapshot by listing their names, one per line, in an
+//! `objects` file in the case.
+use std::{
+    fmt,
+    fs::File,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use prophet_ressa_minify::{try_minify_bundle, Parameters};
+use runestick::Value;
+use serde_json::{Map, Number};
+use source_code_parser::{
+    parse_project_context,
+    ressa::{run_ressa_parse, RessaResult},
+    Directory,
+};
+
+use crate::Error;
+
+/// The directory inside a bundle holding its golden cases
+pub const GOLDEN_DIR: &str = "golden";
+/// The fixture source directory inside a golden case
+pub const FIXTURE_DIR: &str = "src";
+/// The expected context snapshot inside a golden case
+pub const SNAPSHOT_FILE: &str = "ctx.json";
+/// The list of objects besides `ctx` a golden case snapshots, if it has one
+pub const OBJECTS_FILE: &str = "objects";
+/// The result object every golden snapshot includes
+pub const CTX_OBJECT: &str = "ctx";
+/// Set this environment variable to re-bless snapshots when running golden tests
+pub const BLESS_ENV: &str = "PROPHET_RESSA_BLESS";
+
+/// Whether golden tests compare against or overwrite their snapshots
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GoldenMode {
+    /// Compare the produced context against the stored snapshot
+    Verify,
+    /// Overwrite the stored snapshot with the produced context
+    Bless,
+}
+
+impl GoldenMode {
+    /// Selects `Bless` if the `PROPHET_RESSA_BLESS` environment variable is set
+    pub fn from_env() -> Self {
+        match std::env::var_os(BLESS_ENV) {
+            Some(_) => GoldenMode::Bless,
+            None => GoldenMode::Verify,
+        }
+    }
+}
+
+/// A single difference between the expected and produced context
+#[derive(Debug, Clone, PartialEq)]
+pub enum GoldenDiff {
+    /// A key present in the snapshot was not produced
+    Missing {
+        path: String,
+        expected: serde_json::Value,
+    },
+    /// A key was produced that is not in the snapshot
+    Unexpected {
+        path: String,
+        actual: serde_json::Value,
+    },
+    /// A key was produced with a different value than in the snapshot
+    Changed {
+        path: String,
+        expected: serde_json::Value,
+        actual: serde_json::Value,
+    },
+}
+
+impl fmt::Display for GoldenDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GoldenDiff::Missing { path, expected } => write!(f, "- {}: {}", path, expected),
+            GoldenDiff::Unexpected { path, actual } => write!(f, "+ {}: {}", path, actual),
+            GoldenDiff::Changed {
+                path,
+                expected,
+                actual,
+            } => write!(f, "~ {}: {} => {}", path, expected, actual),
+        }
+    }
+}
+
+/// The outcome of running one golden case
+#[derive(Debug, Clone)]
+pub struct GoldenReport {
+    /// The name of the case directory
+    pub case: String,
+    /// The differences found (always empty when blessing)
+    pub diffs: Vec<GoldenDiff>,
+    /// Whether the snapshot was rewritten
+    pub blessed: bool,
+}
+
+impl GoldenReport {
+    /// Whether the produced context matched the snapshot
+    pub fn passed(&self) -> bool {
+        self.diffs.is_empty()
+    }
+}
+
+impl fmt::Display for GoldenReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.blessed {
+            return writeln!(f, "{}: blessed", self.case);
+        }
+        if self.passed() {
+            return writeln!(f, "{}: ok", self.case);
+        }
+        writeln!(f, "{}: {} difference(s)", self.case, self.diffs.len())?;
+        for diff in self.diffs.iter() {
+            writeln!(f, "  {}", diff)?;
+        }
+        Ok(())
+    }
+}
+
+/// Runs every golden case shipped with the bundle in `bundle_dir`, of which a bundle
+/// without a `golden/` directory has none
+pub fn run_golden_tests(bundle_dir: &Path, mode: GoldenMode) -> Result<Vec<GoldenReport>, Error> {
+    let golden_dir = bundle_dir.join(GOLDEN_DIR);
+    if !golden_dir.is_dir() {
+        return Ok(vec![]);
+    }
+
+    let mut cases = vec![];
+    for entry in std::fs::read_dir(golden_dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            cases.push(path);
+        }
+    }
+    cases.sort();
+
+    cases
+        .iter()
+        .map(|case| run_golden_case(bundle_dir, case, mode))
+        .collect()
+}
+
+/// Parses a case's fixtures, applies the bundle, and diffs or blesses the result
+pub fn run_golden_case(
+    bundle_dir: &Path,
+    case_dir: &Path,
+    mode: GoldenMode,
+) -> Result<GoldenReport, Error> {
+    let case = case_dir
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    // Parse the fixtures and apply the bundle to them
+    let dir = load_directory(&case_dir.join(FIXTURE_DIR))?;
+    let mut laast = parse_project_context(&dir)?;
+    let ressa = try_minify_bundle(bundle_dir, &Parameters::new())?;
+    let result = run_ressa_parse(&mut laast.modules, ressa);
+    let actual = ctx_to_json(&result, &snapshot_objects(case_dir)?);
+
+    let snapshot = case_dir.join(SNAPSHOT_FILE);
+    if mode == GoldenMode::Bless {
+        let mut file = File::create(&snapshot)?;
+        serde_json::to_writer_pretty(&mut file, &actual)
+            .map_err(|err| Error::Golden(err.to_string()))?;
+        writeln!(file)?;
+        return Ok(GoldenReport {
+            case,
+            diffs: vec![],
+            blessed: true,
+        });
+    }
+
+    let expected: serde_json::Value = serde_json::from_reader(File::open(&snapshot)?)
+        .map_err(|err| Error::Golden(format!("{:?}: {}", snapshot, err)))?;
+    Ok(GoldenReport {
+        case,
+        diffs: diff_json("", &expected, &actual),
+        blessed: false,
+    })
+}
+
+/// Reads the names of the objects besides `ctx` a case opts into its snapshot
+fn snapshot_objects(case_dir: &Path) -> Result<Vec<String>, Error> {
+    let path = case_dir.join(OBJECTS_FILE);
+    if !path.is_file() {
+        return Ok(vec![]);
+    }
+    Ok(std::fs::read_to_string(path)?
+        .lines()
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(str::to_string)
+        .collect())
+}
+
+/// Converts the `ctx` object of a ReSSA result, and any of the other objects named,
+/// into JSON keyed by their names
+pub fn ctx_to_json(result: &RessaResult, objects: &[String]) -> serde_json::Value {
+    let objects: Map<String, serde_json::Value> = result
+        .iter()
+        .filter(|(name, _)| name.as_str() == CTX_OBJECT || objects.contains(name))
+        .map(|(name, object)| {
+            let object = object
+                .iter()
+                .map(|(key, value)| (key.clone(), value_to_json(value)))
+                .collect();
+            (name.clone(), serde_json::Value::Object(object))
+        })
+        .collect();
+    serde_json::Value::Object(objects)
+}
+
+/// Converts a Rune value into JSON, falling back to its debug representation
+pub fn value_to_json(value: &Value) -> serde_json::Value {
+    use serde_json::Value as Json;
+    match value {
+        Value::Unit => Json::Null,
+        Value::Bool(b) => Json::Bool(*b),
+        Value::Integer(i) => Json::Number((*i).into()),
+        Value::Float(f) => Number::from_f64(*f).map(Json::Number).unwrap_or(Json::Null),
+        Value::Char(c) => Json::String(c.to_string()),
+        Value::StaticString(s) => Json::String(s.as_str().to_string()),
+        Value::String(s) => s
+            .borrow_ref()
+            .map(|s| Json::String(s.to_string()))
+            .unwrap_or(Json::Null),
+        Value::Vec(vec) => vec
+            .borrow_ref()
+            .map(|vec| Json::Array(vec.iter().map(value_to_json).collect()))
+            .unwrap_or(Json::Null),
+        Value::Object(obj) => obj
+            .borrow_ref()
+            .map(|obj| {
+                Json::Object(
+                    obj.iter()
+                        .map(|(key, value)| (key.clone(), value_to_json(value)))
+                        .collect(),
+                )
+            })
+            .unwrap_or(Json::Null),
+        Value::Option(opt) => opt
+            .borrow_ref()
+            .map(|opt| match &*opt {
+                Some(value) => value_to_json(value),
+                None => Json::Null,
+            })
+            .unwrap_or(Json::Null),
+        other => Json::String(format!("{:?}", other)),
+    }
+}
+
+/// Recursively collects the differences between two JSON values, with the paths of
+/// the keys of the top-level value unprefixed if the path given is empty
+pub fn diff_json(
+    path: &str,
+    expected: &serde_json::Value,
+    actual: &serde_json::Value,
+) -> Vec<GoldenDiff> {
+    use serde_json::Value as Json;
+    match (expected, actual) {
+        (Json::Object(expected), Json::Object(actual)) => {
+            let mut diffs = vec![];
+            let key_path = |key: &str| match path {
+                "" => key.to_string(),
+                path => format!("{}.{}", path, key),
+            };
+            for (key, expected_value) in expected.iter() {
+                let key_path = key_path(key);
+                match actual.get(key) {
+                    Some(actual_value) => {
+                        diffs.extend(diff_json(&key_path, expected_value, actual_value))
+                    }
+                    None => diffs.push(GoldenDiff::Missing {
+                        path: key_path,
+                        expected: expected_value.clone(),
+                    }),
+                }
+            }
+            for (key, actual_value) in actual.iter() {
+                if !expected.contains_key(key) {
+                    diffs.push(GoldenDiff::Unexpected {
+                        path: key_path(key),
+                        actual: actual_value.clone(),
+                    });
+                }
+            }
+            diffs
+        }
+        (Json::Array(expected_values), Json::Array(actual_values))
+            if expected_values.len() == actual_values.len() =>
+        {
+            expected_values
+                .iter()
+                .zip(actual_values.iter())
+                .enumerate()
+                .flat_map(|(ndx, (expected, actual))| {
+                    diff_json(&format!("{}[{}]", path, ndx), expected, actual)
+                })
+                .collect()
+        }
+        (expected, actual) if expected == actual => vec![],
+        (expected, actual) => vec![GoldenDiff::Changed {
+            path: path.to_string(),
+            expected: expected.clone(),
+            actual: actual.clone(),
+        }],
+    }
+}
+
+/// Recursively loads a fixture directory into its Directory representation
+pub fn load_directory(path: &Path) -> Result<Directory, Error> {
+    let mut files: Vec<PathBuf> = vec![];
+    let mut sub_dirs = vec![];
+    for entry in std::fs::read_dir(path)? {
+        let entry_path = entry?.path();
+        if entry_path.is_dir() {
+            sub_dirs.push(load_directory(&entry_path)?);
+        } else {
+            files.push(entry_path);
+        }
+    }
+    Ok(Directory::new(files, sub_dirs, path.to_path_buf()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn diff_reports_missing_unexpected_and_changed() {
+        let expected = json!({ "Order": { "id": "String", "price": "double" } });
+        let actual = json!({ "Order": { "id": "long" }, "User": {} });
+        let diffs = diff_json("ctx", &expected, &actual);
+        assert_eq!(
+            diffs,
+            vec![
+                GoldenDiff::Changed {
+                    path: "ctx.Order.id".into(),
+                    expected: json!("String"),
+                    actual: json!("long"),
+                },
+                GoldenDiff::Missing {
+                    path: "ctx.Order.price".into(),
+                    expected: json!("double"),
+                },
+                GoldenDiff::Unexpected {
+                    path: "ctx.User".into(),
+                    actual: json!({}),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn bundles_match_their_snapshots() {
+        let res = Path::new(env!("CARGO_MANIFEST_DIR")).join("../prophet-ressa-minify/res");
+        let mut ran = 0;
+        for system in std::fs::read_dir(res).unwrap() {
+            for bundle in std::fs::read_dir(system.unwrap().path()).unwrap() {
+                let bundle = bundle.unwrap().path();
+                for report in run_golden_tests(&bundle, GoldenMode::from_env()).unwrap() {
+                    assert!(report.passed(), "{:?} {}", bundle, report);
+                    ran += 1;
+                }
+            }
+        }
+        assert!(ran > 0);
+    }
+
+    #[test]
+    fn snapshots_ctx_and_the_objects_opted_into() {
+        let object = |key: &str| BTreeMap::from([(key.to_string(), Value::Bool(true))]);
+        let mut result = RessaResult::default();
+        result.insert("ctx".into(), object("services"));
+        result.insert("User".into(), object("Login"));
+        result.insert("Order".into(), object("id"));
+
+        assert_eq!(
+            json!({ "ctx": { "services": true } }),
+            ctx_to_json(&result, &[])
+        );
+        assert_eq!(
+            json!({ "ctx": { "services": true }, "User": { "Login": true } }),
+            ctx_to_json(&result, &["User".to_string()])
+        );
+    }
+
+    #[test]
+    fn missing_golden_directory_has_no_cases() {
+        let bundle = Path::new(env!("CARGO_MANIFEST_DIR")).join("src");
+        assert!(run_golden_tests(&bundle, GoldenMode::Verify)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn diff_identical_is_empty() {
+        let ctx = json!({ "services": [{ "name": "a", "calls": [] }] });
+        assert!(diff_json("ctx", &ctx, &ctx).is_empty());
+    }
+}
@@ -1,12 +1,26 @@
 mod gen_ressa;
+pub use gen_ressa::{
+    applicable_bundles, find_bundles, Bundle, BundleIndex, BundleParameters, PackageHashes,
+    RESSA_FILE,
+};
+
+mod coverage;
+pub use coverage::*;
+
+mod metrics;
+pub use metrics::*;
 
-use std::path::Path;
+mod golden;
+pub use golden::*;
+
+mod sandbox;
+pub use sandbox::*;
+
+mod scope;
+pub use scope::*;
 
 use gen_ressa::*;
-use source_code_parser::{
-    ressa::{run_ressa_parse, RessaResult},
-    ModuleComponent,
-};
+use source_code_parser::{ressa::RessaResult, ModuleComponent};
 
 /// Errors that arise while running the ReSSA
 #[derive(Debug, Clone, thiserror::Error)]
@@ -15,6 +29,12 @@ pub enum Error {
     Io(String),
     #[error("Minify Error: {0}")]
     Minify(String),
+    #[error("Golden Test Error: {0}")]
+    Golden(String),
+    #[error("Metrics Error: {0}")]
+    Metrics(String),
+    #[error("Sandbox Error: {0}")]
+    Sandbox(String),
 }
 
 impl From<std::io::Error> for Error {
@@ -29,8 +49,14 @@ impl From<prophet_ressa_minify::MinifyError> for Error {
     }
 }
 
-/// Run ressas in the described directory against the provided LAAST
-pub fn run_ressa(ast: &mut Vec<ModuleComponent>, ressa_dir: &Path) -> Result<RessaResult, Error> {
-    let ressas = extract_ressas(ast, ressa_dir)?;
-    Ok(run_ressa_parse(ast, ressas))
+/// Run the indexed ressas against the provided LAAST, with the given
+/// parameter overrides and within the provided resource limits
+pub fn run_ressa(
+    ast: &[ModuleComponent],
+    index: &BundleIndex,
+    params: &BundleParameters,
+    limits: &SandboxLimits,
+) -> Result<(RessaResult, Vec<PatternError>), Error> {
+    let ressas = extract_ressas(ast, index, params)?;
+    Ok(run_sandboxed(ast, ressas, limits))
 }
@@ -0,0 +1,458 @@
+//! Metrics for how much of a LAAST a bundle's patterns understood
+//!
+//! Both the constructs of a LAAST and the ones a bundle matched are found by running
+//! ReSSA over it: once with a pattern matching every construct of each measured kind,
+//! and once with the bundle's patterns, each with its callback replaced by one
+//! recording the match. The bundle's own callbacks are not run.
+//!
+//! A match is identified by the text of its pattern with each variable replaced by the
+//! value it was bound to, such as `UserHandler` for `#{service}Handler`. A pattern
+//! whose text is a regular expression rather than literal text and variables cannot
+//! name what it matched, so each of its matches counts as a construct of its own.
+use std::collections::{BTreeMap, BTreeSet};
+
+use prophet_ressa_minify::{pattern_variables, rune_str};
+use runestick::Value;
+use serde::Serialize;
+use source_code_parser::{
+    ressa::{run_ressa_parse, NodePattern},
+    ModuleComponent,
+};
+
+use crate::{applicable_bundles, BundleIndex, BundleParameters, Error, RessaScope};
+
+/// The maximum number of gaps reported per bundle
+const MAX_GAPS: usize = 20;
+
+/// The object the marker callbacks record matches in, by match number
+const MATCHES_OBJECT: &str = "__matches";
+
+/// The variable the patterns finding every construct bind its name to
+const CONSTRUCT_VARIABLE: &str = "__construct";
+
+/// The kinds of constructs measured
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+pub enum ConstructKind {
+    ClassOrInterface,
+    Method,
+    Annotation,
+    CallExpr,
+}
+
+impl ConstructKind {
+    const ALL: [ConstructKind; 4] = [
+        ConstructKind::ClassOrInterface,
+        ConstructKind::Method,
+        ConstructKind::Annotation,
+        ConstructKind::CallExpr,
+    ];
+
+    /// The kind of construct a pattern's node type is, if it is measured
+    fn of(pat: &NodePattern) -> Option<Self> {
+        let identifier = format!("{:?}", pat.identifier);
+        Self::ALL
+            .into_iter()
+            .find(|kind| format!("{:?}", kind) == identifier)
+    }
+}
+
+/// The fraction of constructs matched by at least one pattern
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct MatchRatio {
+    pub matched: usize,
+    pub total: usize,
+}
+
+impl MatchRatio {
+    /// The matched fraction, or zero if there were no constructs
+    pub fn fraction(&self) -> f64 {
+        if self.total == 0 {
+            return 0.0;
+        }
+        self.matched as f64 / self.total as f64
+    }
+}
+
+/// Something the bundle did not understand
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "gap", rename_all = "snake_case")]
+pub enum CoverageGap {
+    /// A pattern that matched nothing, along with the pattern it is nested in, which
+    /// matched something
+    Pattern {
+        pattern: String,
+        within: Option<String>,
+    },
+    /// A class no pattern matched
+    Class { name: String },
+}
+
+/// The coverage of one bundle over one microservice's LAAST
+#[derive(Debug, Clone, Serialize)]
+pub struct BundleMetrics {
+    /// The microservice (repository root) measured
+    pub scope: String,
+    /// The bundle measured
+    pub bundle: String,
+    pub classes: MatchRatio,
+    pub methods: MatchRatio,
+    pub annotations: MatchRatio,
+    pub calls: MatchRatio,
+    /// The patterns that matched nothing although the patterns they are nested in did,
+    /// then the top-level patterns that matched nothing, then the classes no pattern
+    /// matched
+    pub gaps: Vec<CoverageGap>,
+}
+
+/// Measure every applicable bundle in the index, with the given parameter overrides,
+/// against a scope's LAAST
+pub fn bundle_metrics(
+    scope: &RessaScope,
+    ast: &[ModuleComponent],
+    index: &BundleIndex,
+    params: &BundleParameters,
+) -> Result<Vec<BundleMetrics>, Error> {
+    let constructs = constructs(ast)?;
+    let mut metrics = vec![];
+    for bundle in applicable_bundles(ast, index) {
+        let patterns = bundle.load(params)?;
+        metrics.push(measure(
+            ast,
+            &constructs,
+            &patterns,
+            scope.to_string(),
+            bundle.name.clone(),
+        ));
+    }
+    Ok(metrics)
+}
+
+/// The names of the constructs of each kind in a LAAST
+type Constructs = BTreeMap<ConstructKind, Vec<String>>;
+
+/// Finds every construct of the measured kinds in a LAAST
+fn constructs(ast: &[ModuleComponent]) -> Result<Constructs, Error> {
+    let variables = [CONSTRUCT_VARIABLE.to_string()];
+    let patterns = ConstructKind::ALL
+        .iter()
+        .enumerate()
+        .map(|(id, kind)| {
+            serde_json::from_value(serde_json::json!({
+                "identifier": kind,
+                "pattern": format!("#{{{}}}", CONSTRUCT_VARIABLE),
+                "subpatterns": [],
+                "callback": marker(id, &variables),
+                "essential": true
+            }))
+            .map_err(|err| Error::Metrics(err.to_string()))
+        })
+        .collect::<Result<Vec<NodePattern>, _>>()?;
+
+    let mut constructs = Constructs::new();
+    for (id, mut values) in run_marked(ast, patterns) {
+        if let (Some(kind), Some(name)) = (ConstructKind::ALL.get(id), values.pop()) {
+            constructs.entry(*kind).or_default().push(name);
+        }
+    }
+    Ok(constructs)
+}
+
+/// A bundle pattern whose matches are recorded
+struct Marked {
+    label: String,
+    kind: Option<ConstructKind>,
+    pattern: String,
+    /// The pattern it is nested in, past any transparent ones
+    parent: Option<usize>,
+}
+
+/// Measure a bundle's patterns against a LAAST and the constructs found in it
+fn measure(
+    ast: &[ModuleComponent],
+    constructs: &Constructs,
+    patterns: &[NodePattern],
+    scope: String,
+    bundle: String,
+) -> BundleMetrics {
+    let mut marked = vec![];
+    let patterns = patterns
+        .iter()
+        .cloned()
+        .map(|mut pat| {
+            mark(&mut pat, None, &mut marked);
+            pat
+        })
+        .collect();
+
+    // The number of matches of each pattern, by the name matched
+    let mut found: Vec<BTreeMap<Option<String>, usize>> = vec![BTreeMap::new(); marked.len()];
+    for (id, values) in run_marked(ast, patterns) {
+        if let Some(pat) = marked.get(id) {
+            *found[id]
+                .entry(matched_name(&pat.pattern, &values))
+                .or_default() += 1;
+        }
+    }
+
+    let ratio = |kind: ConstructKind| {
+        let names = constructs.get(&kind).map(Vec::as_slice).unwrap_or_default();
+        // The constructs of a name matched are at least those of the pattern matching
+        // the most of them
+        let mut most: BTreeMap<&Option<String>, usize> = BTreeMap::new();
+        for (pat, counts) in marked.iter().zip(found.iter()) {
+            if pat.kind == Some(kind) {
+                for (name, count) in counts {
+                    let most = most.entry(name).or_default();
+                    *most = (*most).max(*count);
+                }
+            }
+        }
+        let matched = most
+            .into_iter()
+            .map(|(name, count)| match name {
+                Some(name) => count.min(names.iter().filter(|other| *other == name).count()),
+                None => count,
+            })
+            .sum::<usize>();
+        MatchRatio {
+            matched: matched.min(names.len()),
+            total: names.len(),
+        }
+    };
+
+    // A pattern nested in one that matched nothing could not have matched anything
+    let mut gaps: Vec<CoverageGap> = marked
+        .iter()
+        .zip(found.iter())
+        .filter(|(pat, counts)| {
+            counts.is_empty() && pat.parent.map_or(true, |parent| !found[parent].is_empty())
+        })
+        .map(|(pat, _)| CoverageGap::Pattern {
+            pattern: pat.label.clone(),
+            within: pat.parent.map(|parent| marked[parent].label.clone()),
+        })
+        .collect();
+    gaps.sort_by_key(|gap| matches!(gap, CoverageGap::Pattern { within: None, .. }));
+
+    let matched_classes: BTreeSet<&String> = marked
+        .iter()
+        .zip(found.iter())
+        .filter(|(pat, _)| pat.kind == Some(ConstructKind::ClassOrInterface))
+        .flat_map(|(_, counts)| counts.keys().flatten())
+        .collect();
+    let classes: BTreeSet<&String> = constructs
+        .get(&ConstructKind::ClassOrInterface)
+        .into_iter()
+        .flatten()
+        .collect();
+    gaps.extend(
+        classes
+            .difference(&matched_classes)
+            .map(|name| CoverageGap::Class {
+                name: name.to_string(),
+            }),
+    );
+    gaps.truncate(MAX_GAPS);
+
+    BundleMetrics {
+        scope,
+        bundle,
+        classes: ratio(ConstructKind::ClassOrInterface),
+        methods: ratio(ConstructKind::Method),
+        annotations: ratio(ConstructKind::Annotation),
+        calls: ratio(ConstructKind::CallExpr),
+        gaps,
+    }
+}
+
+/// Replaces the callback of a pattern and its subpatterns with one recording their
+/// matches, leaving transparent patterns without one
+fn mark(pat: &mut NodePattern, parent: Option<usize>, marked: &mut Vec<Marked>) {
+    let id = if pat.transparent {
+        pat.callback = None;
+        parent
+    } else {
+        let id = marked.len();
+        pat.callback = Some(marker(id, &pattern_variables(&pat.pattern)));
+        marked.push(Marked {
+            label: format!("{:?} {}", pat.identifier, pat.pattern),
+            kind: ConstructKind::of(pat),
+            pattern: pat.pattern.clone(),
+            parent,
+        });
+        Some(id)
+    };
+    for sub in pat.subpatterns.iter_mut() {
+        mark(sub, id, marked);
+    }
+}
+
+/// Generates the callback recording a match of a pattern, as its id followed by the
+/// values of its variables, each on a line of its own
+fn marker(id: usize, variables: &[String]) -> String {
+    let matches = rune_str(MATCHES_OBJECT);
+    let mut script = format!(
+        "if ctx.get_object({matches}).is_none() {{ ctx.make_object({matches}); }}\n\
+         let n = `${{ctx.get_object({matches}).unwrap().len()}}`;\n\
+         let m = `{id}`;\n",
+        matches = matches,
+        id = id,
+    );
+    for var in variables {
+        script.push_str(&format!(
+            "m.push_str(\"\\n\");\n\
+             match ctx.get_variable({var}) {{ Some(v) => m.push_str(v), None => () }}\n",
+            var = rune_str(var)
+        ));
+    }
+    script.push_str(&format!("ctx.make_attribute({}, n, Some(m));\n", matches));
+    script
+}
+
+/// Runs marked patterns over a copy of a LAAST, returning the id of the pattern of each
+/// match along with the values of its variables
+fn run_marked(ast: &[ModuleComponent], patterns: Vec<NodePattern>) -> Vec<(usize, Vec<String>)> {
+    let mut result = run_ressa_parse(&mut ast.to_vec(), patterns);
+    result
+        .remove(MATCHES_OBJECT)
+        .unwrap_or_default()
+        .into_values()
+        .filter_map(|value| {
+            let record = match value {
+                Value::String(string) => string.borrow_ref().ok()?.to_string(),
+                _ => return None,
+            };
+            let mut lines = record.split('\n');
+            let id = lines.next()?.parse().ok()?;
+            Some((id, lines.map(str::to_string).collect()))
+        })
+        .collect()
+}
+
+/// The name a pattern matched: its text with each variable, and any regular expression
+/// given for it, replaced by the value bound to it, unless the rest of its text is a
+/// regular expression itself
+fn matched_name(pattern: &str, values: &[String]) -> Option<String> {
+    let mut name = String::new();
+    let mut values = values.iter();
+    let mut rest = pattern;
+    while let Some(c) = rest.chars().next() {
+        match rest.strip_prefix("#&{").or_else(|| rest.strip_prefix("#{")) {
+            Some(body) => {
+                name.push_str(values.next()?);
+                rest = &body[body.find('}')? + 1..];
+                if rest.starts_with('(') {
+                    rest = after_group(rest)?;
+                }
+            }
+            None if "\\.+*?()|[]{}^$".contains(c) => return None,
+            None => {
+                name.push(c);
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+    }
+    Some(name)
+}
+
+/// The text after the parenthesized group the text starts with
+fn after_group(text: &str) -> Option<&str> {
+    let mut depth = 0;
+    let mut escaped = false;
+    for (ndx, c) in text.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(&text[ndx + 1..]);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{load_directory, FIXTURE_DIR, GOLDEN_DIR};
+    use prophet_ressa_minify::{try_minify_bundle, Parameters};
+    use source_code_parser::parse_project_context;
+    use std::path::Path;
+
+    /// Measures a bundle against the fixtures of a golden case
+    fn measure_case(bundle: &str, case: &str) -> BundleMetrics {
+        let res = Path::new(env!("CARGO_MANIFEST_DIR")).join("../prophet-ressa-minify/res");
+        let fixtures = res.join(case).join(FIXTURE_DIR);
+        let laast = parse_project_context(&load_directory(&fixtures).unwrap()).unwrap();
+        let constructs = constructs(&laast.modules).unwrap();
+        let patterns = try_minify_bundle(&res.join(bundle), &Parameters::new()).unwrap();
+        measure(
+            &laast.modules,
+            &constructs,
+            &patterns,
+            case.into(),
+            bundle.into(),
+        )
+    }
+
+    fn ratio(ratio: MatchRatio) -> (usize, usize) {
+        (ratio.matched, ratio.total)
+    }
+
+    #[test]
+    fn names_matches_by_their_variables() {
+        let values = ["User".to_string()];
+        assert_eq!(
+            Some("UserHandler".to_string()),
+            matched_name("#{service}Handler", &values)
+        );
+        assert_eq!(
+            Some("User".to_string()),
+            matched_name("#&{service}(^([A-Z][a-z]*)+$)", &values)
+        );
+        assert_eq!(Some("@Data".to_string()), matched_name("@Data", &[]));
+        assert_eq!(None, matched_name("@Data|Entity", &[]));
+    }
+
+    #[test]
+    fn counts_matched_constructs() {
+        let metrics = measure_case(
+            "deathstarbench/simple",
+            &format!("deathstarbench/simple/{}/handler", GOLDEN_DIR),
+        );
+        assert_eq!((1, 1), ratio(metrics.classes));
+        assert_eq!((2, 2), ratio(metrics.methods));
+        assert!(metrics.gaps.is_empty());
+
+        let metrics = measure_case(
+            "trainticket/entity",
+            &format!("trainticket/entity/{}/data_class", GOLDEN_DIR),
+        );
+        assert_eq!((1, 1), ratio(metrics.classes));
+        assert_eq!((1, 1), ratio(metrics.annotations));
+    }
+
+    #[test]
+    fn reports_patterns_and_classes_matching_nothing() {
+        // An entity has no request mapping, so the controller pattern matches nothing
+        let metrics = measure_case(
+            "trainticket/endpoint",
+            &format!("trainticket/entity/{}/data_class", GOLDEN_DIR),
+        );
+        assert!(metrics.gaps.contains(&CoverageGap::Pattern {
+            pattern: "ClassOrInterface #{controller}".into(),
+            within: None,
+        }));
+        assert_eq!(
+            Some(&CoverageGap::Class {
+                name: "Order".into()
+            }),
+            metrics.gaps.last()
+        );
+    }
+}
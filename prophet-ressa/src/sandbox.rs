@@ -0,0 +1,614 @@
+//! Sandboxed execution of ReSSA callbacks
+//!
+//! Callbacks are arbitrary Rune scripts, so a ReSSA run is executed in a separate
+//! worker process, the `prophet-ressa-worker` binary, while the calling process
+//! enforces a wall-clock timeout and a memory limit, killing the worker if it exceeds
+//! either.
+//!
+//! The worker first runs each top-level pattern alone under its own instruction budget,
+//! dropping those that exhaust it, then runs the rest together under the sum of their
+//! budgets. It records which stage it is in, so a worker killed or crashed while
+//! running a pattern alone is blamed on that pattern, which is dropped before the
+//! worker is started again. Each stage gets the full timeout.
+use std::{
+    borrow::Cow,
+    collections::BTreeMap,
+    fmt,
+    fs::File,
+    io::{BufReader, BufWriter},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+use runestick::{Shared, Value};
+use serde::{Deserialize, Serialize};
+use source_code_parser::{
+    ressa::{run_ressa_parse, NodePattern, RessaResult},
+    ModuleComponent,
+};
+
+use crate::Error;
+
+/// How often the supervising process checks on the worker
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// The name of the worker binary
+pub const SANDBOX_WORKER_BIN: &str = "prophet-ressa-worker";
+
+/// The environment variable giving the path of the worker binary, which is otherwise
+/// looked for alongside the current executable
+pub const SANDBOX_WORKER_BIN_ENV: &str = "PROPHET_RESSA_WORKER";
+
+/// The environment variable that marks a process as a sandbox worker, naming the
+/// directory its request and response are exchanged in
+pub const SANDBOX_WORKER_ENV: &str = "PROPHET_RESSA_SANDBOX_WORKER";
+
+/// The file in the exchange directory holding the worker's request
+const REQUEST_FILE: &str = "request.json";
+/// The file in the exchange directory holding the worker's progress
+const PROGRESS_FILE: &str = "progress.json";
+/// The file in the exchange directory holding the worker's response
+const RESPONSE_FILE: &str = "response.json";
+
+/// The resource limits applied to a ReSSA run
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SandboxLimits {
+    /// The number of Rune instructions each top-level pattern's callbacks may execute
+    pub instruction_budget: usize,
+    /// The wall-clock time each stage of the run may take, in milliseconds
+    pub timeout_ms: u64,
+    /// The number of bytes the worker process may keep resident, only enforced where
+    /// the process's memory can be read from `/proc`
+    pub memory_limit: usize,
+}
+
+impl Default for SandboxLimits {
+    fn default() -> Self {
+        SandboxLimits {
+            instruction_budget: 50_000_000,
+            timeout_ms: 60_000,
+            memory_limit: 1024 * 1024 * 1024,
+        }
+    }
+}
+
+/// A resource limit a ReSSA run violated
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Violation {
+    /// The callbacks executed more instructions than the budget allows
+    Budget,
+    /// The run took longer than the timeout
+    Timeout,
+    /// The run used more memory than the limit
+    Memory,
+    /// A callback crashed the worker process
+    Crashed,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Violation::Budget => write!(f, "exceeded the instruction budget"),
+            Violation::Timeout => write!(f, "exceeded the timeout"),
+            Violation::Memory => write!(f, "exceeded the memory limit"),
+            Violation::Crashed => write!(f, "crashed"),
+        }
+    }
+}
+
+/// A top-level pattern that was dropped from a run for violating a limit, or the
+/// patterns that violated one only when run together
+#[derive(Debug, Clone, Serialize)]
+pub struct PatternError {
+    /// The pattern's node type and pattern string, or a count of the patterns run
+    /// together
+    pub pattern: String,
+    pub violation: Violation,
+}
+
+impl fmt::Display for PatternError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Pattern `{}` {}", self.pattern, self.violation)
+    }
+}
+
+/// Runs the patterns against the LAAST within the limits, returning the result of the
+/// patterns that stayed within them and an error for each pattern that did not
+pub fn run_sandboxed(
+    ast: &[ModuleComponent],
+    mut patterns: Vec<NodePattern>,
+    limits: &SandboxLimits,
+) -> (RessaResult, Vec<PatternError>) {
+    let mut errors = vec![];
+    // The number of leading patterns already run alone within the limits
+    let mut checked = 0;
+    loop {
+        if patterns.is_empty() {
+            return (RessaResult::default(), errors);
+        }
+        let exit = run_worker_process(ast, &patterns, checked, limits).unwrap_or_else(|err| {
+            tracing::error!("Could not run the ReSSA sandbox worker: {}", err);
+            WorkerExit::Stopped(None, Violation::Crashed)
+        });
+
+        let (over_budget, outcome) = match exit {
+            WorkerExit::Finished(response) => (response.over_budget, response.result),
+            WorkerExit::Stopped(Some(progress), violation) => match progress.alone {
+                Some(index) => {
+                    drop_patterns(
+                        &mut patterns,
+                        &progress.over_budget,
+                        Violation::Budget,
+                        &mut errors,
+                    );
+                    // Every pattern before this one ran alone within the limits
+                    checked = index - progress.over_budget.len();
+                    errors.push(drop_pattern(&mut patterns, checked, violation));
+                    continue;
+                }
+                None => (progress.over_budget, Err(violation)),
+            },
+            WorkerExit::Stopped(None, violation) => (vec![], Err(violation)),
+        };
+        drop_patterns(&mut patterns, &over_budget, Violation::Budget, &mut errors);
+        return match outcome {
+            Ok(result) => (from_transfer(result), errors),
+            Err(violation) => {
+                let error = PatternError {
+                    pattern: format!("{} patterns run together", patterns.len()),
+                    violation,
+                };
+                tracing::warn!("{}, dropping the ReSSA run", error);
+                errors.push(error);
+                (RessaResult::default(), errors)
+            }
+        };
+    }
+}
+
+/// Drops the patterns at the ascending indices for violating a limit
+fn drop_patterns(
+    patterns: &mut Vec<NodePattern>,
+    indices: &[usize],
+    violation: Violation,
+    errors: &mut Vec<PatternError>,
+) {
+    // Each pattern dropped moves the ones after it forward
+    for (dropped, index) in indices.iter().enumerate() {
+        errors.push(drop_pattern(patterns, index - dropped, violation));
+    }
+}
+
+fn drop_pattern(
+    patterns: &mut Vec<NodePattern>,
+    index: usize,
+    violation: Violation,
+) -> PatternError {
+    let culprit = patterns.remove(index);
+    let error = PatternError {
+        pattern: format!("{:?} {}", culprit.identifier, culprit.pattern),
+        violation,
+    };
+    tracing::warn!("{}, dropping it from the ReSSA run", error);
+    error
+}
+
+/// What a worker is asked to run
+#[derive(Serialize, Deserialize)]
+struct WorkerRequest<'a> {
+    ast: Cow<'a, [ModuleComponent]>,
+    patterns: Cow<'a, [NodePattern]>,
+    /// The number of leading patterns known to run alone within the limits
+    checked: usize,
+    instruction_budget: usize,
+}
+
+/// How far a worker has got, which it records before each stage of its run
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+struct Progress {
+    /// The patterns that exhausted their budget alone, by ascending index
+    over_budget: Vec<usize>,
+    /// The pattern being run alone, or `None` once the patterns run together
+    alone: Option<usize>,
+}
+
+/// What a worker responds with once it finishes
+#[derive(Serialize, Deserialize)]
+struct WorkerResponse {
+    /// The patterns that exhausted their budget alone, by ascending index
+    over_budget: Vec<usize>,
+    /// The result of the other patterns run together
+    result: Result<TransferResult, Violation>,
+}
+
+/// How a worker's run ended
+enum WorkerExit {
+    Finished(WorkerResponse),
+    /// The worker was killed for violating a limit, or crashed, after recording its
+    /// progress, if it got that far
+    Stopped(Option<Progress>, Violation),
+}
+
+/// Runs the patterns in a worker process, supervising it until it finishes or
+/// violates a limit
+fn run_worker_process(
+    ast: &[ModuleComponent],
+    patterns: &[NodePattern],
+    checked: usize,
+    limits: &SandboxLimits,
+) -> Result<WorkerExit, Error> {
+    if std::env::var_os(SANDBOX_WORKER_ENV).is_some() {
+        return Err(Error::Sandbox(
+            "A sandbox worker cannot start workers of its own".into(),
+        ));
+    }
+
+    let exchange = tempfile::tempdir()?;
+    let request = WorkerRequest {
+        ast: Cow::Borrowed(ast),
+        patterns: Cow::Borrowed(patterns),
+        checked,
+        instruction_budget: limits.instruction_budget,
+    };
+    let file = File::create(exchange.path().join(REQUEST_FILE))?;
+    serde_json::to_writer(BufWriter::new(file), &request).map_err(sandbox_error)?;
+
+    let mut worker = worker_command(exchange.path())?.spawn()?;
+    let timeout = Duration::from_millis(limits.timeout_ms);
+    let mut progress = None;
+    let mut deadline = Instant::now() + timeout;
+    let status = loop {
+        if let Some(status) = worker.try_wait()? {
+            break status;
+        }
+
+        // Each stage of the worker's run gets the full timeout
+        let current = read_progress(exchange.path());
+        if current.is_some() && current != progress {
+            progress = current;
+            deadline = Instant::now() + timeout;
+        }
+
+        let violation = match resident_memory(worker.id()) {
+            Some(used) if used > limits.memory_limit => Some(Violation::Memory),
+            _ if Instant::now() >= deadline => Some(Violation::Timeout),
+            _ => None,
+        };
+        if let Some(violation) = violation {
+            // The worker may have exited in the meantime, which is just as good
+            let _ = worker.kill();
+            worker.wait()?;
+            return Ok(WorkerExit::Stopped(progress, violation));
+        }
+        thread::sleep(POLL_INTERVAL);
+    };
+    if !status.success() {
+        // The worker may have moved on since it was last checked
+        let progress = read_progress(exchange.path()).or(progress);
+        return Ok(WorkerExit::Stopped(progress, Violation::Crashed));
+    }
+
+    let file = File::open(exchange.path().join(RESPONSE_FILE))?;
+    let response = serde_json::from_reader(BufReader::new(file)).map_err(sandbox_error)?;
+    Ok(WorkerExit::Finished(response))
+}
+
+/// Reads the progress the worker last recorded, if it has recorded any
+fn read_progress(exchange: &Path) -> Option<Progress> {
+    let progress = std::fs::read(exchange.join(PROGRESS_FILE)).ok()?;
+    serde_json::from_slice(&progress).ok()
+}
+
+/// The command starting a worker that exchanges its request and response in the directory
+fn worker_command(exchange: &Path) -> Result<Command, Error> {
+    let mut command = Command::new(worker_path()?);
+    command
+        .env(SANDBOX_WORKER_ENV, exchange)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null());
+    Ok(command)
+}
+
+/// Finds the worker binary, at the path [`SANDBOX_WORKER_BIN_ENV`] gives, or else
+/// alongside the current executable or in the directory above it, where Cargo builds
+/// binaries for test executables
+fn worker_path() -> Result<PathBuf, Error> {
+    if let Some(path) = std::env::var_os(SANDBOX_WORKER_BIN_ENV) {
+        return Ok(PathBuf::from(path));
+    }
+    let exe = std::env::current_exe()?;
+    let name = format!("{}{}", SANDBOX_WORKER_BIN, std::env::consts::EXE_SUFFIX);
+    exe.ancestors()
+        .skip(1)
+        .take(2)
+        .map(|dir| dir.join(&name))
+        .find(|path| path.is_file())
+        .ok_or_else(|| {
+            Error::Sandbox(format!(
+                "Could not find {} alongside {}, set {} to its path",
+                name,
+                exe.display(),
+                SANDBOX_WORKER_BIN_ENV
+            ))
+        })
+}
+
+/// Reads the number of bytes a process keeps resident, where `/proc` exposes it
+fn resident_memory(pid: u32) -> Option<usize> {
+    let status = std::fs::read_to_string(format!("/proc/{}/status", pid)).ok()?;
+    let kilobytes = status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmRSS:"))?
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse::<usize>()
+        .ok()?;
+    Some(kilobytes * 1024)
+}
+
+/// Runs the ReSSA a supervising process requested and exits, as the worker binary does
+///
+/// Exits with an error unless the process was started as a sandbox worker.
+pub fn serve_sandbox_worker() -> ! {
+    let exchange = match std::env::var_os(SANDBOX_WORKER_ENV) {
+        Some(exchange) => PathBuf::from(exchange),
+        None => {
+            eprintln!(
+                "{} only runs as a ReSSA sandbox worker, with {} set",
+                SANDBOX_WORKER_BIN, SANDBOX_WORKER_ENV
+            );
+            std::process::exit(2);
+        }
+    };
+    match serve_request(&exchange) {
+        Ok(()) => std::process::exit(0),
+        Err(err) => {
+            eprintln!("ReSSA sandbox worker failed: {}", err);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Runs the request in the exchange directory, each pattern alone and then those within
+/// their budget together, writing the response
+fn serve_request(exchange: &Path) -> Result<(), Error> {
+    let file = File::open(exchange.join(REQUEST_FILE))?;
+    let request: WorkerRequest =
+        serde_json::from_reader(BufReader::new(file)).map_err(sandbox_error)?;
+    let mut ast = request.ast.into_owned();
+    let patterns = request.patterns.into_owned();
+
+    let mut progress = Progress::default();
+    for (index, pattern) in patterns.iter().enumerate().skip(request.checked) {
+        progress.alone = Some(index);
+        write_progress(exchange, &progress)?;
+        let alone = run_within_budget(
+            &mut ast.clone(),
+            vec![pattern.clone()],
+            request.instruction_budget,
+        );
+        if alone.is_err() {
+            progress.over_budget.push(index);
+        }
+    }
+    progress.alone = None;
+    write_progress(exchange, &progress)?;
+
+    let together: Vec<_> = patterns
+        .into_iter()
+        .enumerate()
+        .filter(|(index, _)| !progress.over_budget.contains(index))
+        .map(|(_, pattern)| pattern)
+        .collect();
+    let result = if together.is_empty() {
+        Ok(TransferResult::default())
+    } else {
+        // Each pattern brings its own budget to the run together
+        let budget = request.instruction_budget.saturating_mul(together.len());
+        run_within_budget(&mut ast, together, budget).map(|result| to_transfer(&result))
+    };
+
+    let response = WorkerResponse {
+        over_budget: progress.over_budget,
+        result,
+    };
+    let file = File::create(exchange.join(RESPONSE_FILE))?;
+    serde_json::to_writer(BufWriter::new(file), &response).map_err(sandbox_error)
+}
+
+/// Runs the patterns, failing if their callbacks execute more instructions than the
+/// budget allows
+fn run_within_budget(
+    ast: &mut Vec<ModuleComponent>,
+    patterns: Vec<NodePattern>,
+    budget: usize,
+) -> Result<RessaResult, Violation> {
+    runestick::budget::with(budget, || {
+        let result = run_ressa_parse(ast, patterns);
+        // A callback the budget stops only fails itself rather than the run, so check
+        // whether the budget ran out
+        if !runestick::budget::acquire() {
+            return Err(Violation::Budget);
+        }
+        Ok(result)
+    })
+    .call()
+}
+
+/// Records the worker's progress, replacing the file whole so that the supervising
+/// process never reads it half written
+fn write_progress(exchange: &Path, progress: &Progress) -> Result<(), Error> {
+    let partial = exchange.join(PROGRESS_FILE).with_extension("partial");
+    std::fs::write(
+        &partial,
+        serde_json::to_vec(progress).map_err(sandbox_error)?,
+    )?;
+    std::fs::rename(partial, exchange.join(PROGRESS_FILE))?;
+    Ok(())
+}
+
+fn sandbox_error(err: serde_json::Error) -> Error {
+    Error::Sandbox(err.to_string())
+}
+
+/// A ReSSA result in a form that crosses between processes without loss
+type TransferResult = BTreeMap<String, BTreeMap<String, TransferValue>>;
+
+/// A Rune value in a form that crosses between processes without loss
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+enum TransferValue {
+    Unit,
+    Bool(bool),
+    Byte(u8),
+    Char(char),
+    Integer(i64),
+    /// The bits of the float, so that no value is lost to JSON
+    Float(u64),
+    String(String),
+    Bytes(Vec<u8>),
+    Vec(Vec<TransferValue>),
+    Tuple(Vec<TransferValue>),
+    Object(BTreeMap<String, TransferValue>),
+    Option(Option<Box<TransferValue>>),
+    Result(Result<Box<TransferValue>, Box<TransferValue>>),
+}
+
+impl TransferValue {
+    /// Converts a Rune value, unless it cannot leave the VM it was made in, such as a
+    /// function
+    fn from_value(value: &Value) -> Option<Self> {
+        let many = |values: &[Value]| values.iter().map(Self::from_value).collect();
+        Some(match value {
+            Value::Unit => TransferValue::Unit,
+            Value::Bool(b) => TransferValue::Bool(*b),
+            Value::Byte(b) => TransferValue::Byte(*b),
+            Value::Char(c) => TransferValue::Char(*c),
+            Value::Integer(i) => TransferValue::Integer(*i),
+            Value::Float(f) => TransferValue::Float(f.to_bits()),
+            Value::StaticString(s) => TransferValue::String(s.as_str().to_string()),
+            Value::String(s) => TransferValue::String(s.borrow_ref().ok()?.to_string()),
+            Value::Bytes(bytes) => TransferValue::Bytes(bytes.borrow_ref().ok()?.to_vec()),
+            Value::Vec(vec) => TransferValue::Vec(many(&vec.borrow_ref().ok()?)?),
+            Value::Tuple(tuple) => TransferValue::Tuple(many(&tuple.borrow_ref().ok()?)?),
+            Value::Object(obj) => TransferValue::Object(
+                obj.borrow_ref()
+                    .ok()?
+                    .iter()
+                    .map(|(key, value)| Some((key.clone(), Self::from_value(value)?)))
+                    .collect::<Option<_>>()?,
+            ),
+            Value::Option(opt) => TransferValue::Option(match &*opt.borrow_ref().ok()? {
+                Some(value) => Some(Box::new(Self::from_value(value)?)),
+                None => None,
+            }),
+            Value::Result(result) => TransferValue::Result(match &*result.borrow_ref().ok()? {
+                Ok(value) => Ok(Box::new(Self::from_value(value)?)),
+                Err(value) => Err(Box::new(Self::from_value(value)?)),
+            }),
+            _ => return None,
+        })
+    }
+
+    fn into_value(self) -> Value {
+        let many = |values: Vec<TransferValue>| -> Vec<Value> {
+            values.into_iter().map(TransferValue::into_value).collect()
+        };
+        match self {
+            TransferValue::Unit => Value::Unit,
+            TransferValue::Bool(b) => Value::Bool(b),
+            TransferValue::Byte(b) => Value::Byte(b),
+            TransferValue::Char(c) => Value::Char(c),
+            TransferValue::Integer(i) => Value::Integer(i),
+            TransferValue::Float(bits) => Value::Float(f64::from_bits(bits)),
+            TransferValue::String(s) => Value::String(Shared::new(s)),
+            TransferValue::Bytes(bytes) => {
+                Value::Bytes(Shared::new(runestick::Bytes::from_vec(bytes)))
+            }
+            TransferValue::Vec(values) => {
+                Value::Vec(Shared::new(runestick::Vec::from(many(values))))
+            }
+            TransferValue::Tuple(values) => {
+                Value::Tuple(Shared::new(runestick::Tuple::from(many(values))))
+            }
+            TransferValue::Object(obj) => {
+                let mut object = runestick::Object::new();
+                for (key, value) in obj {
+                    object.insert(key, value.into_value());
+                }
+                Value::Object(Shared::new(object))
+            }
+            TransferValue::Option(opt) => {
+                Value::Option(Shared::new(opt.map(|value| value.into_value())))
+            }
+            TransferValue::Result(result) => Value::Result(Shared::new(
+                result
+                    .map(|value| value.into_value())
+                    .map_err(|value| value.into_value()),
+            )),
+        }
+    }
+}
+
+/// Converts every object in a ReSSA result, dropping the attributes that cannot leave
+/// the worker
+fn to_transfer(result: &RessaResult) -> TransferResult {
+    result
+        .iter()
+        .map(|(name, objects)| {
+            let objects = objects
+                .iter()
+                .filter_map(|(key, value)| match TransferValue::from_value(value) {
+                    Some(value) => Some((key.clone(), value)),
+                    None => {
+                        tracing::warn!("Dropping {}.{}, which cannot leave the sandbox", name, key);
+                        None
+                    }
+                })
+                .collect();
+            (name.clone(), objects)
+        })
+        .collect()
+}
+
+fn from_transfer(result: TransferResult) -> RessaResult {
+    result
+        .into_iter()
+        .map(|(name, objects)| {
+            let objects = objects
+                .into_iter()
+                .map(|(key, value)| (key, value.into_value()))
+                .collect();
+            (name, objects)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transfers_values_without_loss() {
+        let mut object = runestick::Object::new();
+        object.insert("unit".into(), Value::Unit);
+        object.insert("char".into(), Value::Char('x'));
+        object.insert("nan".into(), Value::Float(f64::NAN));
+        object.insert("none".into(), Value::Option(Shared::new(None)));
+        let value = TransferValue::from_value(&Value::Object(Shared::new(object))).unwrap();
+
+        let json = serde_json::to_string(&value).unwrap();
+        let back: TransferValue = serde_json::from_str(&json).unwrap();
+        let round_trip = TransferValue::from_value(&back.into_value()).unwrap();
+        assert_eq!(json, serde_json::to_string(&round_trip).unwrap());
+        assert!(matches!(
+            &round_trip,
+            TransferValue::Object(obj) if obj["unit"] == TransferValue::Unit
+                && obj["char"] == TransferValue::Char('x')
+                && obj["none"] == TransferValue::Option(None)
+        ));
+    }
+}
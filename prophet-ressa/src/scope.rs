@@ -0,0 +1,336 @@
+//! Scoping ReSSA runs to repository root directories
+//!
+//! Each root is parsed and analyzed on its own, so that the services and entities a
+//! run finds can be traced back to the repository and root they came from, and
+//! same-named services in different roots are not confused with one another.
+//!
+//! When merging the results, the `services` and other vectors of `ctx` are
+//! concatenated, and a service a bundle leaves unnamed is named after its root. Any other object, or attribute of `ctx`, that more than one root
+//! produces is kept once per root, under its name prefixed with the root's scope, as in
+//! `https://github.com/a/a.git/users::User`.
+use std::{collections::BTreeMap, fmt};
+
+use runestick::{Shared, Value};
+use source_code_parser::{ressa::RessaResult, ModuleComponent};
+
+use crate::{run_ressa, BundleIndex, BundleParameters, Error, PatternError, SandboxLimits};
+
+pub use prophet_model::{ORIGIN_REPOSITORY, ORIGIN_ROOT};
+
+/// The repository root directory a ReSSA run is scoped to
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RessaScope {
+    /// The repository (Git URL) the root belongs to
+    pub repository: String,
+    /// The root directory, relative to the repository
+    pub root: String,
+}
+
+impl RessaScope {
+    pub fn new(repository: impl ToString, root: impl ToString) -> Self {
+        RessaScope {
+            repository: repository.to_string(),
+            root: root.to_string(),
+        }
+    }
+
+    /// The name of the service in the root: the root's last directory, or the
+    /// repository's name if the root is the repository's own
+    pub fn service_name(&self) -> String {
+        let last = |path: &str| {
+            let path = path.trim_end_matches('/');
+            path.rsplit('/').next().unwrap_or(path).to_string()
+        };
+        match last(&self.root) {
+            root if !root.is_empty() && root != "." => root,
+            _ => last(&self.repository).trim_end_matches(".git").to_string(),
+        }
+    }
+}
+
+impl fmt::Display for RessaScope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.repository, self.root)
+    }
+}
+
+/// Run the ReSSAs separately against each scope's LAAST, tag the resulting services
+/// and entities with their origin, and merge the per-scope results into one
+pub fn run_ressa_scoped(
+    scopes: Vec<(RessaScope, Vec<ModuleComponent>)>,
+    index: &BundleIndex,
+    params: &BundleParameters,
+    limits: &SandboxLimits,
+) -> Result<(RessaResult, Vec<PatternError>), Error> {
+    let mut merged = RessaResult::default();
+    let mut origins = Origins::default();
+    let mut errors = vec![];
+    for (scope, ast) in scopes {
+        let (result, scope_errors) = run_ressa(&ast, index, params, limits)?;
+        tracing::info!("Ran ReSSAs for {}", scope);
+        merge_result(&mut merged, &mut origins, result, &scope);
+        errors.extend(scope_errors);
+    }
+    Ok((merged, errors))
+}
+
+/// The object holding the services a run finds, whose vectors are merged across scopes
+const CTX_OBJECT: &str = "ctx";
+
+/// The scope each merged object, and each merged attribute of `ctx`, came from, by
+/// name, or `None` once more than one scope has produced the name
+#[derive(Default)]
+struct Origins {
+    objects: BTreeMap<String, Option<RessaScope>>,
+    ctx: BTreeMap<String, Option<RessaScope>>,
+}
+
+/// Merges a scope's result into the combined result
+///
+/// The vectors of `ctx` are concatenated, with each of the `services` and their
+/// entities tagged with the scope. Any other object, or attribute of `ctx`, named the
+/// same as one from an earlier scope is namespaced by its scope, as is the earlier one.
+fn merge_result(
+    merged: &mut RessaResult,
+    origins: &mut Origins,
+    result: RessaResult,
+    scope: &RessaScope,
+) {
+    for (name, objects) in result {
+        if name != CTX_OBJECT {
+            let (name, moved) = scoped_name(&mut origins.objects, name, scope);
+            if let Some((earlier, moved)) = moved {
+                if let Some(objects) = merged.remove(&earlier) {
+                    merged.insert(moved, objects);
+                }
+            }
+            merged.insert(name, objects);
+            continue;
+        }
+
+        let merged_ctx = merged.entry(name).or_insert_with(BTreeMap::new);
+        for (key, value) in objects {
+            if key == "services" {
+                tag_services(&value, scope);
+            }
+            match (merged_ctx.get(&key), &value) {
+                (Some(Value::Vec(_)), Value::Vec(_)) => append_vec(merged_ctx, key, value),
+                _ => {
+                    let (key, moved) = scoped_name(&mut origins.ctx, key, scope);
+                    if let Some((earlier, moved)) = moved {
+                        if let Some(value) = merged_ctx.remove(&earlier) {
+                            merged_ctx.insert(moved, value);
+                        }
+                    }
+                    merged_ctx.insert(key, value);
+                }
+            }
+        }
+    }
+}
+
+/// Names what a scope produced: by its own name if no earlier scope produced the same
+/// name, and otherwise namespaced by the scope, along with the earlier scope's name for
+/// it and the namespaced name to move it to, if it has not been moved already
+fn scoped_name(
+    origins: &mut BTreeMap<String, Option<RessaScope>>,
+    name: String,
+    scope: &RessaScope,
+) -> (String, Option<(String, String)>) {
+    let scoped = |scope: &RessaScope| format!("{}::{}", scope, name);
+    match origins.get_mut(&name) {
+        None => {
+            origins.insert(name.clone(), Some(scope.clone()));
+            (name, None)
+        }
+        Some(earlier) => {
+            tracing::info!("{} is produced by more than one root, namespacing it", name);
+            let moved = earlier
+                .take()
+                .map(|earlier| (name.clone(), scoped(&earlier)));
+            (scoped(scope), moved)
+        }
+    }
+}
+
+/// Tags every service object, and each of its entities, with the scope, naming the
+/// services left unnamed after it
+fn tag_services(services: &Value, scope: &RessaScope) {
+    for service in vec_items(services) {
+        tag_object(&service, scope);
+        if let Value::Object(obj) = &service {
+            if let Ok(mut obj) = obj.borrow_mut() {
+                if !obj.contains_key("name") {
+                    let name = Value::String(Shared::new(scope.service_name()));
+                    obj.insert("name".to_string(), name);
+                }
+            }
+
+            let entities = match obj.borrow_ref() {
+                Ok(obj) => obj.get("entities").cloned(),
+                Err(_) => None,
+            };
+            for entity in entities.iter().flat_map(vec_items) {
+                tag_object(&entity, scope);
+            }
+        }
+    }
+}
+
+/// Adds the origin attributes to an object
+fn tag_object(value: &Value, scope: &RessaScope) {
+    if let Value::Object(obj) = value {
+        if let Ok(mut obj) = obj.borrow_mut() {
+            obj.insert(
+                ORIGIN_REPOSITORY.to_string(),
+                Value::String(Shared::new(scope.repository.clone())),
+            );
+            obj.insert(
+                ORIGIN_ROOT.to_string(),
+                Value::String(Shared::new(scope.root.clone())),
+            );
+        }
+    }
+}
+
+/// Appends the items of a vector value to the vector stored under `key`
+fn append_vec(objects: &mut BTreeMap<String, Value>, key: String, value: Value) {
+    let mut items = objects.get(&key).map(vec_items).unwrap_or_default();
+    items.extend(vec_items(&value));
+    objects.insert(key, Value::Vec(Shared::new(runestick::Vec::from(items))));
+}
+
+/// Gets the items of a vector value (empty if it is not a vector)
+fn vec_items(value: &Value) -> Vec<Value> {
+    match value {
+        Value::Vec(vec) => match vec.borrow_ref() {
+            Ok(vec) => vec.iter().cloned().collect(),
+            Err(_) => vec![],
+        },
+        _ => vec![],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prophet_model::fixtures::{object, string, vec};
+
+    fn service(name: &str) -> Value {
+        let entity = object(vec![("name", string("User"))]);
+        object(vec![
+            ("name", string(name)),
+            ("entities", vec(vec![entity])),
+        ])
+    }
+
+    fn ctx(attributes: Vec<(&str, Value)>) -> RessaResult {
+        let ctx = attributes
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), value))
+            .collect();
+        RessaResult::from([("ctx".to_string(), ctx)])
+    }
+
+    /// Reads a string value
+    fn text(value: Value) -> Option<String> {
+        let text = value.into_string().ok()?;
+        let text = text.borrow_ref().ok()?;
+        Some(text.to_string())
+    }
+
+    /// Reads an attribute of an object value
+    fn attribute(value: &Value, key: &str) -> Option<Value> {
+        match value {
+            Value::Object(obj) => obj.borrow_ref().ok()?.get(key).cloned(),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn tags_and_concatenates_services() {
+        let mut merged = RessaResult::default();
+        let first = RessaScope::new("https://github.com/a/a.git", "users");
+        let second = RessaScope::new("https://github.com/b/b.git", "users");
+        for scope in [&first, &second] {
+            let result = ctx(vec![("services", vec(vec![service("users")]))]);
+            merge_result(&mut merged, &mut Origins::default(), result, scope);
+        }
+
+        let services = vec_items(&merged["ctx"]["services"]);
+        assert_eq!(2, services.len());
+        for (service, scope) in services.iter().zip([&first, &second]) {
+            let origin = |value: &Value, key| attribute(value, key).and_then(text);
+            assert_eq!(
+                Some(&scope.repository),
+                origin(service, ORIGIN_REPOSITORY).as_ref()
+            );
+            assert_eq!(Some(&scope.root), origin(service, ORIGIN_ROOT).as_ref());
+
+            let entities = attribute(service, "entities").unwrap();
+            let entity = &vec_items(&entities)[0];
+            assert_eq!(
+                Some(&scope.repository),
+                origin(entity, ORIGIN_REPOSITORY).as_ref()
+            );
+        }
+    }
+
+    #[test]
+    fn namespaces_colliding_values_by_scope() {
+        let mut merged = RessaResult::default();
+        let mut origins = Origins::default();
+        let scope = |root: &str| RessaScope::new("https://github.com/a/a.git", root);
+        for root in ["a", "b", "c"] {
+            let mut result = ctx(vec![("version", string(root))]);
+            result.insert(
+                "User".to_string(),
+                BTreeMap::from([("root".to_string(), string(root))]),
+            );
+            merge_result(&mut merged, &mut origins, result, &scope(root));
+        }
+        merge_result(
+            &mut merged,
+            &mut origins,
+            RessaResult::from([(
+                "Order".to_string(),
+                BTreeMap::from([("root".to_string(), string("d"))]),
+            )]),
+            &scope("d"),
+        );
+
+        let scoped = |root: &str| format!("https://github.com/a/a.git/{}::", root);
+        let mut names: Vec<_> = merged.keys().cloned().collect();
+        names.sort();
+        let mut expected = vec!["Order".to_string(), "ctx".to_string()];
+        expected.extend(["a", "b", "c"].map(|root| format!("{}User", scoped(root))));
+        expected.sort();
+        assert_eq!(expected, names);
+        for root in ["a", "b", "c"] {
+            let user = &merged[&format!("{}User", scoped(root))];
+            assert_eq!(Some(root.to_string()), text(user["root"].clone()));
+            let version = merged["ctx"][&format!("{}version", scoped(root))].clone();
+            assert_eq!(Some(root.to_string()), text(version));
+        }
+        assert_eq!(3, merged["ctx"].len());
+    }
+
+    #[test]
+    fn names_unnamed_services_after_their_root() {
+        let mut merged = RessaResult::default();
+        let scope = RessaScope::new("https://github.com/a/train.git", "ts-order-service/");
+        let unnamed = object(vec![]);
+        let result = ctx(vec![("services", vec(vec![unnamed, service("users")]))]);
+        merge_result(&mut merged, &mut Origins::default(), result, &scope);
+
+        let names: Vec<_> = vec_items(&merged["ctx"]["services"])
+            .iter()
+            .filter_map(|service| attribute(service, "name").and_then(text))
+            .collect();
+        assert_eq!(vec!["ts-order-service", "users"], names);
+        assert_eq!(
+            "train",
+            RessaScope::new("https://github.com/a/train.git", "").service_name()
+        );
+    }
+}
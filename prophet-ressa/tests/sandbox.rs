@@ -0,0 +1,120 @@
+use std::path::Path;
+
+use prophet_ressa::{
+    load_directory, run_sandboxed, PatternError, SandboxLimits, Violation, FIXTURE_DIR, GOLDEN_DIR,
+    SANDBOX_WORKER_BIN_ENV,
+};
+use prophet_ressa_minify::{minify_ressa_value, Parameters};
+use serde_json::json;
+use source_code_parser::{parse_project_context, ressa::NodePattern, ModuleComponent};
+
+/// Points the sandbox at the worker Cargo built for the tests
+fn use_built_worker() {
+    std::env::set_var(
+        SANDBOX_WORKER_BIN_ENV,
+        env!("CARGO_BIN_EXE_prophet-ressa-worker"),
+    );
+}
+
+fn handler_laast() -> Vec<ModuleComponent> {
+    use_built_worker();
+    let bundle = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("../prophet-ressa-minify/res/deathstarbench/simple");
+    let fixtures = bundle.join(GOLDEN_DIR).join("handler").join(FIXTURE_DIR);
+    parse_project_context(&load_directory(&fixtures).unwrap())
+        .unwrap()
+        .modules
+}
+
+/// Minifies patterns matching every method, each running the given callback
+fn method_patterns(callbacks: &[&str]) -> Vec<NodePattern> {
+    let patterns = callbacks
+        .iter()
+        .map(|callback| {
+            json!({
+                "identifier": "Method",
+                "pattern": "#{method}",
+                "subpatterns": [],
+                "callback": { "inline": callback },
+                "essential": true
+            })
+        })
+        .collect();
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("src");
+    minify_ressa_value(serde_json::Value::Array(patterns), &dir, &Parameters::new()).unwrap()
+}
+
+const RECORD: &str =
+    r#"ctx.make_attribute("methods", ctx.get_variable("method").unwrap(), Some(1.5));"#;
+
+const COUNT: &str = "let n = 0; while n < 1000000 { n = n + 1; }";
+
+fn violations(errors: &[PatternError]) -> Vec<Violation> {
+    errors.iter().map(|error| error.violation).collect()
+}
+
+#[test]
+fn drops_an_infinitely_looping_pattern() {
+    let limits = SandboxLimits {
+        instruction_budget: 100_000,
+        ..Default::default()
+    };
+    let patterns = method_patterns(&[RECORD, "loop {}"]);
+    let (result, errors) = run_sandboxed(&handler_laast(), patterns, &limits);
+
+    assert_eq!(vec![Violation::Budget], violations(&errors));
+    let methods = &result["methods"];
+    assert_eq!(
+        vec!["Login", "Register"],
+        methods.keys().collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn drops_a_pattern_over_budget() {
+    let limits = SandboxLimits {
+        instruction_budget: 100_000,
+        ..Default::default()
+    };
+    let patterns = method_patterns(&[COUNT, RECORD]);
+    let (result, errors) = run_sandboxed(&handler_laast(), patterns, &limits);
+
+    assert_eq!(vec![Violation::Budget], violations(&errors));
+    assert_eq!(2, result["methods"].len());
+}
+
+#[test]
+fn gives_each_pattern_its_own_budget() {
+    // Each pattern stays within the budget, though together they would not
+    let limits = SandboxLimits {
+        instruction_budget: 100_000,
+        ..Default::default()
+    };
+    let cheap = "let n = 0; while n < 10000 { n = n + 1; }";
+    let patterns = method_patterns(&[cheap, cheap, cheap, cheap, RECORD]);
+    let (result, errors) = run_sandboxed(&handler_laast(), patterns, &limits);
+
+    assert!(errors.is_empty());
+    assert_eq!(2, result["methods"].len());
+}
+
+#[test]
+fn kills_a_worker_past_its_timeout() {
+    let limits = SandboxLimits {
+        instruction_budget: usize::MAX,
+        timeout_ms: 1_000,
+        ..Default::default()
+    };
+    let patterns = method_patterns(&[RECORD, "loop {}"]);
+    let (result, errors) = run_sandboxed(&handler_laast(), patterns, &limits);
+
+    assert_eq!(vec![Violation::Timeout], violations(&errors));
+    assert_eq!(2, result["methods"].len());
+}
+
+#[test]
+fn runs_no_patterns() {
+    let (result, errors) = run_sandboxed(&handler_laast(), vec![], &SandboxLimits::default());
+    assert!(result.is_empty());
+    assert!(errors.is_empty());
+}
@@ -1,5 +1,7 @@
+use std::{fs::File, path::PathBuf};
+
 use actix_web::{middleware::Logger, web, App, FromRequest, HttpServer};
-use prophet::Repositories;
+use prophet::{PackageHashes, Repositories, SandboxLimits};
 use structopt::StructOpt;
 
 mod routes;
@@ -11,6 +13,40 @@ struct Opt {
     host: String,
     #[structopt(long, short, default_value = "8080")]
     port: i32,
+    /// The number of Rune instructions a ReSSA run's callbacks may execute
+    #[structopt(long)]
+    instruction_budget: Option<usize>,
+    /// The wall-clock time a ReSSA run may take, in milliseconds
+    #[structopt(long)]
+    timeout_ms: Option<u64>,
+    /// The number of bytes a ReSSA run may keep resident
+    #[structopt(long)]
+    memory_limit: Option<usize>,
+    /// A JSON file of the hash each packaged bundle must have, by bundle name
+    #[structopt(long)]
+    package_hashes: Option<PathBuf>,
+}
+
+impl Opt {
+    /// The limits every ReSSA run is held to, which requests can only lower
+    fn limits(&self) -> SandboxLimits {
+        let defaults = SandboxLimits::default();
+        SandboxLimits {
+            instruction_budget: self
+                .instruction_budget
+                .unwrap_or(defaults.instruction_budget),
+            timeout_ms: self.timeout_ms.unwrap_or(defaults.timeout_ms),
+            memory_limit: self.memory_limit.unwrap_or(defaults.memory_limit),
+        }
+    }
+
+    /// The hashes packaged bundles are pinned to, with none pinned if no file is given
+    fn package_hashes(&self) -> std::io::Result<PackageHashes> {
+        match &self.package_hashes {
+            Some(path) => Ok(serde_json::from_reader(File::open(path)?)?),
+            None => Ok(PackageHashes::new()),
+        }
+    }
 }
 
 #[actix_web::main]
@@ -18,10 +54,16 @@ async fn main() -> std::io::Result<()> {
     env_logger::init_from_env(env_logger::Env::new().default_filter_or("info"));
     let opt = Opt::from_args();
     let addr = format!("{}:{}", opt.host, opt.port);
+    let limits = web::Data::new(opt.limits());
+    let pinned = web::Data::new(opt.package_hashes()?);
 
-    HttpServer::new(|| {
+    HttpServer::new(move || {
         App::new()
+            .app_data(limits.clone())
+            .app_data(pinned.clone())
             .service(analyze)
+            .service(reload)
+            .service(diff)
             .wrap(Logger::default())
             .app_data(web::Json::<Repositories>::configure(|cfg| {
                 cfg.limit(1024 * 1024 * 4)
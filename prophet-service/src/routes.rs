@@ -1,18 +1,101 @@
 use actix_web::{error, post, web, Error, HttpResponse};
-use prophet::{AppData, Repositories};
+use prophet::{
+    AnalysisDiff, AppData, BundleParameters, ModelDocument, PackageHashes, Policy, Repositories,
+    RessaRun, SandboxLimits,
+};
 use serde::Deserialize;
 
 #[derive(Deserialize)]
 pub struct AnalysisBody {
     ressa_dir: String,
     repositories: Repositories,
+    /// Overrides of bundle parameters, by bundle name
+    #[serde(default)]
+    parameters: BundleParameters,
+    /// Lower resource limits for the run than the server's
+    #[serde(default)]
+    limits: RequestedLimits,
+    /// An architecture policy to check the project against
+    #[serde(default)]
+    policy: Option<Policy>,
+}
+
+/// Resource limits a request asks for, each of which can only lower the server's
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct RequestedLimits {
+    instruction_budget: Option<usize>,
+    timeout_ms: Option<u64>,
+    memory_limit: Option<usize>,
+}
+
+impl RequestedLimits {
+    /// The server's limits, lowered where the request asks for lower ones
+    fn apply(&self, server: &SandboxLimits) -> SandboxLimits {
+        SandboxLimits {
+            instruction_budget: self
+                .instruction_budget
+                .map_or(server.instruction_budget, |budget| {
+                    budget.min(server.instruction_budget)
+                }),
+            timeout_ms: self
+                .timeout_ms
+                .map_or(server.timeout_ms, |timeout| timeout.min(server.timeout_ms)),
+            memory_limit: self.memory_limit.map_or(server.memory_limit, |memory| {
+                memory.min(server.memory_limit)
+            }),
+        }
+    }
 }
 
 #[post("/analyze")]
-pub async fn analyze(payload: web::Json<AnalysisBody>) -> Result<HttpResponse, Error> {
-    let payload = payload.into_inner();
-    let app_data = AppData::from_repositories(payload.repositories, payload.ressa_dir)
+pub async fn analyze(
+    payload: web::Json<AnalysisBody>,
+    limits: web::Data<SandboxLimits>,
+    pinned: web::Data<PackageHashes>,
+) -> Result<HttpResponse, Error> {
+    let AnalysisBody {
+        ressa_dir,
+        repositories,
+        parameters,
+        limits: requested,
+        policy,
+    } = payload.into_inner();
+    let limits = requested.apply(&limits);
+    // Cloning and the sandboxed run block, so keep them off the server's threads
+    let run = web::block(move || {
+        RessaRun::from_repositories(repositories, ressa_dir, &parameters, &limits, &pinned)
+    })
+    .await
+    .map_err(error::ErrorInternalServerError)?;
+    let mut app_data = AppData::from_ressa_run(run)
         .await
         .map_err(error::ErrorInternalServerError)?;
+    if let Some(policy) = &policy {
+        app_data.check_policy(policy);
+    }
     Ok(HttpResponse::Ok().json(app_data))
 }
+
+/// Recreates the analysis of a model stored from an earlier one, without re-running it
+#[post("/reload")]
+pub async fn reload(payload: web::Json<serde_json::Value>) -> Result<HttpResponse, Error> {
+    let model = ModelDocument::from_value(&payload).map_err(error::ErrorBadRequest)?;
+    Ok(HttpResponse::Ok().json(AppData::from_model(model)))
+}
+
+#[derive(Deserialize)]
+pub struct DiffBody {
+    before: serde_json::Value,
+    after: serde_json::Value,
+}
+
+/// Compares two models stored from earlier analyses
+#[post("/diff")]
+pub async fn diff(payload: web::Json<DiffBody>) -> Result<HttpResponse, Error> {
+    let read = |model: &serde_json::Value| {
+        ModelDocument::from_value(model).map_err(error::ErrorBadRequest)
+    };
+    let (before, after) = (read(&payload.before)?, read(&payload.after)?);
+    Ok(HttpResponse::Ok().json(AnalysisDiff::new(&before, &after)))
+}
@@ -1,9 +1,7 @@
-use std::path::Path;
-
 use super::Error;
 use serde::Serialize;
 
-use super::Repositories;
+use super::RessaRun;
 
 /// A compatibility AppData type for the current Prophet frontend
 #[derive(Debug, Default, Serialize)]
@@ -11,15 +9,9 @@ pub struct AppData {}
 
 impl AppData {
     #[allow(dead_code)]
-    /// Clone the provided repositories and generate ReSSAs to analyze them
-    /// based on the languages in its LAAST
-    pub async fn from_repositories<P: AsRef<Path>>(
-        repos: Repositories,
-        ressa_dir: P,
-    ) -> Result<AppData, Error> {
-        super::AppData::from_repositories(repos, ressa_dir)
-            .await
-            .map(AppData::from)
+    /// Creates an AppData from a ReSSA run against a project's repositories
+    pub async fn from_ressa_run(run: RessaRun) -> Result<AppData, Error> {
+        super::AppData::from_ressa_run(run).await.map(AppData::from)
     }
 }
 
@@ -1,19 +1,31 @@
 use std::path::Path;
 
 use crate::{Error, Repositories};
-use prophet_ressa::run_ressa;
+use prophet_ressa::{
+    bundle_metrics, find_bundles, run_ressa_scoped, BundleParameters, CoverageReport,
+    PackageHashes, PatternError, SandboxLimits,
+};
 
 use prophet_bounded_context::get_bounded_context;
 use prophet_mermaid::MermaidString;
-use prophet_model::MicroserviceGraph;
+use prophet_model::{
+    detect_smells, ArchitectureMetrics, Endpoint, ExternalService, MicroserviceGraph,
+    ModelDocument, Policy, PolicyReport, Smell, SmellThresholds,
+};
 use serde::Serialize;
-use source_code_parser::{parse_project_context, ressa::RessaResult, Directory};
+use source_code_parser::{parse_project_context, ressa::RessaResult};
 
 /// An analyzed microservice within a project
 #[derive(Debug, Default, Serialize)]
 pub struct Microservice {
     /// The name of the microservice
     pub name: String,
+    /// The repository the microservice was found in, if known
+    pub repository: Option<String>,
+    /// The root directory the microservice was found in, if known
+    pub root: Option<String>,
+    /// The endpoints the microservice exposes
+    pub endpoints: Vec<Endpoint>,
     /// The entity diagram for the analyzed microservice,
     pub entity_diagram: Option<MermaidString>,
 }
@@ -30,17 +42,40 @@ pub struct AppData {
     pub entity_diagram: Option<MermaidString>,
     /// The microservices in the analyzed project
     pub microservices: Vec<Microservice>,
+    /// The services the microservices call that are not in the analyzed project
+    pub external_services: Vec<ExternalService>,
+    /// The coupling and size metrics of the microservices and the system
+    pub architecture_metrics: ArchitectureMetrics,
+    /// The architecture smells found in the project, most severe first
+    pub smells: Vec<Smell>,
+    /// The result of checking the project against an architecture policy, if one was given
+    pub policy_report: Option<PolicyReport>,
+    /// The model graphs the analysis produced, which the rest can be recreated from
+    pub model: Option<ModelDocument>,
+    /// Which languages and files the ReSSAs covered
+    pub coverage: CoverageReport,
+    /// The patterns dropped from the analysis for violating a resource limit
+    pub ressa_errors: Vec<PatternError>,
 }
 
 impl AppData {
     /// Creates an AppData from the results of a ReSSA
     pub async fn from_ressa_result(ressa_result: &RessaResult) -> Result<AppData, Error> {
-        let ms_graph = match MicroserviceGraph::try_new(ressa_result) {
-            Some(ms_graph) => ms_graph,
-            None => return Err(Error::AppData("Could not create microservice graph".into())),
-        };
+        AppData::from_microservice_graph(MicroserviceGraph::try_new(ressa_result)?).await
+    }
+
+    /// Creates an AppData from a ReSSA run against a project's repositories
+    pub async fn from_ressa_run(run: RessaRun) -> Result<AppData, Error> {
+        let mut app_data = AppData::from_microservice_graph(run.microservice_graph).await?;
+        app_data.coverage = run.coverage;
+        app_data.ressa_errors = run.ressa_errors;
+        Ok(app_data)
+    }
 
-        let microservices = ms_graph.nodes();
+    /// Creates an AppData from the microservices a ReSSA found, requesting the bounded
+    /// context of their entities
+    async fn from_microservice_graph(ms_graph: MicroserviceGraph) -> Result<AppData, Error> {
+        let microservices = ms_graph.microservices();
         // Collect all entities from all microservices to be bound
         let entities: Vec<_> = microservices
             .iter()
@@ -48,11 +83,30 @@ impl AppData {
             .cloned()
             .collect();
 
-        // Get the bounded context and its diagram
+        // Get the bounded context
         let bounded_entity_graph = get_bounded_context(&entities).await?;
+
+        Ok(AppData::from_model(ModelDocument::new(
+            ms_graph,
+            bounded_entity_graph,
+        )))
+    }
+
+    /// Creates an AppData from a stored model, without re-running the analysis
+    pub fn from_model(model: ModelDocument) -> AppData {
+        let ms_graph = model.microservice_graph.clone();
+        let bounded_entity_graph = model.entity_graph.clone();
         let entity_diagram = Some(MermaidString::from(bounded_entity_graph.clone()));
+        let architecture_metrics = ArchitectureMetrics::new(&ms_graph, &bounded_entity_graph);
+        let smells = detect_smells(
+            &ms_graph,
+            &bounded_entity_graph,
+            &SmellThresholds::default(),
+        );
 
         // Get the microservice communication diagram
+        let microservices = ms_graph.microservices();
+        let external_services = ms_graph.external_services();
         let communication_diagram = Some(MermaidString::from(ms_graph));
 
         // Get the microservice bounded entity diagrams
@@ -61,36 +115,100 @@ impl AppData {
             .map(|ms| {
                 let mut entity_graph = bounded_entity_graph.clone();
                 entity_graph.filter_entities(&ms.ref_entities);
+                let (repository, root) = match ms.origin {
+                    Some(origin) => (Some(origin.repository), Some(origin.root)),
+                    None => (None, None),
+                };
                 Microservice {
                     name: ms.name,
+                    repository,
+                    root,
+                    endpoints: ms.endpoints,
                     entity_diagram: Some(MermaidString::from(entity_graph)),
                 }
             })
             .collect();
 
-        Ok(AppData {
-            name: "system".into(),
+        AppData {
+            name: model
+                .metadata
+                .get("name")
+                .cloned()
+                .unwrap_or_else(|| "system".into()),
             communication_diagram,
             entity_diagram,
             microservices,
-        })
+            external_services,
+            architecture_metrics,
+            smells,
+            model: Some(model),
+            ..Default::default()
+        }
+    }
+
+    /// Checks the analyzed project against an architecture policy, recording the report
+    pub fn check_policy(&mut self, policy: &Policy) -> Option<&PolicyReport> {
+        let model = self.model.as_ref()?;
+        self.policy_report = Some(policy.check(&model.microservice_graph, &model.entity_graph));
+        self.policy_report.as_ref()
     }
+}
+
+/// The ReSSAs run against a project's repositories, before their bounded context is
+/// requested
+#[derive(Debug)]
+pub struct RessaRun {
+    /// The microservices the ReSSAs found
+    pub microservice_graph: MicroserviceGraph,
+    /// Which languages and files the ReSSAs covered
+    pub coverage: CoverageReport,
+    /// The patterns dropped from the run for violating a resource limit
+    pub ressa_errors: Vec<PatternError>,
+}
 
+impl RessaRun {
     /// Clone the provided repositories and generate ReSSAs to analyze them
-    /// based on the languages in its LAAST
-    pub async fn from_repositories<P: AsRef<Path>>(
+    /// based on the languages in its LAAST, with the given bundle parameter overrides
+    /// and running them within the provided limits
+    ///
+    /// Packaged bundles must match their pinned hashes, and are unpacked once for the
+    /// whole analysis. This blocks until the clones and the sandboxed run finish, so
+    /// async callers should run it on a thread that may block.
+    pub fn from_repositories<P: AsRef<Path>>(
         mut repos: Repositories,
         ressa_dir: P,
-    ) -> Result<AppData, Error> {
+        params: &BundleParameters,
+        limits: &SandboxLimits,
+        pinned: &PackageHashes,
+    ) -> Result<RessaRun, Error> {
+        let index = find_bundles(ressa_dir.as_ref(), pinned)
+            .map_err(|err| Error::AppData(err.to_string()))?;
         repos.clone_all()?;
 
-        let dir: Directory = repos.into();
-        let mut laast = parse_project_context(&dir)?;
-        // Generate ReSSAs based on languages in ctx modules
-        let result: RessaResult = run_ressa(&mut laast.modules, ressa_dir.as_ref())
+        // Parse each repository root on its own so results can be attributed to it
+        let mut scopes = vec![];
+        let mut metrics = vec![];
+        for (scope, dir) in repos.scoped_roots() {
+            let laast = parse_project_context(&dir)?;
+            match bundle_metrics(&scope, &laast.modules, &index, params) {
+                Ok(scope_metrics) => metrics.extend(scope_metrics),
+                Err(err) => tracing::warn!("Could not measure ReSSAs for {}: {}", scope, err),
+            }
+            scopes.push((scope, laast.modules));
+        }
+
+        let mut coverage = CoverageReport::new(scopes.iter().flat_map(|(_, ast)| ast), &index);
+        coverage.metrics = metrics;
+
+        // Generate ReSSAs based on languages in each scope's modules
+        let (result, ressa_errors) = run_ressa_scoped(scopes, &index, params, limits)
             .map_err(|err| Error::AppData(err.to_string()))?;
 
-        AppData::from_ressa_result(&result).await
-        // Clean up repos on disk on drop
+        Ok(RessaRun {
+            microservice_graph: MicroserviceGraph::try_new(&result)?,
+            coverage,
+            ressa_errors,
+        })
+        // Clean up repos and unpacked bundles on disk on drop
     }
 }
@@ -0,0 +1,52 @@
+//! Checks a stored analysis against an architecture policy, for use in CI
+//!
+//! Usage: `check-policy <model.json> <policy.json|yaml>`. Prints each violation and
+//! exits with 1 if there are any.
+use std::path::Path;
+
+use prophet::{ModelDocument, Policy};
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let (model, policy) = match args.as_slice() {
+        [model, policy] => (model, policy),
+        _ => {
+            eprintln!("usage: check-policy <model.json> <policy.json|yaml>");
+            std::process::exit(2);
+        }
+    };
+
+    let model = std::fs::read_to_string(model)
+        .map_err(|err| err.to_string())
+        .and_then(|json| ModelDocument::from_json(&json).map_err(|err| err.to_string()));
+    let policy = Policy::from_path(Path::new(policy)).map_err(|err| err.to_string());
+    let (model, policy) = match (model, policy) {
+        (Ok(model), Ok(policy)) => (model, policy),
+        (Err(err), _) | (_, Err(err)) => {
+            eprintln!("{}", err);
+            std::process::exit(2);
+        }
+    };
+
+    let report = policy.check(&model.microservice_graph, &model.entity_graph);
+    for violation in report.violations.iter() {
+        let locations: Vec<_> = violation
+            .locations
+            .iter()
+            .map(|location| match &location.call {
+                Some(call) => format!("{} ({})", location.service, call),
+                None => location.service.clone(),
+            })
+            .collect();
+        println!("{}: [{}]", violation.message, locations.join(", "));
+    }
+    if report.passed {
+        println!("policy passed");
+    } else {
+        println!(
+            "policy failed with {} violation(s)",
+            report.violations.len()
+        );
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,41 @@
+//! Compares two stored analyses, for reviewing the architectural impact of a change
+//!
+//! Usage: `diff-models <before.json> <after.json>`. Prints the differences and drift
+//! diagram as JSON, and exits with 1 if the architecture changed.
+use prophet::{AnalysisDiff, ModelDocument};
+
+fn read_model(path: &str) -> Result<ModelDocument, String> {
+    let json = std::fs::read_to_string(path).map_err(|err| format!("{}: {}", path, err))?;
+    ModelDocument::from_json(&json).map_err(|err| format!("{}: {}", path, err))
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let (before, after) = match args.as_slice() {
+        [before, after] => (before, after),
+        _ => {
+            eprintln!("usage: diff-models <before.json> <after.json>");
+            std::process::exit(2);
+        }
+    };
+
+    let (before, after) = match (read_model(before), read_model(after)) {
+        (Ok(before), Ok(after)) => (before, after),
+        (Err(err), _) | (_, Err(err)) => {
+            eprintln!("{}", err);
+            std::process::exit(2);
+        }
+    };
+
+    let drift = AnalysisDiff::new(&before, &after);
+    match serde_json::to_string_pretty(&drift) {
+        Ok(json) => println!("{}", json),
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(2);
+        }
+    }
+    if !drift.diff.is_empty() {
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,31 @@
+use prophet_mermaid::MermaidString;
+use prophet_model::{ModelDiff, ModelDocument};
+use serde::Serialize;
+
+use crate::AppData;
+
+/// The architecture drift between two analyses of a project
+#[derive(Debug, Serialize)]
+pub struct AnalysisDiff {
+    /// What changed between the analyses
+    pub diff: ModelDiff,
+    /// The communication diagram with added calls highlighted and removed calls dashed
+    pub diagram: MermaidString,
+}
+
+impl AnalysisDiff {
+    /// Compares two stored models
+    pub fn new(before: &ModelDocument, after: &ModelDocument) -> AnalysisDiff {
+        let diff = ModelDiff::new(before, after);
+        let diagram = MermaidString::from(&diff);
+        AnalysisDiff { diff, diagram }
+    }
+
+    /// Compares two analyses, if both kept their models
+    pub fn from_app_data(before: &AppData, after: &AppData) -> Option<AnalysisDiff> {
+        Some(AnalysisDiff::new(
+            before.model.as_ref()?,
+            after.model.as_ref()?,
+        ))
+    }
+}
@@ -8,6 +8,8 @@ pub enum Error {
     Io(String),
     #[error("Could not create an AppData from the provided ReSSA: {0}")]
     AppData(String),
+    #[error("The ReSSA result does not match the context schema: {0}")]
+    Schema(String),
     #[error("Could not create bounded context")]
     BoundedContext(#[from] prophet_bounded_context::Error),
 }
@@ -24,4 +26,8 @@ macro_rules! error_from_impl {
     };
 }
 
-error_from_impl!(git2::Error: CloneRepo, std::io::Error: Io);
+error_from_impl!(
+    git2::Error: CloneRepo,
+    std::io::Error: Io,
+    prophet_model::SchemaErrors: Schema
+);
@@ -9,4 +9,10 @@ pub use error::*;
 pub(crate) mod app_data;
 pub use app_data::*;
 
+pub(crate) mod drift;
+pub use drift::*;
+
 pub(crate) mod adapter;
+
+pub use prophet_model::{InterchangeError, ModelDiff, ModelDocument, Policy, PolicyReport};
+pub use prophet_ressa::{BundleParameters, PackageHashes, PatternError, SandboxLimits};
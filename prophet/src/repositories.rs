@@ -1,6 +1,7 @@
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
 
 use git2::Repository;
+use prophet_ressa::RessaScope;
 use serde::Deserialize;
 use source_code_parser::Directory;
 
@@ -22,6 +23,37 @@ impl MicroservicesRepository {
         Repository::clone(&self.git_url, &self.clone_dir)?;
         Ok(())
     }
+
+    /// Gets the Directory representation of each root directory, scoped to this repository,
+    /// along with the files at the top level of the repository under the root `.`
+    pub fn scoped_roots(&self) -> Vec<(RessaScope, Directory)> {
+        let mut roots: Vec<_> = self
+            .root_dirs
+            .iter()
+            .flat_map(|relative_path| {
+                let mut root = self.clone_dir.clone();
+                root.push(relative_path);
+                let dir = convert_sub_dir(root)?;
+                let scope = RessaScope::new(&self.git_url, relative_path.display());
+                Some((scope, dir))
+            })
+            .collect();
+
+        // A root directory of the repository itself already includes its top level
+        let covers_top_level = self
+            .root_dirs
+            .iter()
+            .any(|relative_path| relative_path.components().all(|c| c == Component::CurDir));
+        if !covers_top_level {
+            if let Ok((files, _)) = get_dir_contents(&self.clone_dir) {
+                if !files.is_empty() {
+                    let dir = Directory::new(files, vec![], self.clone_dir.clone());
+                    roots.push((RessaScope::new(&self.git_url, "."), dir));
+                }
+            }
+        }
+        roots
+    }
 }
 
 impl Drop for MicroservicesRepository {
@@ -144,4 +176,13 @@ impl Repositories {
         }
         Ok(())
     }
+
+    /// Gets the Directory representation of every repository root directory, so each
+    /// can be analyzed on its own
+    pub fn scoped_roots(&self) -> Vec<(RessaScope, Directory)> {
+        self.0
+            .iter()
+            .flat_map(MicroservicesRepository::scoped_roots)
+            .collect()
+    }
 }